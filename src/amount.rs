@@ -0,0 +1,395 @@
+//! Numeric type of prices, volumes and balances.
+//!
+//! Kraken returns monetary amounts as strings to avoid any precision loss.
+//! All the typed models use the `Amount` type, which stores an `f64` by
+//! default, or the exact `rust_decimal::Decimal` with the `rust_decimal`
+//! optional feature. Amounts are deserialized from both strings and numbers,
+//! and serialized back as strings without scientific notation.
+//!
+//! The `Amount` API is the same in both configurations (parsing, formatting,
+//! arithmetic and comparison operators, and the conversions from integers
+//! and `f64`), so that enabling the `rust_decimal` feature anywhere in the
+//! dependency graph only changes the precision of the amounts, and never
+//! breaks the crates using them. The feature only adds the conversions from
+//! and to `Decimal`.
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, iter::Sum, ops, str::FromStr};
+
+use crate::Error;
+
+/// The representation of the amounts.
+#[cfg(not(feature = "rust_decimal"))]
+type Repr = f64;
+
+/// The representation of the amounts.
+#[cfg(feature = "rust_decimal")]
+type Repr = rust_decimal::Decimal;
+
+/// Price, volume or balance.
+#[derive(Clone, Copy, Default, PartialEq, PartialOrd)]
+pub struct Amount(Repr);
+
+impl Amount {
+    /// Converts the given number, if finite, to an amount.
+    pub fn from_f64(value: f64) -> Option<Self> {
+        if !value.is_finite() {
+            return None;
+        }
+        #[cfg(not(feature = "rust_decimal"))]
+        return Some(Self(value));
+        #[cfg(feature = "rust_decimal")]
+        // the shortest representation of the number is parsed exactly
+        value.to_string().parse().ok()
+    }
+
+    /// Converts the amount to the nearest `f64`.
+    pub fn to_f64(self) -> f64 {
+        #[cfg(not(feature = "rust_decimal"))]
+        return self.0;
+        #[cfg(feature = "rust_decimal")]
+        rust_decimal::prelude::ToPrimitive::to_f64(&self.0).unwrap_or_default()
+    }
+
+    /// Gets the absolute value of the amount.
+    pub fn abs(self) -> Self {
+        Self(self.0.abs())
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Debug for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+impl FromStr for Amount {
+    type Err = Error;
+
+    /// Parses a decimal amount (e.g. `-0.5` or `100`), where the infinite and
+    /// `NaN` values are not valid amounts.
+    fn from_str(amount: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidAmount(amount.to_string());
+        let value: Repr = amount.parse().map_err(|_| invalid())?;
+        #[cfg(not(feature = "rust_decimal"))]
+        if !value.is_finite() {
+            return Err(invalid());
+        }
+        Ok(Self(value))
+    }
+}
+
+macro_rules! impl_from_int {
+    ($($int:ty),*) => {
+        $(
+            impl From<$int> for Amount {
+                fn from(value: $int) -> Self {
+                    Self(Repr::from(value))
+                }
+            }
+        )*
+    };
+}
+
+impl_from_int!(i8, i16, i32, u8, u16, u32);
+
+#[cfg(feature = "rust_decimal")]
+impl From<rust_decimal::Decimal> for Amount {
+    fn from(value: rust_decimal::Decimal) -> Self {
+        Self(value)
+    }
+}
+
+#[cfg(feature = "rust_decimal")]
+impl From<Amount> for rust_decimal::Decimal {
+    fn from(amount: Amount) -> Self {
+        amount.0
+    }
+}
+
+macro_rules! impl_ops {
+    ($($op:ident $method:ident $op_assign:ident $method_assign:ident),*) => {
+        $(
+            impl ops::$op for Amount {
+                type Output = Self;
+
+                fn $method(self, rhs: Self) -> Self {
+                    Self(ops::$op::$method(self.0, rhs.0))
+                }
+            }
+
+            impl ops::$op_assign for Amount {
+                fn $method_assign(&mut self, rhs: Self) {
+                    ops::$op_assign::$method_assign(&mut self.0, rhs.0)
+                }
+            }
+        )*
+    };
+}
+
+impl_ops!(
+    Add add AddAssign add_assign,
+    Sub sub SubAssign sub_assign,
+    Mul mul MulAssign mul_assign,
+    Div div DivAssign div_assign,
+    Rem rem RemAssign rem_assign
+);
+
+impl ops::Neg for Amount {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self(-self.0)
+    }
+}
+
+impl Sum for Amount {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), |sum, amount| sum + amount)
+    }
+}
+
+impl<'a> Sum<&'a Amount> for Amount {
+    fn sum<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.copied().sum()
+    }
+}
+
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(AmountVisitor)
+    }
+}
+
+/// Rounds the given amount to the nearest multiple of the given step.
+pub(crate) fn round_to(amount: Amount, step: Amount) -> Amount {
+    #[cfg(not(feature = "rust_decimal"))]
+    let steps = (amount.0 / step.0).round();
+    #[cfg(feature = "rust_decimal")]
+    let steps = (amount.0 / step.0).round_dp_with_strategy(
+        0,
+        rust_decimal::RoundingStrategy::MidpointAwayFromZero,
+    );
+    Amount(steps) * step
+}
+
+/// Rounds the given amount down to the given number of decimals.
+pub(crate) fn floor_dp(amount: Amount, decimals: u32) -> Amount {
+    #[cfg(not(feature = "rust_decimal"))]
+    {
+        // the shortest representation of the number is the exact decimal
+        // amount, which is truncated without any representation error
+        let repr = amount.0.abs().to_string();
+        let (int, frac) = repr.split_once('.').unwrap_or((&repr, ""));
+        let kept = frac.len().min(decimals as usize);
+        let truncated: f64 = format!("{}.{}0", int, &frac[..kept])
+            .parse()
+            .unwrap_or_default();
+        let dropped = frac[kept..].bytes().any(|b| b != b'0');
+        if amount.0 < 0.0 && dropped {
+            Amount(-truncated) - Amount(10f64.powi(-(decimals as i32)))
+        } else {
+            Amount(truncated.copysign(amount.0))
+        }
+    }
+    #[cfg(feature = "rust_decimal")]
+    Amount(amount.0.round_dp_with_strategy(
+        decimals,
+        rust_decimal::RoundingStrategy::ToNegativeInfinity,
+    ))
+}
+
+/// Parses the given amount.
+fn parse<E: de::Error>(amount: &str) -> Result<Amount, E> {
+    amount.parse().map_err(|_| {
+        E::invalid_value(de::Unexpected::Str(amount), &"a decimal amount")
+    })
+}
+
+/// Visitor of amounts either as strings or numbers.
+struct AmountVisitor;
+
+impl<'de> de::Visitor<'de> for AmountVisitor {
+    type Value = Amount;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a decimal amount as string or number")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Amount, E> {
+        parse(v)
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Amount, E> {
+        // the shortest representation of the number is parsed exactly
+        parse(&v.to_string())
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Amount, E> {
+        parse(&v.to_string())
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Amount, E> {
+        parse(&v.to_string())
+    }
+}
+
+/// Serde functions of `Amount` fields.
+pub(crate) mod serde_amount {
+    use super::*;
+
+    pub(crate) fn serialize<S: Serializer>(
+        amount: &Amount,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(amount)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Amount, D::Error> {
+        deserializer.deserialize_any(AmountVisitor)
+    }
+}
+
+/// Serde functions of `Option<Amount>` fields.
+pub(crate) mod serde_opt_amount {
+    use super::*;
+
+    pub(crate) fn serialize<S: Serializer>(
+        amount: &Option<Amount>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match amount {
+            Some(amount) => serializer.collect_str(amount),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Amount>, D::Error> {
+        struct OptionVisitor;
+
+        impl<'de> de::Visitor<'de> for OptionVisitor {
+            type Value = Option<Amount>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "an optional decimal amount")
+            }
+
+            fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+                Ok(None)
+            }
+
+            fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+                Ok(None)
+            }
+
+            fn visit_some<D: Deserializer<'de>>(
+                self,
+                deserializer: D,
+            ) -> Result<Self::Value, D::Error> {
+                serde_amount::deserialize(deserializer).map(Some)
+            }
+        }
+
+        deserializer.deserialize_option(OptionVisitor)
+    }
+}
+
+/// Parses the given amount, for tests only.
+#[cfg(test)]
+pub(crate) fn amount(amount: &str) -> Amount {
+    amount.parse().expect("invalid amount")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Balance {
+        #[serde(with = "serde_amount")]
+        amount: Amount,
+        #[serde(default, with = "serde_opt_amount")]
+        hold: Option<Amount>,
+    }
+
+    #[test]
+    fn amount_serde() -> Result<()> {
+        let balance: Balance =
+            serde_json::from_value(json!({"amount": "0.00000001"}))?;
+        assert_eq!(balance.amount, amount("0.00000001"));
+        assert_eq!(balance.hold, None);
+        assert_eq!(
+            serde_json::to_value(&balance)?,
+            json!({"amount": "0.00000001", "hold": null})
+        );
+
+        let balance: Balance =
+            serde_json::from_value(json!({"amount": 12.5, "hold": 3}))?;
+        assert_eq!(balance.amount, amount("12.5"));
+        assert_eq!(balance.hold, Some(amount("3")));
+
+        for invalid in &["", "x", "NaN", "inf"] {
+            assert!(serde_json::from_value::<Balance>(
+                json!({ "amount": invalid })
+            )
+            .is_err());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn amount_rounding() {
+        let price = round_to(amount("30123.456"), amount("0.1"));
+        assert_eq!(format!("{:.1}", price), "30123.5");
+        assert_eq!(floor_dp(amount("0.123456789"), 8), amount("0.12345678"));
+        assert_eq!(floor_dp(amount("0.29"), 2), amount("0.29"));
+        // not rounded up by a representation tolerance
+        assert_eq!(floor_dp(amount("0.12345699999"), 6), amount("0.123456"));
+        assert_eq!(floor_dp(amount("42"), 2), amount("42"));
+        assert_eq!(floor_dp(amount("-0.125"), 2), amount("-0.13"));
+    }
+
+    #[test]
+    fn amount_api() {
+        assert_eq!("-1.5".parse(), Ok(amount("-1.5")));
+        assert_eq!(
+            "inf".parse::<Amount>(),
+            Err(Error::InvalidAmount("inf".into()))
+        );
+        assert!("".parse::<Amount>().is_err());
+        assert_eq!(Amount::from_f64(0.1), Some(amount("0.1")));
+        assert_eq!(Amount::from_f64(f64::NAN), None);
+        assert_eq!(amount("2.5").to_f64(), 2.5);
+
+        let amounts = [amount("1.5"), amount("-0.5"), Amount::from(2u8)];
+        assert_eq!(amounts.iter().sum::<Amount>(), amount("3"));
+        assert_eq!(-amounts[1].abs(), amounts[1]);
+        assert_eq!(format!("{:.2}", amounts[0]), "1.50");
+        assert_eq!(format!("{:?}", amounts[0]), "1.5");
+    }
+}
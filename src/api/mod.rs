@@ -1,7 +1,10 @@
-use serde::{Deserialize, Serialize};
+use reqwest::header::HeaderMap;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::{value::Index, Value};
 use std::fmt;
 
+use crate::{Error, Result};
+
 pub(crate) use body::Body;
 pub use builder::ApiBuilder;
 
@@ -49,6 +52,49 @@ impl<T> Response<T> {
     }
 }
 
+/// Maximum number of characters of the body included in decoding errors.
+const BODY_SNIPPET_LEN: usize = 256;
+
+/// Raw Kraken API response, not decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawResponse {
+    /// The response HTTP status code.
+    pub status_code: u16,
+    /// The response headers.
+    pub headers: HeaderMap,
+    /// The response body.
+    pub body: Vec<u8>,
+}
+
+impl RawResponse {
+    /// Returns true only if the HTTP status code is within [200, 299].
+    pub fn is_success(&self) -> bool {
+        self.status_code >= 200 && self.status_code < 300
+    }
+
+    /// Decodes the JSON body of the response.
+    ///
+    /// On failure the error includes the status code and the beginning of
+    /// the body, which may not be JSON at all (e.g. HTML error pages).
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_slice(&self.body).map_err(|e| Error::Decode {
+            err: e.to_string(),
+            status: self.status_code,
+            body: self.body_snippet(),
+        })
+    }
+
+    /// Gets the beginning of the body as text.
+    fn body_snippet(&self) -> String {
+        let body = String::from_utf8_lossy(&self.body);
+        let mut snippet: String = body.chars().take(BODY_SNIPPET_LEN).collect();
+        if body.chars().nth(BODY_SNIPPET_LEN).is_some() {
+            snippet.push_str("...");
+        }
+        snippet
+    }
+}
+
 /// A single Kraken API.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Api {
@@ -99,3 +145,47 @@ impl fmt::Display for ApiKind {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_response_decode() {
+        let raw = RawResponse {
+            status_code: 200,
+            headers: HeaderMap::new(),
+            body: br#"{"error":[],"result":{"unixtime":1625918400}}"#.to_vec(),
+        };
+        let resp: ResponseValue = raw.json().expect("invalid JSON");
+        assert_eq!(resp.get("unixtime"), Some(&Value::from(1625918400)));
+
+        let html = format!("<html>{}</html>", "x".repeat(1000));
+        let raw = RawResponse {
+            status_code: 520,
+            headers: HeaderMap::new(),
+            body: html.into_bytes(),
+        };
+        match raw.json::<ResponseValue>() {
+            Err(Error::Decode { status, body, .. }) => {
+                assert_eq!(status, 520);
+                assert!(body.starts_with("<html>xxx"));
+                assert_eq!(body.len(), BODY_SNIPPET_LEN + 3);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // the snippet is measured in characters, not bytes
+        let raw = RawResponse {
+            status_code: 502,
+            headers: HeaderMap::new(),
+            body: "Passerelle défaillante ❌".as_bytes().to_vec(),
+        };
+        match raw.json::<ResponseValue>() {
+            Err(Error::Decode { body, .. }) => {
+                assert_eq!(body, "Passerelle défaillante ❌");
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
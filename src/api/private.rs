@@ -23,6 +23,8 @@ pub(crate) enum PrivateMethod {
     TradesHistory,
     // Private User Trading
     AddOrder,
+    AddOrderBatch,
+    EditOrder,
     CancelAll,
     CancelOrder,
     CancelAllOrdersAfter,
@@ -165,6 +167,16 @@ pub fn add_order() -> ApiBuilder {
     ApiBuilder::private(PrivateMethod::AddOrder)
 }
 
+/// Add a batch of orders of the same pair.
+pub fn add_order_batch() -> ApiBuilder {
+    ApiBuilder::private(PrivateMethod::AddOrderBatch)
+}
+
+/// Edit open order.
+pub fn edit_order() -> ApiBuilder {
+    ApiBuilder::private(PrivateMethod::EditOrder)
+}
+
 /// Cancel open order.
 pub fn cancel_order() -> ApiBuilder {
     ApiBuilder::private(PrivateMethod::CancelOrder)
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
-use std::{borrow::Cow, fmt};
+use std::{borrow::Cow, fmt, str::FromStr};
+
+use crate::Error;
 
 /// Kraken tradable asset name, such a cryptocurrencies or FIAT.
 #[derive(
@@ -58,6 +60,56 @@ impl<'a> fmt::Display for AssetPair<'a> {
     }
 }
 
+impl<'a> FromStr for AssetPair<'a> {
+    type Err = Error;
+
+    /// Parses the Websockets name (e.g. `XBT/EUR`), the REST name with
+    /// legacy prefixes (e.g. `XXBTZEUR`) or the alternate name made of two
+    /// three characters assets (e.g. `XBTEUR`).
+    ///
+    /// Other alternate names (e.g. `USDCUSD`) cannot be split without the
+    /// assets list, use the `AssetRegistry` to resolve them.
+    fn from_str(pair: &str) -> Result<Self, Self::Err> {
+        let (base, quote) = if let Some((base, quote)) = pair.split_once('/') {
+            (base, quote)
+        } else if is_legacy_pair(pair) {
+            pair.split_at(4)
+        } else if pair.len() == 6 && pair.is_ascii() {
+            pair.split_at(3)
+        } else {
+            return Err(Error::invalid_pair(pair));
+        };
+
+        let valid = |asset: &str| {
+            !asset.is_empty()
+                && asset.chars().all(|c| c.is_ascii_alphanumeric())
+        };
+        if !valid(base) || !valid(quote) {
+            return Err(Error::invalid_pair(pair));
+        }
+
+        Ok(Asset::new(base.to_string()).pair(quote.to_string()))
+    }
+}
+
+/// Returns true only if the given pair is made of two assets with the legacy
+/// `X` (crypto) or `Z` (fiat) prefixes (e.g. `XXBTZEUR`).
+fn is_legacy_pair(pair: &str) -> bool {
+    let prefixed = |c| c == b'X' || c == b'Z';
+    let bytes = pair.as_bytes();
+    bytes.len() == 8
+        && pair.is_ascii()
+        && prefixed(bytes[0])
+        && prefixed(bytes[4])
+}
+
+impl<'a> AssetPair<'a> {
+    /// Gets the Websockets name of the asset pair (e.g. `XBT/EUR`).
+    pub fn wsname(&self) -> String {
+        format!("{}/{}", self.base, self.quote)
+    }
+}
+
 impl<'a> From<(Asset<'a>, Asset<'a>)> for AssetPair<'a> {
     fn from((base, quote): (Asset<'a>, Asset<'a>)) -> Self {
         Self { base, quote }
@@ -73,13 +125,25 @@ impl<'a> From<AssetPair<'a>> for (Asset<'a>, Asset<'a>) {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{api, client, Client, Response};
     use anyhow::Result;
-    use std::collections::HashMap;
 
+    #[cfg(feature = "testkit")]
     #[tokio::test]
     async fn asset_pairs() -> Result<()> {
-        let client = Client::new(client::user_agent())?;
+        use crate::{api, client, testkit::MockServer, Client, Response};
+        use serde_json::json;
+        use std::collections::HashMap;
+
+        let server = MockServer::start()?;
+        server.mock(
+            "AssetPairs",
+            json!({
+                "XXBTZEUR": { "base": "XXBT", "quote": "ZEUR" },
+                "XETHZUSD": { "base": "XETH", "quote": "ZUSD" }
+            }),
+        );
+        let client =
+            Client::new(client::user_agent())?.with_base_url(server.url());
 
         type AssetPairs<'a> = HashMap<String, AssetPair<'a>>;
 
@@ -89,6 +153,10 @@ mod tests {
         println!("{:#?}", resp.result);
 
         let asset_pairs = resp.result.expect("No asset pairs in response");
+        assert_eq!(
+            asset_pairs.get("XXBTZEUR"),
+            Some(&Asset::new("XXBT").pair("ZEUR"))
+        );
         for (_, asset_pair) in asset_pairs {
             let (base, quote): (Asset, Asset) = asset_pair.clone().into();
             assert_eq!(AssetPair::from((base, quote)), asset_pair);
@@ -113,4 +181,25 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn asset_pair_from_str() -> Result<()> {
+        let xbt_eur = Asset::new("XBT").pair("EUR");
+        assert_eq!("XBT/EUR".parse::<AssetPair>()?, xbt_eur);
+        assert_eq!("XBTEUR".parse::<AssetPair>()?, xbt_eur);
+        assert_eq!(xbt_eur.wsname(), "XBT/EUR");
+
+        let legacy: AssetPair = "XXBTZEUR".parse()?;
+        assert_eq!(legacy, Asset::new("XXBT").pair("ZEUR"));
+        assert_eq!(legacy.to_string(), "XXBTZEUR");
+
+        assert_eq!(
+            "USDCUSD".parse::<AssetPair>(),
+            Err(Error::InvalidAssetPair("USDCUSD".into()))
+        );
+        assert!("XBT/".parse::<AssetPair>().is_err());
+        assert!("XBT/E-R".parse::<AssetPair>().is_err());
+
+        Ok(())
+    }
 }
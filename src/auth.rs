@@ -1,51 +1,378 @@
 use reqwest::header::HeaderValue;
-use std::{fs, path::Path};
+use serde::{de, Deserialize, Deserializer};
+use std::{
+    collections::BTreeMap,
+    env, fmt, fs,
+    path::{Path, PathBuf},
+};
+use zeroize::{Zeroize, Zeroizing};
 
 use crate::{Error, Result};
 
+/// Number of characters of the API key shown by `Debug` and `Display`.
+const VISIBLE_KEY_CHARS: usize = 4;
+
+/// Default name of the environment variable of the public API key.
+pub const API_KEY_VAR: &str = "KRAKEN_API_KEY";
+
+/// Default name of the environment variable of the private key.
+pub const PRIVATE_KEY_VAR: &str = "KRAKEN_PRIVATE_KEY";
+
+/// Source of the credentials, such as environment variables, key files or
+/// an external secret store.
+pub trait CredentialProvider {
+    /// Loads the credentials.
+    fn credentials(&self) -> Result<Credentials>;
+}
+
 /// Public-Private key pair to be used by the API.
+///
+/// The keys are validated at construction, the private key is decoded once,
+/// and both are zeroed in memory when dropped. `Debug` and `Display` only
+/// show the beginning of the public API key.
 #[derive(Clone)]
 pub struct Credentials {
-    api_key: HeaderValue,
-    private_key: HeaderValue,
+    api_key: Zeroizing<String>,
+    /// The Base64 decoded private key.
+    private_key: Zeroizing<Vec<u8>>,
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("api_key", &self.redacted_api_key())
+            .field("private_key", &"<redacted>")
+            .finish()
+    }
+}
+
+impl fmt::Display for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "API key {}", self.redacted_api_key())
+    }
 }
 
 impl Credentials {
+    /// Constructs new credentials from the given public API key and Base64
+    /// encoded private key.
+    pub fn new(
+        api_key: impl Into<String>,
+        private_key: impl Into<String>,
+    ) -> Result<Self> {
+        let private_key = Zeroizing::new(private_key.into());
+        Ok(Self {
+            api_key: validate_api_key(api_key.into())
+                .map_err(Error::invalid_key)?,
+            private_key: decode_private_key(&private_key)
+                .map_err(Error::invalid_key)?,
+        })
+    }
+
+    /// Reads the credentials from the `KRAKEN_API_KEY` and
+    /// `KRAKEN_PRIVATE_KEY` environment variables.
+    pub fn from_env() -> Result<Self> {
+        EnvVars::default().credentials()
+    }
+
+    /// Reads the credentials from the given environment variables.
+    pub fn from_env_vars(
+        api_key_var: &str,
+        private_key_var: &str,
+    ) -> Result<Self> {
+        EnvVars::new(api_key_var, private_key_var).credentials()
+    }
+
     /// Reads the given file where the first line contains the public API key
     /// and the second line contains the private key.
+    ///
+    /// Returns an `InvalidKeyFile` error with the line number of the missing
+    /// or invalid key, if any.
     pub fn read(path: impl AsRef<Path>) -> Result<Self> {
-        let content = fs::read_to_string(path).map_err(Error::invalid_key)?;
-        let mut lines: Vec<&str> = content.lines().collect();
+        let content = Zeroizing::new(
+            fs::read_to_string(path).map_err(Error::invalid_key)?,
+        );
+        let mut lines = content
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty());
 
-        let private_key = lines
-            .pop()
-            .map(|k| HeaderValue::from_str(k))
-            .transpose()
-            .map_err(Error::invalid_key)?;
+        let (line, api_key) = lines
+            .next()
+            .ok_or_else(|| key_file_error(1, "missing API key"))?;
+        let api_key = validate_api_key(api_key.to_string())
+            .map_err(|e| key_file_error(line, e))?;
 
-        let api_key = lines
-            .pop()
-            .map(|k| HeaderValue::from_str(k))
-            .transpose()
-            .map_err(Error::invalid_key)?;
+        let (line, private_key) = lines
+            .next()
+            .ok_or_else(|| key_file_error(line + 1, "missing private key"))?;
+        let private_key = decode_private_key(private_key)
+            .map_err(|e| key_file_error(line, e))?;
 
-        match (api_key, private_key) {
-            (Some(api_key), Some(private_key)) => Ok(Self {
-                api_key,
-                private_key,
-            }),
-            _ => Err(Error::invalid_key("key not found")),
+        if let Some((line, _)) = lines.next() {
+            return Err(key_file_error(line, "unexpected content"));
         }
+
+        Ok(Self {
+            api_key,
+            private_key,
+        })
     }
 
     /// Gets the API public key.
-    pub(crate) fn api_key(&self) -> &HeaderValue {
+    pub(crate) fn api_key(&self) -> &str {
         &self.api_key
     }
 
+    /// Gets the API public key as sensitive header value.
+    pub(crate) fn api_key_header(&self) -> Result<HeaderValue> {
+        let mut header = HeaderValue::from_str(self.api_key())
+            .map_err(Error::invalid_key)?;
+        header.set_sensitive(true);
+        Ok(header)
+    }
+
     /// Gets the private key decoded as Base64.
-    pub(crate) fn private_key(&self) -> Result<Vec<u8>> {
-        base64::decode(&self.private_key).map_err(Error::invalid_key)
+    pub(crate) fn private_key(&self) -> &[u8] {
+        &self.private_key
+    }
+
+    /// Gets the beginning of the API key, followed by asterisks.
+    fn redacted_api_key(&self) -> String {
+        let visible: String =
+            self.api_key.chars().take(VISIBLE_KEY_CHARS).collect();
+        format!("{}****", visible)
+    }
+}
+
+/// Validates the given API key, which must be a non empty header value.
+fn validate_api_key(
+    api_key: String,
+) -> std::result::Result<Zeroizing<String>, String> {
+    let api_key = Zeroizing::new(api_key);
+    if api_key.trim().is_empty() {
+        return Err("empty API key".into());
+    }
+    if HeaderValue::from_str(&api_key).is_err() {
+        return Err("invalid characters in API key".into());
+    }
+    Ok(api_key)
+}
+
+/// Decodes the given Base64 private key, which must not be empty.
+fn decode_private_key(
+    private_key: &str,
+) -> std::result::Result<Zeroizing<Vec<u8>>, String> {
+    let decoded = base64::decode(private_key.trim())
+        .map(Zeroizing::new)
+        .map_err(|e| format!("private key is not valid Base64: {}", e))?;
+    if decoded.is_empty() {
+        return Err("empty private key".into());
+    }
+    Ok(decoded)
+}
+
+/// Constructs the error of the given line of a key file.
+fn key_file_error(line: usize, err: impl fmt::Display) -> Error {
+    Error::InvalidKeyFile {
+        line,
+        err: err.to_string(),
+    }
+}
+
+impl CredentialProvider for Credentials {
+    fn credentials(&self) -> Result<Credentials> {
+        Ok(self.clone())
+    }
+}
+
+/// Names of the environment variables containing the keys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvVars {
+    api_key: String,
+    private_key: String,
+}
+
+impl Default for EnvVars {
+    /// The `KRAKEN_API_KEY` and `KRAKEN_PRIVATE_KEY` variables.
+    fn default() -> Self {
+        Self::new(API_KEY_VAR, PRIVATE_KEY_VAR)
+    }
+}
+
+impl EnvVars {
+    /// Constructs the names of the public and private key variables.
+    pub fn new(
+        api_key: impl Into<String>,
+        private_key: impl Into<String>,
+    ) -> Self {
+        Self {
+            api_key: api_key.into(),
+            private_key: private_key.into(),
+        }
+    }
+
+    /// Gets the value of the given variable.
+    fn var(name: &str) -> Result<Zeroizing<String>> {
+        env::var(name)
+            .map(Zeroizing::new)
+            .map_err(|e| Error::invalid_key(format!("{}: {}", name, e)))
+    }
+}
+
+impl CredentialProvider for EnvVars {
+    fn credentials(&self) -> Result<Credentials> {
+        let api_key = Self::var(&self.api_key)?;
+        let private_key = Self::var(&self.private_key)?;
+        Credentials::new(api_key.as_str(), private_key.as_str())
+    }
+}
+
+/// Keys of a named key set of a structured key file.
+#[derive(Deserialize)]
+struct KeySet {
+    api_key: String,
+    private_key: String,
+}
+
+/// Key set validated while parsing, so that the errors have the line of the
+/// key set.
+struct ValidKeySet(Credentials);
+
+impl<'de> Deserialize<'de> for ValidKeySet {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let set = KeySet::deserialize(deserializer)?;
+        Credentials::new(set.api_key.as_str(), set.private_key.as_str())
+            .map(Self)
+            .map_err(de::Error::custom)
+    }
+}
+
+impl Drop for KeySet {
+    fn drop(&mut self) {
+        self.api_key.zeroize();
+        self.private_key.zeroize();
+    }
+}
+
+/// Named key sets (e.g. "trading", "funding", "readonly") read from a TOML
+/// or JSON file.
+///
+/// Each key set is a table (or object) with the `api_key` and `private_key`
+/// fields:
+///
+/// ```toml
+/// [trading]
+/// api_key = "<api_key>"
+/// private_key = "<private_key>"
+///
+/// [readonly]
+/// api_key = "<api_key>"
+/// private_key = "<private_key>"
+/// ```
+///
+/// The TOML files require the `toml` optional feature.
+#[derive(Debug, Clone, Default)]
+pub struct Keyring {
+    sets: BTreeMap<String, Credentials>,
+}
+
+impl Keyring {
+    /// Reads the given key file, parsed as JSON if its extension is `json`,
+    /// and as TOML otherwise.
+    ///
+    /// Returns an `InvalidKeyFile` error with the line number of the syntax
+    /// error or of the invalid key set, if any.
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = Zeroizing::new(
+            fs::read_to_string(path).map_err(Error::invalid_key)?,
+        );
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => Self::from_json(&content),
+            #[cfg(feature = "toml")]
+            _ => Self::from_toml(&content),
+            #[cfg(not(feature = "toml"))]
+            _ => Err(Error::invalid_key(
+                "TOML key files require the `toml` feature",
+            )),
+        }
+    }
+
+    /// Parses the key sets of the given TOML document.
+    #[cfg(feature = "toml")]
+    pub fn from_toml(content: &str) -> Result<Self> {
+        let sets: BTreeMap<String, ValidKeySet> = toml::from_str(content)
+            .map_err(|e| {
+                let line = e.line_col().map(|(line, _)| line + 1).unwrap_or(1);
+                key_file_error(line, e)
+            })?;
+        Ok(Self::from_sets(sets))
+    }
+
+    /// Parses the key sets of the given JSON document.
+    pub fn from_json(content: &str) -> Result<Self> {
+        let sets: BTreeMap<String, ValidKeySet> = serde_json::from_str(content)
+            .map_err(|e| key_file_error(e.line(), e))?;
+        Ok(Self::from_sets(sets))
+    }
+
+    /// Constructs the keyring of the given validated key sets.
+    fn from_sets(sets: BTreeMap<String, ValidKeySet>) -> Self {
+        let sets = sets.into_iter().map(|(name, set)| (name, set.0)).collect();
+        Self { sets }
+    }
+
+    /// Gets the credentials of the given key set, if any.
+    pub fn get(&self, name: &str) -> Option<&Credentials> {
+        self.sets.get(name)
+    }
+
+    /// Gets the names of the key sets.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.sets.keys().map(String::as_str)
+    }
+}
+
+/// Key file read at each load, either the two-line file of
+/// `Credentials::read`, or a named key set of a `Keyring` file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyFile {
+    path: PathBuf,
+    name: Option<String>,
+}
+
+impl KeyFile {
+    /// Constructs the provider of the given two-line key file.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            name: None,
+        }
+    }
+
+    /// Constructs the provider of the given key set of a `Keyring` file.
+    pub fn named(path: impl Into<PathBuf>, name: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            name: Some(name.into()),
+        }
+    }
+}
+
+impl CredentialProvider for KeyFile {
+    fn credentials(&self) -> Result<Credentials> {
+        match &self.name {
+            None => Credentials::read(&self.path),
+            Some(name) => Keyring::read(&self.path)?
+                .get(name)
+                .cloned()
+                .ok_or_else(|| {
+                    Error::invalid_key(format!("unknown key set: {}", name))
+                }),
+        }
     }
 }
 
@@ -69,7 +396,8 @@ pub(crate) mod tests {
             let path = env::temp_dir().join(Uuid::new_v4().to_string());
 
             let api_key = "<api_key>".to_string();
-            let private_key = "<private_key>".to_string();
+            // base64 encoded "<private_key>"
+            let private_key = "PHByaXZhdGVfa2V5Pg==".to_string();
             fs::write(&path, format!("{}\n{}", api_key, private_key))?;
 
             Ok(Self {
@@ -93,8 +421,166 @@ pub(crate) mod tests {
         let dummy = DummyCredentials::new()?;
 
         let credentials = Credentials::read(&dummy.path)?;
-        assert_eq!(dummy.api_key, credentials.api_key.to_str()?);
-        assert_eq!(dummy.private_key, credentials.private_key.to_str()?);
+        assert_eq!(dummy.api_key, credentials.api_key());
+        assert_eq!(
+            base64::decode(&dummy.private_key)?,
+            credentials.private_key()
+        );
+        assert!(credentials.api_key_header()?.is_sensitive());
+
+        Ok(())
+    }
+
+    #[test]
+    fn redacted_keys() -> Result<()> {
+        let credentials = Credentials::new("abcdefgh", "PHByaXZhdGVfa2V5Pg==")?;
+        assert_eq!(credentials.to_string(), "API key abcd****");
+        assert_eq!(
+            format!("{:?}", credentials),
+            r#"Credentials { api_key: "abcd****", private_key: "<redacted>" }"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_key_file() -> Result<()> {
+        let path = env::temp_dir().join(Uuid::new_v4().to_string());
+        let read = |content: &str| {
+            fs::write(&path, content).expect("cannot write key file");
+            Credentials::read(&path).err()
+        };
+        let error = |line, err: &str| {
+            Some(Error::InvalidKeyFile {
+                line,
+                err: err.into(),
+            })
+        };
+
+        assert_eq!(read(""), error(1, "missing API key"));
+        assert_eq!(read("\n<api_key>\n"), error(3, "missing private key"));
+        assert!(matches!(
+            read("<api_key>\nnot base64!"),
+            Some(Error::InvalidKeyFile { line: 2, .. })
+        ));
+        assert_eq!(
+            read("<api_key>\nPHByaXZhdGVfa2V5Pg==\nextra"),
+            error(3, "unexpected content")
+        );
+        assert!(read("\n<api_key>\n\nPHByaXZhdGVfa2V5Pg==\n").is_none());
+        fs::remove_file(&path)?;
+
+        assert_eq!(
+            Credentials::new("", "PHByaXZhdGVfa2V5Pg==").err(),
+            Some(Error::InvalidKey("empty API key".into()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn env_keys() -> Result<()> {
+        let vars =
+            EnvVars::new("AKKOROKAMUI_TEST_API", "AKKOROKAMUI_TEST_PRIVATE");
+        assert!(vars.credentials().is_err());
+
+        env::set_var("AKKOROKAMUI_TEST_API", "<api_key>");
+        env::set_var("AKKOROKAMUI_TEST_PRIVATE", "PHByaXZhdGVfa2V5Pg==");
+        let credentials = Credentials::from_env_vars(
+            "AKKOROKAMUI_TEST_API",
+            "AKKOROKAMUI_TEST_PRIVATE",
+        )?;
+        assert_eq!(credentials.api_key(), "<api_key>");
+        assert_eq!(credentials.private_key(), b"<private_key>");
+
+        Ok(())
+    }
+
+    #[test]
+    fn keyring() -> Result<()> {
+        let json = r#"{
+            "trading": {
+                "api_key": "<trading>",
+                "private_key": "PHByaXZhdGVfa2V5Pg=="
+            },
+            "readonly": {
+                "api_key": "<readonly>",
+                "private_key": "PHByaXZhdGVfa2V5Pg=="
+            }
+        }"#;
+        let keyring = Keyring::from_json(json)?;
+        assert_eq!(
+            keyring.names().collect::<Vec<_>>(),
+            ["readonly", "trading"]
+        );
+        assert_eq!(
+            keyring.get("trading").map(Credentials::api_key),
+            Some("<trading>")
+        );
+        assert!(keyring.get("funding").is_none());
+
+        // the invalid key sets are reported once parsed, after their object
+        let json = r#"{
+            "funding": {
+                "api_key": "<funding>",
+                "private_key": "not base64!"
+            }
+        }"#;
+        assert!(matches!(
+            Keyring::from_json(json),
+            Err(Error::InvalidKeyFile { line: 6, .. })
+        ));
+        assert!(matches!(
+            Keyring::from_json("{\n\"funding\": {\n\"api_key\" 1"),
+            Err(Error::InvalidKeyFile { line: 3, .. })
+        ));
+
+        let path = env::temp_dir().join(format!("{}.json", Uuid::new_v4()));
+        let json = r#"{
+            "readonly": {
+                "api_key": "<readonly>",
+                "private_key": "PHByaXZhdGVfa2V5Pg=="
+            }
+        }"#;
+        fs::write(&path, json)?;
+        let credentials = KeyFile::named(&path, "readonly").credentials()?;
+        assert_eq!(credentials.api_key(), "<readonly>");
+        assert!(KeyFile::named(&path, "funding").credentials().is_err());
+        fs::remove_file(&path)?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn toml_keyring() -> Result<()> {
+        let toml = r#"
+            [trading]
+            api_key = "<trading>"
+            private_key = "PHByaXZhdGVfa2V5Pg=="
+
+            [readonly]
+            api_key = "<readonly>"
+            private_key = "PHByaXZhdGVfa2V5Pg=="
+        "#;
+        let keyring = Keyring::from_toml(toml)?;
+        assert_eq!(
+            keyring.names().collect::<Vec<_>>(),
+            ["readonly", "trading"]
+        );
+
+        let path = env::temp_dir().join(format!("{}.toml", Uuid::new_v4()));
+        fs::write(&path, toml)?;
+        let credentials = KeyFile::named(&path, "readonly").credentials()?;
+        assert_eq!(credentials.api_key(), "<readonly>");
+        fs::remove_file(&path)?;
+
+        // the invalid key sets are reported at their header line
+        let toml = "[trading]\n\
+            api_key = \"<trading>\"\n\
+            private_key = \"not base64!\"\n";
+        assert!(matches!(
+            Keyring::from_toml(toml),
+            Err(Error::InvalidKeyFile { line: 1, .. })
+        ));
 
         Ok(())
     }
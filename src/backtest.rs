@@ -0,0 +1,464 @@
+//! Backtesting of trading strategies.
+//!
+//! The `Backtest` replays historical candles or public trades through a
+//! `SimExchange`, calling the `Strategy` on each market event. The strategy
+//! places its orders with typed `OrderRequest`s through a client backed by
+//! the simulated exchange, which it can also query with the usual private
+//! APIs (e.g. `balance` or `open_orders`). The returned `Report` summarizes
+//! the fills, fees and PnL.
+//!
+//! The history is either fetched with the `ohlc` and `trades` APIs and cached
+//! to disk (`fetch_candles` and `fetch_trades`), or loaded from CSV files
+//! (`load_candles` and `load_trades`).
+//!
+//! For each candle the simulated price moves from the open to the low and
+//! the high (the one farther from the close first) and then to the close,
+//! filling the resting limit orders it crosses, before the strategy is
+//! called at the close price.
+//!
+//! ```no_run
+//! use akkorokamui::{
+//!     backtest::{self, Backtest, Context, MarketEvent, Strategy},
+//!     sim::SimExchange,
+//!     Error, Order, OrderRequest,
+//! };
+//!
+//! struct BuyTheDip;
+//!
+//! impl Strategy for BuyTheDip {
+//!     fn on_event(
+//!         &mut self,
+//!         event: &MarketEvent,
+//!         ctx: &Context,
+//!     ) -> Result<(), Error> {
+//!         if let MarketEvent::Candle(candle) = event {
+//!             if candle.close < candle.open {
+//!                 let volume = "0.01".parse().unwrap();
+//!                 ctx.submit(&OrderRequest::market(Order::Buy, volume))?;
+//!             }
+//!         }
+//!         Ok(())
+//!     }
+//! }
+//!
+//! fn main() -> anyhow::Result<()> {
+//!     let candles = backtest::load_candles("XBTEUR_60.csv")?;
+//!
+//!     let sim = SimExchange::new().with_balance("EUR", "10000".parse()?);
+//!     let backtest = Backtest::new("XBTEUR", sim)?;
+//!     let report = backtest.run(candles, &mut BuyTheDip)?;
+//!     println!("{}", report);
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use std::{
+    fmt,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+
+use crate::{
+    api, blocking, client,
+    history::TradeInfo,
+    market::{self, Candle, Ohlc, PublicTrade, Trades},
+    sim::SimExchange,
+    timestamp::Timestamp,
+    Amount, AssetPair, Error, OrderRequest, Response, ResponseValue, Result,
+};
+
+/// Historical market event.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MarketEvent {
+    /// A closed candle.
+    Candle(Candle),
+    /// A public trade.
+    Trade(PublicTrade),
+}
+
+impl From<Candle> for MarketEvent {
+    fn from(candle: Candle) -> Self {
+        Self::Candle(candle)
+    }
+}
+
+impl From<PublicTrade> for MarketEvent {
+    fn from(trade: PublicTrade) -> Self {
+        Self::Trade(trade)
+    }
+}
+
+impl MarketEvent {
+    /// Gets the event time.
+    pub fn time(&self) -> Timestamp {
+        match self {
+            Self::Candle(candle) => candle.time,
+            Self::Trade(trade) => trade.time,
+        }
+    }
+
+    /// Gets the price after the event (i.e. the candle close price).
+    pub fn price(&self) -> Amount {
+        match self {
+            Self::Candle(candle) => candle.close,
+            Self::Trade(trade) => trade.price,
+        }
+    }
+
+    /// Gets the successive prices of the event.
+    fn path(&self) -> Vec<Amount> {
+        match self {
+            Self::Candle(c) if c.close >= c.open => {
+                vec![c.open, c.low, c.high, c.close]
+            }
+            Self::Candle(c) => vec![c.open, c.high, c.low, c.close],
+            Self::Trade(trade) => vec![trade.price],
+        }
+    }
+}
+
+/// Trading strategy.
+pub trait Strategy {
+    /// Handles the given market event, once the simulated exchange reflects
+    /// it.
+    fn on_event(&mut self, event: &MarketEvent, ctx: &Context) -> Result<()>;
+}
+
+/// Access of the strategy to the simulated exchange.
+pub struct Context<'a> {
+    pair: &'a str,
+    client: &'a blocking::Client,
+}
+
+impl<'a> Context<'a> {
+    /// Gets the backtested pair.
+    pub fn pair(&self) -> &str {
+        self.pair
+    }
+
+    /// Gets the client of the simulated exchange.
+    pub fn client(&self) -> &blocking::Client {
+        self.client
+    }
+
+    /// Submits the given order for the backtested pair, returning its
+    /// transaction ID.
+    pub fn submit(&self, order: &OrderRequest) -> Result<String> {
+        let resp: ResponseValue = self.client.send(order.api(self.pair))?;
+        if !resp.error.is_empty() {
+            return Err(Error::Api(resp.error));
+        }
+        resp.get("txid")
+            .and_then(|txid| txid[0].as_str())
+            .map(String::from)
+            .ok_or_else(|| Error::invalid_message("missing order txid"))
+    }
+
+    /// Cancels the given order.
+    pub fn cancel(&self, txid: &str) -> Result<()> {
+        let api = api::private::cancel_order().with("txid", txid);
+        let resp: ResponseValue = self.client.send(api)?;
+        if !resp.error.is_empty() {
+            return Err(Error::Api(resp.error));
+        }
+        Ok(())
+    }
+}
+
+/// Summary of a backtest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    /// The fills of the strategy orders, oldest first.
+    pub fills: Vec<TradeInfo>,
+    /// The total fees, in quote asset.
+    pub fees: Amount,
+    /// The total traded cost, in quote asset.
+    pub volume: Amount,
+    /// The value of the pair balances at the first price, in quote asset.
+    pub start_equity: Amount,
+    /// The value of the pair balances at the last price, in quote asset.
+    pub end_equity: Amount,
+    /// The profit and loss, net of fees, in quote asset.
+    pub pnl: Amount,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "fills:        {}", self.fills.len())?;
+        writeln!(f, "volume:       {}", self.volume)?;
+        writeln!(f, "fees:         {}", self.fees)?;
+        writeln!(f, "start equity: {}", self.start_equity)?;
+        writeln!(f, "end equity:   {}", self.end_equity)?;
+        write!(f, "pnl:          {}", self.pnl)
+    }
+}
+
+/// Backtest runner of a single pair.
+#[derive(Clone)]
+pub struct Backtest {
+    /// The pair name of the orders.
+    pair: String,
+    /// The assets of the pair.
+    assets: AssetPair<'static>,
+    sim: SimExchange,
+    /// The client backed by the simulated exchange.
+    client: blocking::Client,
+}
+
+impl Backtest {
+    /// Constructs a new backtest of the given pair on the given simulated
+    /// exchange, whose balances are keyed by the pair asset names.
+    pub fn new(pair: impl Into<String>, sim: SimExchange) -> Result<Self> {
+        let pair = pair.into();
+        let assets = pair.parse()?;
+        let client =
+            blocking::Client::new(client::user_agent())?.with_sim(sim.clone());
+        Ok(Self {
+            pair,
+            assets,
+            sim,
+            client,
+        })
+    }
+
+    /// Gets the simulated exchange.
+    pub fn sim(&self) -> &SimExchange {
+        &self.sim
+    }
+
+    /// Replays the given events through the simulated exchange and the given
+    /// strategy.
+    pub fn run<E, S>(
+        &self,
+        events: impl IntoIterator<Item = E>,
+        strategy: &mut S,
+    ) -> Result<Report>
+    where
+        E: Into<MarketEvent>,
+        S: Strategy + ?Sized,
+    {
+        let ctx = Context {
+            pair: &self.pair,
+            client: &self.client,
+        };
+        let fills_before = self.sim.trades().len();
+        let mut start_equity = None;
+        let mut last_price = None;
+
+        for event in events {
+            let event = event.into();
+            self.sim.set_time(event.time());
+            for price in event.path() {
+                if start_equity.is_none() {
+                    start_equity = Some(self.equity(price));
+                }
+                self.sim.set_price(&self.pair, price)?;
+            }
+            strategy.on_event(&event, &ctx)?;
+            last_price = Some(event.price());
+        }
+
+        let fills: Vec<_> =
+            self.sim.trades().into_iter().skip(fills_before).collect();
+        let zero = Amount::default();
+        let fees = fills.iter().fold(zero, |sum, fill| sum + fill.fee);
+        let volume = fills.iter().fold(zero, |sum, fill| sum + fill.cost);
+        let start_equity = start_equity.unwrap_or_default();
+        let end_equity = last_price.map(|p| self.equity(p)).unwrap_or_default();
+
+        Ok(Report {
+            fills,
+            fees,
+            volume,
+            start_equity,
+            end_equity,
+            pnl: end_equity - start_equity,
+        })
+    }
+
+    /// Gets the value of the pair balances at the given price.
+    fn equity(&self, price: Amount) -> Amount {
+        let base = self.sim.balance(&self.assets.base.to_string());
+        let quote = self.sim.balance(&self.assets.quote.to_string());
+        quote + base * price
+    }
+}
+
+/// Fetches the last closed candles of the given pair and interval (in
+/// minutes), unless already cached in the given CSV file.
+pub fn fetch_candles(
+    client: &blocking::Client,
+    pair: &str,
+    interval: u32,
+    cache: impl AsRef<Path>,
+) -> Result<Vec<Candle>> {
+    let cache = cache.as_ref();
+    if cache.exists() {
+        return load_candles(cache);
+    }
+
+    let api = api::public::ohlc()
+        .with("pair", pair)
+        .with("interval", interval);
+    let resp: Response<Ohlc> = client.send(api)?;
+    let mut candles = result(resp)?.into_entries();
+    // the last candle is still forming
+    candles.pop();
+
+    let file = File::create(cache).map_err(Error::internal)?;
+    market::write_candles(BufWriter::new(file), &candles)?;
+    Ok(candles)
+}
+
+/// Fetches the trades of the given pair since the given time, unless already
+/// cached in the given CSV file.
+pub fn fetch_trades(
+    client: &blocking::Client,
+    pair: &str,
+    since: Timestamp,
+    cache: impl AsRef<Path>,
+) -> Result<Vec<PublicTrade>> {
+    let cache = cache.as_ref();
+    if cache.exists() {
+        return load_trades(cache);
+    }
+
+    let api = api::public::trades()
+        .with("pair", pair)
+        .with("since", since.secs());
+    let resp: Response<Trades> = client.send(api)?;
+    let trades = result(resp)?.into_entries();
+
+    let file = File::create(cache).map_err(Error::internal)?;
+    market::write_trades(BufWriter::new(file), &trades)?;
+    Ok(trades)
+}
+
+/// Loads the candles of the given CSV file.
+pub fn load_candles(path: impl AsRef<Path>) -> Result<Vec<Candle>> {
+    let file = File::open(path).map_err(Error::internal)?;
+    market::read_candles(BufReader::new(file))
+}
+
+/// Loads the trades of the given CSV file.
+pub fn load_trades(path: impl AsRef<Path>) -> Result<Vec<PublicTrade>> {
+    let file = File::open(path).map_err(Error::internal)?;
+    market::read_trades(BufReader::new(file))
+}
+
+/// Gets the result of the given response.
+fn result<T>(resp: Response<T>) -> Result<T> {
+    if !resp.error.is_empty() {
+        return Err(Error::Api(resp.error));
+    }
+    resp.result
+        .ok_or_else(|| Error::invalid_message("missing market data"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{amount::amount, Order};
+    use anyhow::Result;
+
+    fn candle(time: i64, prices: [&str; 4]) -> Candle {
+        let [open, high, low, close] = prices;
+        Candle {
+            time: Timestamp::from_secs(time),
+            open: amount(open),
+            high: amount(high),
+            low: amount(low),
+            close: amount(close),
+            vwap: amount(close),
+            volume: amount("1"),
+            count: 1,
+        }
+    }
+
+    /// Buys once and sells at the given take profit price.
+    struct TakeProfit {
+        target: Amount,
+        bought: bool,
+    }
+
+    impl Strategy for TakeProfit {
+        fn on_event(
+            &mut self,
+            _event: &MarketEvent,
+            ctx: &Context,
+        ) -> crate::Result<()> {
+            if !self.bought {
+                let volume = amount("1");
+                ctx.submit(&OrderRequest::market(Order::Buy, volume))?;
+                let sell =
+                    OrderRequest::limit(Order::Sell, volume, self.target);
+                ctx.submit(&sell)?;
+                self.bought = true;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn candles_backtest() -> Result<()> {
+        let sim = SimExchange::new()
+            .with_balance("EUR", amount("1000"))
+            .with_fees(amount("0"), amount("0.1"));
+        let backtest = Backtest::new("XBTEUR", sim)?;
+
+        let candles = vec![
+            candle(1_625_918_400, ["100", "105", "95", "100"]),
+            // crosses 110 before closing at 108
+            candle(1_625_922_000, ["100", "112", "99", "108"]),
+        ];
+        let mut strategy = TakeProfit {
+            target: amount("110"),
+            bought: false,
+        };
+        let report = backtest.run(candles, &mut strategy)?;
+
+        assert_eq!(report.fills.len(), 2);
+        assert_eq!(report.fills[0].time, Timestamp::from_secs(1_625_918_400));
+        assert_eq!(report.fills[1].price, amount("110"));
+        assert_eq!(report.fills[1].time, Timestamp::from_secs(1_625_922_000));
+        assert_eq!(format!("{:.2}", report.volume), "210.00");
+        assert_eq!(format!("{:.2}", report.fees), "0.10");
+        assert_eq!(format!("{:.2}", report.start_equity), "1000.00");
+        assert_eq!(format!("{:.2}", report.pnl), "9.90");
+        assert!(report.to_string().contains("fills:        2"));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "testkit")]
+    #[test]
+    fn cached_candles() -> Result<()> {
+        use crate::testkit::MockServer;
+        use serde_json::json;
+
+        let server = MockServer::start()?;
+        server.mock(
+            "OHLC",
+            json!({
+                "XXBTZEUR": [
+                    [1625918400, "1", "2", "1", "2", "1.5", "10", 5],
+                    [1625922000, "2", "2", "2", "2", "2", "1", 1]
+                ],
+                "last": 1625918400
+            }),
+        );
+        let client = blocking::Client::new(client::user_agent())?
+            .with_base_url(server.url());
+
+        let cache = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let candles = fetch_candles(&client, "XBTEUR", 60, &cache)?;
+        assert_eq!(candles.len(), 1);
+        assert_eq!(fetch_candles(&client, "XBTEUR", 60, &cache)?, candles);
+        assert_eq!(server.requests().len(), 1);
+        std::fs::remove_file(&cache)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,211 @@
+//! Local order books maintained from Kraken market data.
+
+use serde::{de, Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::{cmp::Ordering, fmt};
+
+pub use l2::{BookUpdate, Depth, OrderBook};
+pub use l3::{
+    L3Data, L3Entry, L3Event, L3Message, L3Order, L3OrderBook, QueuePosition,
+};
+
+mod l2;
+mod l3;
+
+/// Number of price levels per side included in the Kraken book checksum.
+pub(crate) const CHECKSUM_LEVELS: usize = 10;
+
+/// The side of an order book.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    Hash,
+)]
+pub enum Side {
+    /// Buy orders.
+    Bid,
+    /// Sell orders.
+    Ask,
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let side = match self {
+            Self::Bid => "bid",
+            Self::Ask => "ask",
+        };
+        write!(f, "{}", side)
+    }
+}
+
+/// A single price level of an order book.
+///
+/// Prices and volumes are kept as the strings sent by Kraken, since their
+/// exact representation is required to compute the book checksum.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Hash)]
+pub struct Level {
+    /// The price level.
+    pub price: String,
+    /// The volume available at this price level.
+    pub volume: String,
+    /// The timestamp of the last update of this price level.
+    pub timestamp: String,
+}
+
+impl Level {
+    /// Returns true only if the volume of this level is zero, which in a book
+    /// update means that the level must be removed.
+    pub fn is_empty(&self) -> bool {
+        self.volume.chars().all(|c| c == '0' || c == '.')
+    }
+
+    /// Parses a book entry in the form `[price, volume, timestamp, ...]`,
+    /// where the timestamp can either be a string or a number.
+    pub(crate) fn from_entry(entry: &[Value]) -> crate::Result<Self> {
+        let field = |index: usize| -> crate::Result<String> {
+            match entry.get(index) {
+                Some(Value::String(s)) => Ok(s.clone()),
+                Some(Value::Number(n)) => Ok(n.to_string()),
+                _ => Err(crate::Error::invalid_message(format!(
+                    "invalid book entry: {:?}",
+                    entry
+                ))),
+            }
+        };
+
+        Ok(Self {
+            price: field(0)?,
+            volume: field(1)?,
+            timestamp: field(2)?,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Level {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        let entry = Vec::<Value>::deserialize(deserializer)?;
+        Level::from_entry(&entry).map_err(de::Error::custom)
+    }
+}
+
+/// Exact ordering key of a decimal price represented as a string.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct PriceKey {
+    /// Integer digits without leading zeros.
+    int: String,
+    /// Fractional digits without trailing zeros.
+    frac: String,
+}
+
+impl PriceKey {
+    /// Constructs the ordering key of the given decimal string.
+    pub(crate) fn new(price: &str) -> Self {
+        let (int, frac) = match price.find('.') {
+            Some(i) => (&price[..i], &price[i + 1..]),
+            None => (price, ""),
+        };
+        Self {
+            int: int.trim_start_matches('0').to_string(),
+            frac: frac.trim_end_matches('0').to_string(),
+        }
+    }
+}
+
+impl Ord for PriceKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.int
+            .len()
+            .cmp(&other.int.len())
+            .then_with(|| self.int.cmp(&other.int))
+            .then_with(|| self.frac.cmp(&other.frac))
+    }
+}
+
+impl PartialOrd for PriceKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Computes the Kraken CRC32 checksum of the given asks (ascending) and bids
+/// (descending) price-volume pairs.
+pub(crate) fn checksum<'a>(
+    asks: impl Iterator<Item = (&'a str, &'a str)>,
+    bids: impl Iterator<Item = (&'a str, &'a str)>,
+) -> u32 {
+    let mut payload = String::new();
+    for (price, volume) in
+        asks.take(CHECKSUM_LEVELS).chain(bids.take(CHECKSUM_LEVELS))
+    {
+        push_checksum_field(&mut payload, price);
+        push_checksum_field(&mut payload, volume);
+    }
+    crc32fast::hash(payload.as_bytes())
+}
+
+/// Appends the given decimal string without the decimal point and leading
+/// zeros, as required by the checksum algorithm.
+fn push_checksum_field(payload: &mut String, value: &str) {
+    let digits: String = value.chars().filter(|&c| c != '.').collect();
+    payload.push_str(digits.trim_start_matches('0'));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn price_key_ordering() {
+        assert!(PriceKey::new("0.05") < PriceKey::new("0.5"));
+        assert!(PriceKey::new("0.1") < PriceKey::new("0.12"));
+        assert!(PriceKey::new("9.99") < PriceKey::new("10.0"));
+        assert!(PriceKey::new("0099.1") < PriceKey::new("100"));
+        assert_eq!(PriceKey::new("1.10"), PriceKey::new("1.1000"));
+    }
+
+    #[test]
+    fn level_deserialize() -> anyhow::Result<()> {
+        let rest: Level =
+            serde_json::from_str(r#"["30000.10000","1.500",1616663113]"#)?;
+        assert_eq!(rest.price, "30000.10000");
+        assert_eq!(rest.volume, "1.500");
+        assert_eq!(rest.timestamp, "1616663113");
+
+        let ws: Level = serde_json::from_str(
+            r#"["5541.30000","0.00000000","1534614248.456738","r"]"#,
+        )?;
+        assert!(ws.is_empty());
+        assert_eq!(ws.timestamp, "1534614248.456738");
+
+        assert!(serde_json::from_str::<Level>(r#"["1.0"]"#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn checksum_example() {
+        // example from the Kraken Websockets book checksum guide
+        let asks = [
+            "0.05005", "0.05010", "0.05015", "0.05020", "0.05025", "0.05030",
+            "0.05035", "0.05040", "0.05045", "0.05050",
+        ];
+        let bids = [
+            "0.05000", "0.04995", "0.04990", "0.04980", "0.04975", "0.04970",
+            "0.04965", "0.04960", "0.04955", "0.04950",
+        ];
+        let volume = "0.00000500";
+        let crc = checksum(
+            asks.iter().map(|&p| (p, volume)),
+            bids.iter().map(|&p| (p, volume)),
+        );
+        assert_eq!(crc, 974947235);
+    }
+}
@@ -0,0 +1,504 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::BTreeMap, convert::TryFrom, str::FromStr};
+
+use crate::{
+    book::{self, Level, PriceKey, Side},
+    Amount, AssetPair, Error, Result,
+};
+
+/// Order book snapshot, as returned by the `depth` API for a single pair.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Depth {
+    /// Ask levels sorted by ascending price.
+    pub asks: Vec<Level>,
+    /// Bid levels sorted by descending price.
+    pub bids: Vec<Level>,
+}
+
+/// A Websockets `book` channel message, either a snapshot or an update.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BookUpdate {
+    /// The Websockets channel ID.
+    pub channel_id: u64,
+    /// The channel name (e.g. `book-10`).
+    pub channel_name: String,
+    /// The Websockets asset pair name (e.g. `XBT/EUR`).
+    pub pair: String,
+    /// True only if this message contains the book snapshot.
+    pub snapshot: bool,
+    /// Ask levels to insert, update or remove.
+    pub asks: Vec<Level>,
+    /// Bid levels to insert, update or remove.
+    pub bids: Vec<Level>,
+    /// The checksum of the book after this update is applied.
+    pub checksum: Option<u32>,
+}
+
+impl BookUpdate {
+    /// Gets the book depth from the channel name, if present.
+    pub fn depth(&self) -> Option<usize> {
+        self.channel_name
+            .strip_prefix("book-")
+            .and_then(|d| d.parse().ok())
+    }
+}
+
+impl TryFrom<&Value> for BookUpdate {
+    type Error = Error;
+
+    fn try_from(message: &Value) -> Result<Self> {
+        let invalid = || Error::invalid_message(message);
+
+        // [channelID, {..}, ({..}), channelName, pair]
+        let items = message.as_array().ok_or_else(invalid)?;
+        if items.len() < 4 {
+            return Err(invalid());
+        }
+
+        let channel_id = items[0].as_u64().ok_or_else(invalid)?;
+        let pair = items[items.len() - 1].as_str().ok_or_else(invalid)?;
+        let channel_name =
+            items[items.len() - 2].as_str().ok_or_else(invalid)?;
+
+        let mut update = Self {
+            channel_id,
+            channel_name: channel_name.to_string(),
+            pair: pair.to_string(),
+            snapshot: false,
+            asks: Vec::new(),
+            bids: Vec::new(),
+            checksum: None,
+        };
+
+        for payload in &items[1..items.len() - 2] {
+            let payload = payload.as_object().ok_or_else(invalid)?;
+            for (key, value) in payload {
+                let levels = match key.as_str() {
+                    "as" | "a" => &mut update.asks,
+                    "bs" | "b" => &mut update.bids,
+                    "c" => {
+                        let checksum = value
+                            .as_str()
+                            .and_then(|c| c.parse().ok())
+                            .ok_or_else(invalid)?;
+                        update.checksum = Some(checksum);
+                        continue;
+                    }
+                    _ => continue,
+                };
+
+                update.snapshot |= key.len() == 2;
+                let entries = value.as_array().ok_or_else(invalid)?;
+                for entry in entries {
+                    let entry = entry.as_array().ok_or_else(invalid)?;
+                    levels.push(Level::from_entry(entry)?);
+                }
+            }
+        }
+
+        Ok(update)
+    }
+}
+
+impl FromStr for BookUpdate {
+    type Err = Error;
+
+    fn from_str(message: &str) -> Result<Self> {
+        let message: Value =
+            serde_json::from_str(message).map_err(Error::invalid_message)?;
+        Self::try_from(&message)
+    }
+}
+
+/// Local level 2 order book of a single asset pair.
+///
+/// The book can be seeded from a `depth` API snapshot and then maintained
+/// with the updates received from the Websockets `book` channel. Each update
+/// carrying a checksum is verified against the local state: on mismatch the
+/// book is cleared and will ignore further updates until it is reset with a
+/// new snapshot (i.e. after subscribing to the channel again).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderBook<'a> {
+    /// The asset pair of this book.
+    pair: AssetPair<'a>,
+    /// The subscribed number of levels per side.
+    depth: usize,
+    /// Ask levels by price.
+    asks: BTreeMap<PriceKey, Level>,
+    /// Bid levels by price.
+    bids: BTreeMap<PriceKey, Level>,
+    /// Whether the book reflects the server state.
+    synced: bool,
+}
+
+impl<'a> OrderBook<'a> {
+    /// Constructs a new empty book that keeps at most `depth` levels per side.
+    ///
+    /// The book is not synchronized until the first snapshot is applied.
+    pub fn new(pair: AssetPair<'a>, depth: usize) -> Self {
+        Self {
+            pair,
+            depth,
+            asks: BTreeMap::new(),
+            bids: BTreeMap::new(),
+            synced: false,
+        }
+    }
+
+    /// Constructs a new book seeded with the given snapshot.
+    pub fn with_snapshot(
+        pair: AssetPair<'a>,
+        depth: usize,
+        snapshot: Depth,
+    ) -> Self {
+        let mut book = Self::new(pair, depth);
+        book.reset(snapshot);
+        book
+    }
+
+    /// Replaces the content of the book with the given snapshot.
+    pub fn reset(&mut self, snapshot: Depth) {
+        self.asks.clear();
+        self.bids.clear();
+        self.insert(Side::Ask, snapshot.asks);
+        self.insert(Side::Bid, snapshot.bids);
+        self.truncate();
+        self.synced = true;
+    }
+
+    /// Applies the given Websockets snapshot or update to the book.
+    ///
+    /// Returns an error, without changing the book, if the update belongs to
+    /// another pair (the book pair must use the Websockets asset names, e.g.
+    /// `XBT/EUR`) or to a channel of another depth.
+    ///
+    /// Returns an error if the book checksum does not match the one of the
+    /// update, in which case the book is cleared and must be resynchronized.
+    pub fn apply(&mut self, update: &BookUpdate) -> Result<()> {
+        if update.pair != self.pair.wsname() {
+            return Err(Error::invalid_pair(format!(
+                "update of {} applied to the {} book",
+                update.pair,
+                self.pair.wsname()
+            )));
+        }
+        if let Some(depth) = update.depth() {
+            if depth != self.depth {
+                return Err(Error::invalid_message(format!(
+                    "update of depth {} applied to a book of depth {}",
+                    depth, self.depth
+                )));
+            }
+        }
+
+        if update.snapshot {
+            self.reset(Depth {
+                asks: update.asks.clone(),
+                bids: update.bids.clone(),
+            });
+        } else if self.synced {
+            self.insert(Side::Ask, update.asks.iter().cloned());
+            self.insert(Side::Bid, update.bids.iter().cloned());
+            self.truncate();
+        } else {
+            log::debug!("Ignoring update of unsynchronized {} book", self.pair);
+            return Ok(());
+        }
+
+        if let Some(expected) = update.checksum {
+            let computed = self.checksum();
+            if computed != expected {
+                self.asks.clear();
+                self.bids.clear();
+                self.synced = false;
+                return Err(Error::ChecksumMismatch { expected, computed });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns true only if the book is synchronized with the server.
+    pub fn is_synced(&self) -> bool {
+        self.synced
+    }
+
+    /// Gets the asset pair of this book.
+    pub fn pair(&self) -> &AssetPair<'a> {
+        &self.pair
+    }
+
+    /// Gets the maximum number of levels per side.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Gets the best (highest) bid level.
+    pub fn best_bid(&self) -> Option<&Level> {
+        self.bids.values().next_back()
+    }
+
+    /// Gets the best (lowest) ask level.
+    pub fn best_ask(&self) -> Option<&Level> {
+        self.asks.values().next()
+    }
+
+    /// Gets the difference between the best ask and the best bid prices.
+    pub fn spread(&self) -> Option<Amount> {
+        let (bid, ask) = self.best_prices()?;
+        Some(ask - bid)
+    }
+
+    /// Gets the mid price between the best ask and the best bid.
+    pub fn mid(&self) -> Option<Amount> {
+        let (bid, ask) = self.best_prices()?;
+        Some((ask + bid) / Amount::from(2u8))
+    }
+
+    /// Gets the level of the given side at the given price.
+    pub fn level(&self, side: Side, price: &str) -> Option<&Level> {
+        self.side(side).get(&PriceKey::new(price))
+    }
+
+    /// Gets the bid levels sorted by descending price.
+    pub fn bids(&self) -> impl Iterator<Item = &Level> {
+        self.bids.values().rev()
+    }
+
+    /// Gets the ask levels sorted by ascending price.
+    pub fn asks(&self) -> impl Iterator<Item = &Level> {
+        self.asks.values()
+    }
+
+    /// Computes the Kraken CRC32 checksum of the current book.
+    pub fn checksum(&self) -> u32 {
+        let asks = self.asks().map(|l| (l.price.as_str(), l.volume.as_str()));
+        let bids = self.bids().map(|l| (l.price.as_str(), l.volume.as_str()));
+        book::checksum(asks, bids)
+    }
+
+    /// Gets the best bid and ask prices.
+    fn best_prices(&self) -> Option<(Amount, Amount)> {
+        let bid = self.best_bid()?.price.parse().ok()?;
+        let ask = self.best_ask()?.price.parse().ok()?;
+        Some((bid, ask))
+    }
+
+    /// Gets the levels of the given side.
+    fn side(&self, side: Side) -> &BTreeMap<PriceKey, Level> {
+        match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        }
+    }
+
+    /// Inserts, updates or removes (if the volume is zero) the given levels.
+    fn insert(&mut self, side: Side, levels: impl IntoIterator<Item = Level>) {
+        let book = match side {
+            Side::Bid => &mut self.bids,
+            Side::Ask => &mut self.asks,
+        };
+
+        for level in levels {
+            let key = PriceKey::new(&level.price);
+            if level.is_empty() {
+                book.remove(&key);
+            } else {
+                book.insert(key, level);
+            }
+        }
+    }
+
+    /// Removes the levels out of the subscribed depth.
+    fn truncate(&mut self) {
+        while self.asks.len() > self.depth {
+            self.asks.pop_last();
+        }
+        while self.bids.len() > self.depth {
+            self.bids.pop_first();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{amount::amount, Asset};
+    use anyhow::Result;
+
+    const SNAPSHOT: &str = r#"[
+        0,
+        {
+            "as": [
+                ["5541.30000", "2.50700000", "1534614248.123678"],
+                ["5541.80000", "0.33000000", "1534614098.345543"],
+                ["5542.70000", "0.64700000", "1534614244.654432"]
+            ],
+            "bs": [
+                ["5541.20000", "1.52900000", "1534614248.765567"],
+                ["5539.90000", "0.30000000", "1534614241.769870"],
+                ["5539.50000", "5.00000000", "1534613831.243486"]
+            ]
+        },
+        "book-3",
+        "XBT/USD"
+    ]"#;
+
+    fn xbt_usd() -> AssetPair<'static> {
+        Asset::new("XBT").pair("USD")
+    }
+
+    fn update(asks: &str, bids: &str, checksum: Option<u32>) -> String {
+        let checksum = checksum
+            .map(|c| format!(r#", "c": "{}""#, c))
+            .unwrap_or_default();
+        format!(
+            r#"[0, {{"a": {}}}, {{"b": {}{}}}, "book-3", "XBT/USD"]"#,
+            asks, bids, checksum
+        )
+    }
+
+    #[test]
+    fn parse_update() -> Result<()> {
+        let snapshot: BookUpdate = SNAPSHOT.parse()?;
+        assert!(snapshot.snapshot);
+        assert_eq!(snapshot.depth(), Some(3));
+        assert_eq!(snapshot.pair, "XBT/USD");
+        assert_eq!(snapshot.asks.len(), 3);
+        assert_eq!(snapshot.bids.len(), 3);
+        assert_eq!(snapshot.checksum, None);
+
+        let msg = update(
+            r#"[["5541.30000", "2.50700000", "1534614248.456738"]]"#,
+            r#"[["5541.20000", "1.52900000", "1534614248.765567", "r"]]"#,
+            Some(974942666),
+        );
+        let update: BookUpdate = msg.parse()?;
+        assert!(!update.snapshot);
+        assert_eq!(update.asks.len(), 1);
+        assert_eq!(update.bids.len(), 1);
+        assert_eq!(update.checksum, Some(974942666));
+
+        assert!("[0, {}]".parse::<BookUpdate>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn apply_updates() -> Result<()> {
+        let mut book = OrderBook::new(xbt_usd(), 3);
+        assert!(!book.is_synced());
+        book.apply(&SNAPSHOT.parse()?)?;
+        assert!(book.is_synced());
+
+        assert_eq!(
+            book.best_ask().map(|l| l.price.as_str()),
+            Some("5541.30000")
+        );
+        assert_eq!(
+            book.best_bid().map(|l| l.price.as_str()),
+            Some("5541.20000")
+        );
+        let close = |a: Option<Amount>, b: &str| match a {
+            Some(a) => (a - amount(b)).abs() < amount("0.000001"),
+            None => false,
+        };
+        assert!(close(book.spread(), "0.1"));
+        assert!(close(book.mid(), "5541.25"));
+
+        // remove the best ask and add a new worse bid, which is truncated
+        let msg = update(
+            r#"[["5541.30000", "0.00000000", "1534614335.345903"]]"#,
+            r#"[["5530.00000", "1.00000000", "1534614335.345903"]]"#,
+            None,
+        );
+        book.apply(&msg.parse()?)?;
+        assert_eq!(book.asks().count(), 2);
+        assert_eq!(book.bids().count(), 3);
+        assert_eq!(
+            book.best_ask().map(|l| l.price.as_str()),
+            Some("5541.80000")
+        );
+        assert!(book.level(Side::Bid, "5530.0").is_none());
+
+        // add a better bid, pushing the worst one out of the book
+        let msg = update(
+            "[]",
+            r#"[["5541.50000", "0.10000000", "1534614335.345903"]]"#,
+            None,
+        );
+        book.apply(&msg.parse()?)?;
+        let bids: Vec<&str> = book.bids().map(|l| l.price.as_str()).collect();
+        assert_eq!(bids, vec!["5541.50000", "5541.20000", "5539.90000"]);
+        assert_eq!(
+            book.level(Side::Bid, "5541.5").map(|l| l.volume.as_str()),
+            Some("0.10000000")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn checksum_resync() -> Result<()> {
+        let snapshot: BookUpdate = SNAPSHOT.parse()?;
+        let mut book = OrderBook::with_snapshot(
+            xbt_usd(),
+            3,
+            Depth {
+                asks: snapshot.asks.clone(),
+                bids: snapshot.bids.clone(),
+            },
+        );
+
+        let mut expected = book.clone();
+        let ask = r#"[["5542.70000", "1.00000000", "1534614335.345903"]]"#;
+        expected.apply(&update(ask, "[]", None).parse()?)?;
+        let checksum = expected.checksum();
+
+        book.apply(&update(ask, "[]", Some(checksum)).parse()?)?;
+        assert_eq!(book, expected);
+
+        let err = book
+            .apply(&update("[]", "[]", Some(checksum + 1)).parse()?)
+            .unwrap_err();
+        assert_eq!(
+            err,
+            Error::ChecksumMismatch {
+                expected: checksum + 1,
+                computed: checksum,
+            }
+        );
+        assert!(!book.is_synced());
+        assert!(book.best_ask().is_none());
+
+        // updates are ignored until a new snapshot is received
+        book.apply(&update(ask, "[]", None).parse()?)?;
+        assert!(book.best_ask().is_none());
+        book.apply(&snapshot)?;
+        assert!(book.is_synced());
+        assert_eq!(book.asks().count(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_other_books() -> Result<()> {
+        let mut book = OrderBook::new(xbt_usd(), 3);
+        book.apply(&SNAPSHOT.parse()?)?;
+
+        let other_pair = SNAPSHOT.replace("XBT/USD", "ETH/USD");
+        assert!(matches!(
+            book.apply(&other_pair.parse()?),
+            Err(Error::InvalidAssetPair(_))
+        ));
+        let other_depth = SNAPSHOT.replace("book-3", "book-10");
+        assert!(matches!(
+            book.apply(&other_depth.parse()?),
+            Err(Error::InvalidMessage(_))
+        ));
+        assert!(book.is_synced());
+        assert_eq!(book.asks().count(), 3);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,653 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+};
+
+use crate::{
+    amount::serde_amount,
+    book::{self, Depth, Level, PriceKey, Side},
+    registry::ALIASES,
+    Amount, Asset, AssetPair, Error, Result,
+};
+
+/// A Websockets `level3` channel message.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct L3Message {
+    /// The channel name (`level3`).
+    pub channel: String,
+    /// The message type, either `snapshot` or `update`.
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// The book snapshots or updates, one per symbol.
+    pub data: Vec<L3Data>,
+}
+
+impl L3Message {
+    /// Returns true only if this message contains book snapshots.
+    pub fn is_snapshot(&self) -> bool {
+        self.kind == "snapshot"
+    }
+}
+
+impl FromStr for L3Message {
+    type Err = Error;
+
+    fn from_str(message: &str) -> Result<Self> {
+        serde_json::from_str(message).map_err(Error::invalid_message)
+    }
+}
+
+/// The level 3 book snapshot or update of a single symbol.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct L3Data {
+    /// The Websockets symbol (e.g. `BTC/USD`).
+    pub symbol: String,
+    /// The checksum of the book after this snapshot or update is applied.
+    pub checksum: Option<u32>,
+    /// Bid orders events.
+    #[serde(default)]
+    pub bids: Vec<L3Entry>,
+    /// Ask orders events.
+    #[serde(default)]
+    pub asks: Vec<L3Entry>,
+}
+
+/// A single order entry of a level 3 book message.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct L3Entry {
+    /// The Kraken order ID.
+    pub order_id: String,
+    /// The order limit price.
+    #[serde(with = "serde_amount")]
+    pub limit_price: Amount,
+    /// The order remaining quantity.
+    #[serde(with = "serde_amount")]
+    pub order_qty: Amount,
+    /// The time of the order event.
+    pub timestamp: String,
+    /// The order event (not present in snapshots).
+    pub event: Option<L3Event>,
+}
+
+/// Level 3 order events.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    Hash,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum L3Event {
+    /// A new order entered the book.
+    Add,
+    /// The quantity of an existing order changed.
+    Modify,
+    /// The order left the book.
+    Delete,
+}
+
+/// An individual order of a level 3 book.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Hash)]
+pub struct L3Order {
+    /// The Kraken order ID.
+    pub order_id: String,
+    /// The book side of the order.
+    pub side: Side,
+    /// The order limit price, formatted with the pair price precision.
+    pub price: String,
+    /// The order remaining quantity, formatted with the pair volume precision.
+    pub volume: String,
+    /// The time of the last order event.
+    pub timestamp: String,
+}
+
+/// The position of an order in its price level queue.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Hash)]
+pub struct QueuePosition {
+    /// Number of orders ahead in the queue.
+    pub orders_ahead: usize,
+    /// Total volume of the orders ahead in the queue.
+    pub volume_ahead: String,
+}
+
+/// Local level 3 order book of a single asset pair, tracking each individual
+/// order by ID.
+///
+/// Prices and quantities are received as numbers and formatted with the pair
+/// precisions (see `pair_decimals` and `lot_decimals` of the `asset_pairs`
+/// API), which are required to verify the book checksum. On checksum mismatch
+/// the book is cleared and will ignore further updates until a new snapshot
+/// is applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct L3OrderBook<'a> {
+    /// The asset pair of this book.
+    pair: AssetPair<'a>,
+    /// The subscribed number of price levels per side.
+    depth: usize,
+    /// Number of decimals of the prices.
+    price_decimals: usize,
+    /// Number of decimals of the volumes.
+    volume_decimals: usize,
+    /// Orders by ID.
+    orders: HashMap<String, L3Order>,
+    /// Ask orders IDs queues by price.
+    asks: BTreeMap<PriceKey, Vec<String>>,
+    /// Bid orders IDs queues by price.
+    bids: BTreeMap<PriceKey, Vec<String>>,
+    /// Whether the book reflects the server state.
+    synced: bool,
+}
+
+impl<'a> L3OrderBook<'a> {
+    /// Constructs a new empty book that keeps at most `depth` price levels per
+    /// side.
+    pub fn new(
+        pair: AssetPair<'a>,
+        depth: usize,
+        price_decimals: usize,
+        volume_decimals: usize,
+    ) -> Self {
+        Self {
+            pair,
+            depth,
+            price_decimals,
+            volume_decimals,
+            orders: HashMap::new(),
+            asks: BTreeMap::new(),
+            bids: BTreeMap::new(),
+            synced: false,
+        }
+    }
+
+    /// Constructs the message to subscribe to the `level3` channel of the
+    /// given pairs, authenticated with the token returned by the
+    /// `get_websockets_token` API.
+    pub fn subscribe_message(
+        pairs: &[AssetPair],
+        depth: usize,
+        token: &str,
+    ) -> Value {
+        let symbols: Vec<String> = pairs.iter().map(symbol).collect();
+        json!({
+            "method": "subscribe",
+            "params": {
+                "channel": "level3",
+                "symbol": symbols,
+                "depth": depth,
+                "snapshot": true,
+                "token": token,
+            }
+        })
+    }
+
+    /// Applies the given snapshot or update of this book symbol.
+    ///
+    /// Returns an error if the data is of another symbol, leaving the book
+    /// untouched. Returns an error if an order quantity is invalid or the book
+    /// checksum does not match the one of the update, in which case the book
+    /// is cleared and must be resynchronized.
+    pub fn apply(&mut self, snapshot: bool, data: &L3Data) -> Result<()> {
+        if data.symbol != symbol(&self.pair) {
+            return Err(Error::invalid_pair(format!(
+                "data of {} applied to the {} book",
+                data.symbol,
+                symbol(&self.pair)
+            )));
+        }
+
+        if snapshot {
+            self.clear();
+            self.synced = true;
+        } else if !self.synced {
+            log::debug!("Ignoring update of unsynchronized {} book", self.pair);
+            return Ok(());
+        }
+
+        for (side, entries) in
+            &[(Side::Bid, &data.bids), (Side::Ask, &data.asks)]
+        {
+            for entry in entries.iter() {
+                if let Err(e) = self.apply_entry(*side, entry) {
+                    self.clear();
+                    self.synced = false;
+                    return Err(e);
+                }
+            }
+        }
+        self.truncate();
+
+        if let Some(expected) = data.checksum {
+            let computed = self.checksum();
+            if computed != expected {
+                self.clear();
+                self.synced = false;
+                return Err(Error::ChecksumMismatch { expected, computed });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns true only if the book is synchronized with the server.
+    pub fn is_synced(&self) -> bool {
+        self.synced
+    }
+
+    /// Gets the asset pair of this book.
+    pub fn pair(&self) -> &AssetPair<'a> {
+        &self.pair
+    }
+
+    /// Gets the order with the given ID.
+    pub fn order(&self, order_id: &str) -> Option<&L3Order> {
+        self.orders.get(order_id)
+    }
+
+    /// Gets the orders of the given side at the given price, in queue order.
+    pub fn orders_at(
+        &self,
+        side: Side,
+        price: &str,
+    ) -> impl Iterator<Item = &L3Order> {
+        self.side(side)
+            .get(&PriceKey::new(price))
+            .into_iter()
+            .flatten()
+            .filter_map(move |id| self.orders.get(id))
+    }
+
+    /// Gets the number of orders and the volume ahead of the given order in
+    /// its price level queue.
+    pub fn queue_position(&self, order_id: &str) -> Option<QueuePosition> {
+        let order = self.orders.get(order_id)?;
+        let queue = self.side(order.side).get(&PriceKey::new(&order.price))?;
+        let orders_ahead = queue.iter().position(|id| id == order_id)?;
+
+        let volume_ahead = queue[..orders_ahead]
+            .iter()
+            .filter_map(|id| self.orders.get(id))
+            .map(|o| units(&o.volume))
+            .sum::<Result<u128>>()
+            .ok()?;
+
+        Some(QueuePosition {
+            orders_ahead,
+            volume_ahead: format_units(volume_ahead, self.volume_decimals),
+        })
+    }
+
+    /// Aggregates the orders into the level 2 book with at most `levels`
+    /// price levels per side.
+    pub fn to_depth(&self, levels: usize) -> Depth {
+        Depth {
+            asks: self.levels(Side::Ask).take(levels).collect(),
+            bids: self.levels(Side::Bid).take(levels).collect(),
+        }
+    }
+
+    /// Computes the Kraken CRC32 checksum of the current book.
+    pub fn checksum(&self) -> u32 {
+        let orders = |side| {
+            self.queues(side)
+                .take(book::CHECKSUM_LEVELS)
+                .flatten()
+                .filter_map(move |id| self.orders.get(id))
+                .map(|o| (o.price.as_str(), o.volume.as_str()))
+        };
+        book::checksum(orders(Side::Ask), orders(Side::Bid))
+    }
+
+    /// Gets the aggregated price levels of the given side, best first.
+    fn levels(&self, side: Side) -> impl Iterator<Item = Level> + '_ {
+        self.queues(side).filter_map(move |queue| {
+            let orders: Vec<&L3Order> =
+                queue.iter().filter_map(|id| self.orders.get(id)).collect();
+            let first = orders.first()?;
+            let volume = orders
+                .iter()
+                .map(|o| units(&o.volume))
+                .sum::<Result<u128>>()
+                .ok()?;
+            let timestamp = orders.iter().map(|o| &o.timestamp).max()?;
+
+            Some(Level {
+                price: first.price.clone(),
+                volume: format_units(volume, self.volume_decimals),
+                timestamp: timestamp.clone(),
+            })
+        })
+    }
+
+    /// Gets the orders IDs queues of the given side, best price first.
+    fn queues(
+        &self,
+        side: Side,
+    ) -> Box<dyn Iterator<Item = &Vec<String>> + '_> {
+        match side {
+            Side::Bid => Box::new(self.bids.values().rev()),
+            Side::Ask => Box::new(self.asks.values()),
+        }
+    }
+
+    /// Gets the orders IDs queues of the given side by price.
+    fn side(&self, side: Side) -> &BTreeMap<PriceKey, Vec<String>> {
+        match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        }
+    }
+
+    /// Applies a single order event to the book, failing if the order
+    /// quantity is not a valid volume.
+    fn apply_entry(&mut self, side: Side, entry: &L3Entry) -> Result<()> {
+        let price = format!("{:.*}", self.price_decimals, entry.limit_price);
+        let volume = format!("{:.*}", self.volume_decimals, entry.order_qty);
+        // the volumes of the book orders are always valid units
+        units(&volume)?;
+
+        match entry.event.unwrap_or(L3Event::Add) {
+            L3Event::Add => {
+                // a duplicate add replaces the order, at the back of its queue
+                if self.orders.contains_key(&entry.order_id) {
+                    log::debug!("Replacing existing order {}", entry.order_id);
+                    self.remove(&entry.order_id);
+                }
+
+                let queues = match side {
+                    Side::Bid => &mut self.bids,
+                    Side::Ask => &mut self.asks,
+                };
+                queues
+                    .entry(PriceKey::new(&price))
+                    .or_default()
+                    .push(entry.order_id.clone());
+
+                let order = L3Order {
+                    order_id: entry.order_id.clone(),
+                    side,
+                    price,
+                    volume,
+                    timestamp: entry.timestamp.clone(),
+                };
+                self.orders.insert(entry.order_id.clone(), order);
+            }
+            L3Event::Modify => {
+                if let Some(order) = self.orders.get_mut(&entry.order_id) {
+                    order.volume = volume;
+                    order.timestamp = entry.timestamp.clone();
+                } else {
+                    log::debug!(
+                        "Cannot modify unknown order {}",
+                        entry.order_id
+                    );
+                }
+            }
+            L3Event::Delete => self.remove(&entry.order_id),
+        }
+
+        Ok(())
+    }
+
+    /// Removes the order with the given ID from the book.
+    fn remove(&mut self, order_id: &str) {
+        let order = match self.orders.remove(order_id) {
+            Some(order) => order,
+            None => return,
+        };
+
+        let queues = match order.side {
+            Side::Bid => &mut self.bids,
+            Side::Ask => &mut self.asks,
+        };
+        let key = PriceKey::new(&order.price);
+        if let Some(queue) = queues.get_mut(&key) {
+            queue.retain(|id| id != order_id);
+            if queue.is_empty() {
+                queues.remove(&key);
+            }
+        }
+    }
+
+    /// Removes the price levels out of the subscribed depth.
+    fn truncate(&mut self) {
+        while self.asks.len() > self.depth {
+            for id in self.asks.pop_last().map(|(_, q)| q).unwrap_or_default() {
+                self.orders.remove(&id);
+            }
+        }
+        while self.bids.len() > self.depth {
+            for id in self.bids.pop_first().map(|(_, q)| q).unwrap_or_default()
+            {
+                self.orders.remove(&id);
+            }
+        }
+    }
+
+    /// Removes all the orders from the book.
+    fn clear(&mut self) {
+        self.orders.clear();
+        self.asks.clear();
+        self.bids.clear();
+    }
+}
+
+/// Gets the Websockets v2 symbol of the given pair, with the common asset
+/// names (e.g. `BTC/USD` for `XBT/USD`).
+fn symbol(pair: &AssetPair) -> String {
+    let common = |asset: &Asset| {
+        let asset = asset.to_string();
+        match ALIASES.iter().find(|(_, altname)| *altname == asset) {
+            Some((alias, _)) => alias.to_string(),
+            None => asset,
+        }
+    };
+    format!("{}/{}", common(&pair.base), common(&pair.quote))
+}
+
+/// Converts a fixed precision decimal string into integer units.
+fn units(value: &str) -> Result<u128> {
+    value.replace('.', "").parse().map_err(|_| {
+        Error::invalid_message(format!("invalid order quantity {}", value))
+    })
+}
+
+/// Formats integer units as a decimal string with the given precision.
+fn format_units(units: u128, decimals: usize) -> String {
+    let digits = format!("{:0>width$}", units, width = decimals + 1);
+    if decimals == 0 {
+        return digits;
+    }
+    let (int, frac) = digits.split_at(digits.len() - decimals);
+    format!("{}.{}", int, frac)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Asset;
+    use anyhow::Result;
+
+    const SNAPSHOT: &str = r#"{
+        "channel": "level3",
+        "type": "snapshot",
+        "data": [{
+            "symbol": "BTC/USD",
+            "bids": [
+                {"order_id": "O1", "limit_price": 100.1, "order_qty": 1.5, "timestamp": "2023-10-06T17:35:00.000Z"},
+                {"order_id": "O2", "limit_price": 100.1, "order_qty": 0.25, "timestamp": "2023-10-06T17:35:01.000Z"},
+                {"order_id": "O3", "limit_price": 100.0, "order_qty": 2, "timestamp": "2023-10-06T17:35:02.000Z"}
+            ],
+            "asks": [
+                {"order_id": "O4", "limit_price": 100.5, "order_qty": 1, "timestamp": "2023-10-06T17:35:03.000Z"}
+            ]
+        }]
+    }"#;
+
+    fn book() -> Result<L3OrderBook<'static>> {
+        let pair = Asset::new("XBT").pair("USD");
+        let mut book = L3OrderBook::new(pair, 10, 1, 8);
+        let msg: L3Message = SNAPSHOT.parse()?;
+        assert!(msg.is_snapshot());
+        book.apply(true, &msg.data[0])?;
+        Ok(book)
+    }
+
+    fn update(bids: &str, checksum: Option<u32>) -> Result<L3Data> {
+        let checksum = checksum.map(|c| c.to_string());
+        let msg = format!(
+            r#"{{"channel": "level3", "type": "update", "data": [
+                {{"symbol": "BTC/USD", "checksum": {}, "bids": {}}}
+            ]}}"#,
+            checksum.as_deref().unwrap_or("null"),
+            bids
+        );
+        let mut msg: L3Message = msg.parse()?;
+        Ok(msg.data.remove(0))
+    }
+
+    #[test]
+    fn format_decimals() {
+        assert_eq!(units("1.50000000"), Ok(150000000));
+        assert!(units("-1.00000000").is_err());
+        assert!(units("NaN").is_err());
+        assert_eq!(format_units(175000000, 8), "1.75000000");
+        assert_eq!(format_units(5, 3), "0.005");
+        assert_eq!(format_units(42, 0), "42");
+    }
+
+    #[test]
+    fn aggregate_depth() -> Result<()> {
+        let book = book()?;
+        let depth = book.to_depth(10);
+
+        let bids: Vec<(&str, &str)> = depth
+            .bids
+            .iter()
+            .map(|l| (l.price.as_str(), l.volume.as_str()))
+            .collect();
+        assert_eq!(
+            bids,
+            vec![("100.1", "1.75000000"), ("100.0", "2.00000000")]
+        );
+        assert_eq!(depth.asks.len(), 1);
+        assert_eq!(depth.bids[0].timestamp, "2023-10-06T17:35:01.000Z");
+
+        Ok(())
+    }
+
+    #[test]
+    fn order_events() -> Result<()> {
+        let mut book = book()?;
+
+        let position = book.queue_position("O2").expect("O2 not found");
+        assert_eq!(position.orders_ahead, 1);
+        assert_eq!(position.volume_ahead, "1.50000000");
+
+        let bids = r#"[
+            {"order_id": "O1", "limit_price": 100.1, "order_qty": 0.5, "timestamp": "t", "event": "modify"},
+            {"order_id": "O5", "limit_price": 100.1, "order_qty": 3, "timestamp": "t", "event": "add"},
+            {"order_id": "O3", "limit_price": 100.0, "order_qty": 2, "timestamp": "t", "event": "delete"}
+        ]"#;
+        book.apply(false, &update(bids, None)?)?;
+
+        assert!(book.order("O3").is_none());
+        assert_eq!(
+            book.order("O1").map(|o| o.volume.as_str()),
+            Some("0.50000000")
+        );
+        let ids: Vec<&str> = book
+            .orders_at(Side::Bid, "100.1")
+            .map(|o| o.order_id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["O1", "O2", "O5"]);
+
+        let position = book.queue_position("O5").expect("O5 not found");
+        assert_eq!(position.orders_ahead, 2);
+        assert_eq!(position.volume_ahead, "0.75000000");
+        assert_eq!(book.to_depth(10).bids.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_add() -> Result<()> {
+        let mut book = book()?;
+
+        let bids = r#"[
+            {"order_id": "O1", "limit_price": 100.1, "order_qty": 2, "timestamp": "t", "event": "add"}
+        ]"#;
+        book.apply(false, &update(bids, None)?)?;
+
+        let ids: Vec<&str> = book
+            .orders_at(Side::Bid, "100.1")
+            .map(|o| o.order_id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["O2", "O1"]);
+        let position = book.queue_position("O1").expect("O1 not found");
+        assert_eq!(position.orders_ahead, 1);
+        assert_eq!(position.volume_ahead, "0.25000000");
+
+        Ok(())
+    }
+
+    #[test]
+    fn checksum_resync() -> Result<()> {
+        let mut book = book()?;
+        let checksum = book.checksum();
+        book.apply(false, &update("[]", Some(checksum))?)?;
+        assert!(book.is_synced());
+
+        let err = book
+            .apply(false, &update("[]", Some(checksum ^ 1))?)
+            .unwrap_err();
+        assert!(matches!(err, Error::ChecksumMismatch { .. }));
+        assert!(!book.is_synced());
+        assert!(book.order("O1").is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_data() -> Result<()> {
+        let mut book = book()?;
+        let mut other = update("[]", None)?;
+        other.symbol = "ETH/USD".into();
+        assert!(matches!(
+            book.apply(false, &other),
+            Err(Error::InvalidAssetPair(_))
+        ));
+        assert!(book.is_synced());
+        assert!(book.order("O1").is_some());
+
+        let bids = r#"[
+            {"order_id": "O5", "limit_price": 100.1, "order_qty": -1, "timestamp": "t", "event": "add"}
+        ]"#;
+        assert!(matches!(
+            book.apply(false, &update(bids, None)?),
+            Err(Error::InvalidMessage(_))
+        ));
+        assert!(!book.is_synced());
+        assert!(book.order("O1").is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn subscribe_message() {
+        let pairs =
+            [Asset::new("XBT").pair("USD"), Asset::new("ETH").pair("EUR")];
+        let msg = L3OrderBook::subscribe_message(&pairs, 10, "<token>");
+        assert_eq!(msg["params"]["channel"], "level3");
+        assert_eq!(msg["params"]["symbol"][0], "BTC/USD");
+        assert_eq!(msg["params"]["symbol"][1], "ETH/EUR");
+        assert_eq!(msg["params"]["token"], "<token>");
+    }
+}
@@ -0,0 +1,446 @@
+//! Record and replay of the API interactions.
+//!
+//! A client configured with a recording `Cassette` stores every request it
+//! sends together with the response it receives, which can be saved to a
+//! JSON file. A client configured with a replaying `Cassette` loaded from
+//! that file serves the recorded responses back, without any network access,
+//! matching the requests on HTTP method, URI path and parameters.
+//!
+//! The `API-Key` and `API-Sign` headers, the `nonce` and the `otp` parameters
+//! are never recorded, while response fields (e.g. account balances) can be
+//! redacted before being recorded.
+//!
+//! ```no_run
+//! use akkorokamui::{api, blocking::Client, cassette::Cassette, Credentials, ResponseValue};
+//! use anyhow::Result;
+//!
+//! fn main() -> Result<()> {
+//!     let credentials = Credentials::read("kraken.key")?;
+//!     let user_agent = "<product>/<product-version>";
+//!
+//!     // record the real payloads once
+//!     let cassette = Cassette::record("balance.json").redact_amounts("Balance");
+//!     let client = Client::with_credentials(user_agent, credentials)?
+//!         .with_cassette(cassette.clone());
+//!     let _: ResponseValue = client.send(api::private::balance())?;
+//!     cassette.save()?;
+//!
+//!     // and replay them offline
+//!     let cassette = Cassette::replay("balance.json")?;
+//!     let client = Client::new(user_agent)?.with_cassette(cassette);
+//!     let resp: ResponseValue = client.send(api::private::balance())?;
+//!     println!("{:?}", resp);
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::{Api, Error, RawResponse, Result};
+
+/// Parameters that are never recorded nor matched.
+const VOLATILE_PARAMS: &[&str] = &["nonce", "otp"];
+
+/// Replacement of the redacted fields.
+const REDACTED: &str = "<redacted>";
+
+/// Recorded request and response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Interaction {
+    /// The HTTP method (`GET` or `POST`).
+    pub method: String,
+    /// The URI path (e.g. `/0/public/Time`).
+    pub path: String,
+    /// The request parameters, except the volatile ones.
+    pub params: BTreeMap<String, String>,
+    /// The response HTTP status code.
+    pub status_code: u16,
+    /// The response body, if JSON.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub json: Option<Value>,
+    /// The response body encoded as base64, if not JSON.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw: Option<String>,
+}
+
+impl Interaction {
+    /// Constructs the request part of the interaction of the given API.
+    fn request(api: &Api) -> Self {
+        let params = api
+            .inner
+            .params
+            .iter()
+            .filter(|(k, _)| !VOLATILE_PARAMS.contains(&k.as_str()))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        Self {
+            method: if api.is_public() { "GET" } else { "POST" }.into(),
+            path: api.inner.uri_path(),
+            params,
+            status_code: 0,
+            json: None,
+            raw: None,
+        }
+    }
+
+    /// Returns true only if the given interaction has the same request.
+    fn matches(&self, other: &Self) -> bool {
+        self.method == other.method
+            && self.path == other.path
+            && self.params == other.params
+    }
+
+    /// Gets the API method name (e.g. `Time`).
+    fn api_method(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or_default()
+    }
+
+    /// Gets the recorded response.
+    fn response(&self) -> Result<RawResponse> {
+        let body = match (&self.json, &self.raw) {
+            (Some(json), _) => json.to_string().into_bytes(),
+            (None, Some(raw)) => {
+                base64::decode(raw).map_err(Error::invalid_message)?
+            }
+            (None, None) => Vec::new(),
+        };
+        Ok(RawResponse {
+            status_code: self.status_code,
+            headers: HeaderMap::new(),
+            body,
+        })
+    }
+}
+
+/// Cassette file content.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Tape {
+    interactions: Vec<Interaction>,
+}
+
+/// Cassette mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Requests are sent and recorded with their responses.
+    Record,
+    /// Requests are answered with the recorded responses.
+    Replay,
+}
+
+/// State shared by all the clones of a cassette.
+#[derive(Debug, Default)]
+struct State {
+    /// The recorded interactions.
+    interactions: Vec<Interaction>,
+    /// Whether each interaction has already been replayed.
+    replayed: Vec<bool>,
+}
+
+/// Recorded interactions, shared by all its clones.
+#[derive(Debug, Clone)]
+pub struct Cassette {
+    /// The cassette file.
+    path: PathBuf,
+    /// The cassette mode.
+    mode: Mode,
+    /// The response fields to redact.
+    redacted_fields: HashSet<String>,
+    /// The API methods whose response amounts are redacted.
+    redacted_amounts: HashSet<String>,
+    /// The interactions.
+    state: Arc<Mutex<State>>,
+}
+
+impl Cassette {
+    /// Constructs a new empty cassette recording to the given file.
+    pub fn record(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            mode: Mode::Record,
+            redacted_fields: HashSet::new(),
+            redacted_amounts: HashSet::new(),
+            state: Arc::new(Mutex::new(State::default())),
+        }
+    }
+
+    /// Loads the cassette from the given file to replay it.
+    pub fn replay(path: impl AsRef<Path>) -> Result<Self> {
+        let content =
+            fs::read_to_string(path.as_ref()).map_err(Error::internal)?;
+        let tape: Tape =
+            serde_json::from_str(&content).map_err(Error::invalid_message)?;
+
+        let cassette = Self {
+            mode: Mode::Replay,
+            ..Self::record(path)
+        };
+        {
+            let mut state = cassette.lock();
+            state.replayed = vec![false; tape.interactions.len()];
+            state.interactions = tape.interactions;
+        }
+        Ok(cassette)
+    }
+
+    /// Redacts the given field, at any depth, of all the recorded responses.
+    pub fn redact_field(mut self, field: impl Into<String>) -> Self {
+        self.redacted_fields.insert(field.into());
+        self
+    }
+
+    /// Redacts all the amounts (numbers and decimal strings, replaced by
+    /// zero) of the recorded responses of the given API method (e.g.
+    /// `Balance`), keeping the payload shape. The time and ID fields (e.g.
+    /// `opentm`, `unixtime`, `txid` or `userref`) are not redacted.
+    pub fn redact_amounts(mut self, api_method: impl Into<String>) -> Self {
+        self.redacted_amounts.insert(api_method.into());
+        self
+    }
+
+    /// Gets the cassette mode.
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Gets all the interactions.
+    pub fn interactions(&self) -> Vec<Interaction> {
+        self.lock().interactions.clone()
+    }
+
+    /// Saves the interactions to the cassette file.
+    pub fn save(&self) -> Result<()> {
+        let tape = Tape {
+            interactions: self.interactions(),
+        };
+        let content =
+            serde_json::to_string_pretty(&tape).map_err(Error::internal)?;
+        fs::write(&self.path, content).map_err(Error::internal)
+    }
+
+    /// Gets the recorded response of the given API, if replaying.
+    ///
+    /// Matching interactions are replayed in order, and the last one is
+    /// repeated for all the following requests.
+    pub(crate) fn play(&self, api: &Api) -> Result<Option<RawResponse>> {
+        if self.mode != Mode::Replay {
+            return Ok(None);
+        }
+
+        let request = Interaction::request(api);
+        let mut state = self.lock();
+        let State {
+            interactions,
+            replayed,
+        } = &mut *state;
+
+        let mut matching = interactions
+            .iter()
+            .zip(replayed.iter_mut())
+            .filter(|(i, _)| i.matches(&request))
+            .peekable();
+        let mut last = None;
+        while let Some((interaction, replayed)) = matching.next() {
+            if !*replayed || matching.peek().is_none() {
+                *replayed = true;
+                last = Some(interaction);
+                break;
+            }
+        }
+
+        match last {
+            Some(interaction) => interaction.response().map(Some),
+            None => Err(Error::Request {
+                err: format!("no recorded interaction for {}", api),
+                status: None,
+            }),
+        }
+    }
+
+    /// Records the given API and its response, if recording.
+    pub(crate) fn tape(&self, api: &Api, response: &RawResponse) {
+        if self.mode != Mode::Record {
+            return;
+        }
+
+        let mut interaction = Interaction::request(api);
+        interaction.status_code = response.status_code;
+        match serde_json::from_slice::<Value>(&response.body) {
+            Ok(mut json) => {
+                let amounts =
+                    self.redacted_amounts.contains(interaction.api_method());
+                self.redact(&mut json, amounts);
+                interaction.json = Some(json);
+            }
+            Err(_) => interaction.raw = Some(base64::encode(&response.body)),
+        }
+
+        let mut state = self.lock();
+        state.interactions.push(interaction);
+        state.replayed.push(false);
+    }
+
+    /// Redacts the given JSON value.
+    fn redact(&self, value: &mut Value, amounts: bool) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map.iter_mut() {
+                    if self.redacted_fields.contains(key) {
+                        *value = Value::from(REDACTED);
+                    } else {
+                        self.redact(value, amounts && !is_time_or_id(key));
+                    }
+                }
+            }
+            Value::Array(values) => {
+                values.iter_mut().for_each(|v| self.redact(v, amounts))
+            }
+            Value::Number(_) if amounts => *value = Value::from(0),
+            Value::String(s) if amounts && is_decimal(s) => {
+                *value = Value::from("0")
+            }
+            _ => (),
+        }
+    }
+
+    /// Locks the shared state.
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Whether the given response field is a time or an identifier.
+fn is_time_or_id(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    key.ends_with("tm")
+        || key.ends_with("time")
+        || key.ends_with("id")
+        || key == "userref"
+        || key == "last"
+}
+
+/// Whether the given string is a decimal number (e.g. `-0.5` or `100`).
+fn is_decimal(s: &str) -> bool {
+    let digits =
+        |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let s = s.strip_prefix('-').unwrap_or(s);
+    match s.split_once('.') {
+        Some((int, frac)) => digits(int) && digits(frac),
+        None => digits(s),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api;
+    use anyhow::Result;
+    use serde_json::json;
+    use std::env;
+    use uuid::Uuid;
+
+    fn response(body: Value) -> RawResponse {
+        RawResponse {
+            status_code: 200,
+            headers: HeaderMap::new(),
+            body: body.to_string().into_bytes(),
+        }
+    }
+
+    #[test]
+    fn record_and_replay() -> Result<()> {
+        let path = env::temp_dir().join(Uuid::new_v4().to_string());
+        let cassette = Cassette::record(&path)
+            .redact_field("refid")
+            .redact_amounts("Balance");
+        assert_eq!(cassette.mode(), Mode::Record);
+
+        let balance = api::private::balance().with("nonce", 1).into();
+        cassette.tape(
+            &balance,
+            &response(json!({"error": [], "result": {
+                "ZEUR": "100.5",
+                "XXBT": 1,
+                "XETH": "-2",
+                "NAN": "nan",
+                "INF": "inf",
+                "last": "1625918400123456789",
+                "orders": [{"txid": "123", "opentm": 1625918400.5}]
+            }})),
+        );
+        let ledgers = api::private::ledgers().with("ofs", 0).into();
+        cassette.tape(
+            &ledgers,
+            &response(
+                json!({"error": [], "result": {"refid": "R1", "count": 1}}),
+            ),
+        );
+        assert_eq!(cassette.play(&ledgers)?, None);
+        cassette.clone().save()?;
+
+        let interactions = cassette.interactions();
+        assert!(!interactions[0].params.contains_key("nonce"));
+        assert_eq!(
+            interactions[0].json,
+            Some(json!({"error": [], "result": {
+                "ZEUR": "0",
+                "XXBT": 0,
+                "XETH": "0",
+                "NAN": "nan",
+                "INF": "inf",
+                "last": "1625918400123456789",
+                "orders": [{"txid": "123", "opentm": 1625918400.5}]
+            }}))
+        );
+        assert_eq!(
+            interactions[1].json,
+            Some(
+                json!({"error": [], "result": {"refid": "<redacted>", "count": 1}})
+            )
+        );
+
+        let cassette = Cassette::replay(&path)?;
+        fs::remove_file(&path)?;
+
+        // the nonce is not matched
+        let balance = api::private::balance().with("nonce", 2).into();
+        let raw = cassette.play(&balance)?.expect("not replaying");
+        assert_eq!(raw.json::<Value>()?["result"]["ZEUR"], "0");
+
+        let ledgers: Api = api::private::ledgers().with("ofs", 50).into();
+        assert!(cassette.play(&ledgers).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn replay_in_order() -> Result<()> {
+        let cassette = Cassette::record("unused.json");
+        let time: Api = api::public::time().into();
+        cassette.tape(&time, &response(json!({"result": {"unixtime": 1}})));
+        cassette.tape(&time, &response(json!({"result": {"unixtime": 2}})));
+
+        let cassette = Cassette {
+            mode: Mode::Replay,
+            ..cassette
+        };
+        let unixtime = |raw: Option<RawResponse>| -> Result<Value> {
+            let json: Value = raw.expect("not replaying").json()?;
+            Ok(json["result"]["unixtime"].clone())
+        };
+        assert_eq!(unixtime(cassette.play(&time)?)?, 1);
+        assert_eq!(unixtime(cassette.play(&time)?)?, 2);
+        assert_eq!(unixtime(cassette.play(&time)?)?, 2);
+
+        Ok(())
+    }
+}
@@ -3,10 +3,22 @@ use reqwest::header::{HeaderMap, HeaderValue};
 use sha2::{Digest, Sha256, Sha512};
 use std::{
     fmt,
-    time::{SystemTime, UNIX_EPOCH},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use crate::{api::Body, Api, Credentials, Error, Result};
+use crate::{
+    api::Body,
+    cassette::Cassette,
+    clock::ClockSync,
+    keys::KeyRouter,
+    risk::{Reservation, RiskGuard},
+    sim::SimExchange,
+    Api, Credentials, Error, RawResponse, Result,
+};
 
 pub use r#async::Client;
 
@@ -25,6 +37,20 @@ pub struct HttpClient<T> {
     client: T,
     /// The credentials to use for private APIs.
     credentials: Option<Credentials>,
+    /// The keys selected by permission for private APIs, if any.
+    keys: Option<KeyRouter>,
+    /// The server-corrected clock used for nonces, if any.
+    clock: Option<ClockSync>,
+    /// The last nonce of the credentials, shared by the cloned clients.
+    last_nonce: Arc<AtomicU64>,
+    /// The base URL replacing the Kraken domain, if any.
+    base_url: Option<String>,
+    /// The cassette recording or replaying the requests, if any.
+    cassette: Option<Cassette>,
+    /// The simulated exchange answering the requests, if any.
+    sim: Option<SimExchange>,
+    /// The pre-trade checks of the order placements, if any.
+    guard: Option<RiskGuard>,
     /// The User-Agent header used for each request.
     user_agent: HeaderValue,
 }
@@ -41,58 +67,200 @@ impl<T> fmt::Display for HttpClient<T> {
 }
 
 impl<T> HttpClient<T> {
+    /// Computes the nonces from the given server-corrected clock instead of
+    /// the local clock.
+    pub fn with_clock_sync(mut self, clock: ClockSync) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Sends the requests to the given base URL instead of the Kraken domain
+    /// (e.g. a proxy or the `testkit` mock server).
+    pub fn with_base_url(mut self, base_url: impl fmt::Display) -> Self {
+        let base_url = base_url.to_string();
+        self.base_url = Some(base_url.trim_end_matches('/').to_string());
+        self
+    }
+
+    /// Records the requests and their responses to the given cassette, or
+    /// replays them from it without sending any request.
+    pub fn with_cassette(mut self, cassette: Cassette) -> Self {
+        self.cassette = Some(cassette);
+        self
+    }
+
+    /// Sends the private requests to the given simulated exchange instead of
+    /// the Kraken servers (i.e. paper trading). The public requests not
+    /// simulated are still sent to the Kraken servers (or replayed from the
+    /// cassette).
+    pub fn with_sim(mut self, sim: SimExchange) -> Self {
+        self.sim = Some(sim);
+        self
+    }
+
+    /// Signs each private request with the key of the given router granting
+    /// the permission of the method, instead of the client credentials.
+    ///
+    /// The requests are also throttled by the API rate counter of each key,
+    /// which the clients without router do not keep.
+    pub fn with_keys(mut self, keys: KeyRouter) -> Self {
+        self.keys = Some(keys);
+        self
+    }
+
+    /// Checks each order placement against the given risk guard before
+    /// signing and sending it.
+    pub fn with_risk_guard(mut self, guard: RiskGuard) -> Self {
+        self.guard = Some(guard);
+        self
+    }
+
+    /// Checks the given API against the risk guard, if any, counting its
+    /// orders until the response is settled.
+    fn check_risk(&self, api: &Api) -> Result<Reservation> {
+        match &self.guard {
+            Some(guard) => guard.check(api),
+            None => Ok(Reservation::default()),
+        }
+    }
+
+    /// Keeps the reserved orders accepted by the given response, if any,
+    /// releasing the rejected ones.
+    fn settle_risk(
+        &self,
+        reservation: Reservation,
+        response: Option<&RawResponse>,
+    ) {
+        if let Some(guard) = &self.guard {
+            guard.settle(reservation, response);
+        }
+    }
+
+    /// Gets the simulated or recorded response of the given API, if any.
+    fn replay(&self, api: &Api) -> Result<Option<RawResponse>> {
+        // the public APIs not simulated are sent as usual
+        if let Some(response) = self.sim.as_ref().and_then(|s| s.respond(api)) {
+            return Ok(Some(response));
+        }
+        match &self.cassette {
+            Some(cassette) => cassette.play(api),
+            None => Ok(None),
+        }
+    }
+
+    /// Records the given API and its response, if recording.
+    fn record(&self, api: Option<Api>, response: &RawResponse) {
+        if let (Some(cassette), Some(api)) = (&self.cassette, api) {
+            cassette.tape(&api, response);
+        }
+    }
+
+    /// Gets a copy of the given API to record, if recording.
+    fn to_record(&self, api: &Api) -> Option<Api> {
+        self.cassette.as_ref().map(|_| api.clone())
+    }
+
+    /// Sets the base URL of the given API, if any.
+    fn set_base_url(&self, api: &mut Api) {
+        if let Some(base_url) = &self.base_url {
+            api.inner.domain = base_url.clone();
+        }
+    }
+
+    /// Counts the given private request in the rate counter of its key,
+    /// returning how long to wait before sending it (never waiting without
+    /// key router).
+    fn rate_delay(&self, api: &Api) -> Result<Duration> {
+        match &self.keys {
+            Some(keys) => {
+                Ok(keys.route(&api.inner.method)?.acquire(&api.inner.method))
+            }
+            None => Ok(Duration::from_secs(0)),
+        }
+    }
+
     /// Builds the POST request headers and body.
     fn make_req_args(&self, api: Api) -> Result<(HeaderMap, String)> {
-        let nonce = self.nonce()?;
+        let mut nonce = self.nonce()?;
         let uri_path = api.inner.uri_path();
 
+        let credentials = match &self.keys {
+            Some(keys) => {
+                let key = keys.route(&api.inner.method)?;
+                nonce = key.nonce(nonce);
+                Some(key.credentials())
+            }
+            None => self.credentials.as_ref(),
+        };
+
         debug_assert!(!api.is_public());
         let body = Body::with_params(nonce, api.inner.params);
         let body = body.urlencode();
 
         let mut headers: HeaderMap = api.inner.headers;
-        if let Some(credentials) = &self.credentials {
-            let api_sign = self.api_sign(uri_path, nonce, &body)?;
-            headers.insert("API-Key", credentials.api_key().to_owned());
+        if let Some(credentials) = credentials {
+            let api_sign = api_sign(credentials, uri_path, nonce, &body)?;
+            headers.insert("API-Key", credentials.api_key_header()?);
             headers.insert("API-Sign", api_sign);
         }
 
         Ok((headers, body))
     }
 
-    /// Gets a new increasing nonce value.
+    /// Gets a new nonce, always greater than the previous ones, even when
+    /// several requests are signed within the same millisecond.
     fn nonce(&self) -> Result<u64> {
-        let elapsed = SystemTime::now().duration_since(UNIX_EPOCH)?;
-        Ok(elapsed.as_millis() as u64)
+        let now = match &self.clock {
+            Some(clock) => return Ok(clock.nonce()),
+            None => nonce()?,
+        };
+        let last = self
+            .last_nonce
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+                Some(now.max(last + 1))
+            })
+            .unwrap_or(now);
+        Ok(now.max(last + 1))
     }
+}
 
-    /// Generates the API-Sign header value.
-    fn api_sign(
-        &self,
-        uri_path: String,
-        nonce: u64,
-        body: &str,
-    ) -> Result<HeaderValue> {
-        if let Some(credentials) = &self.credentials {
-            type HmacSha512 = Hmac<Sha512>;
-
-            // API-Sign = Message signature using HMAC-SHA512 of (URI path +
-            // SHA256(nonce + POST data)) and base64 decoded secret API key
-            let sha_body = format!("{}{}", nonce, body);
-            let sha = Sha256::digest(sha_body.as_bytes());
-
-            let private_key = credentials.private_key()?;
-            let mut mac = HmacSha512::new_from_slice(&private_key)?;
-            let mut hmac_data = uri_path.into_bytes();
-            hmac_data.append(&mut sha.to_vec());
-            mac.update(&hmac_data);
-
-            let b64 = base64::encode(mac.finalize().into_bytes());
-            HeaderValue::from_str(&b64).map_err(Error::internal)
-        } else {
-            Err(Error::Unauthorized)
-        }
-    }
+/// Generates the API-Sign header value.
+fn api_sign(
+    credentials: &Credentials,
+    uri_path: String,
+    nonce: u64,
+    body: &str,
+) -> Result<HeaderValue> {
+    let b64 = sign(credentials, &uri_path, nonce, body)?;
+    HeaderValue::from_str(&b64).map_err(Error::internal)
+}
+
+/// Signs the given private request, returning the base64 API-Sign value.
+pub(crate) fn sign(
+    credentials: &Credentials,
+    uri_path: &str,
+    nonce: u64,
+    body: &str,
+) -> Result<String> {
+    type HmacSha512 = Hmac<Sha512>;
+
+    // API-Sign = Message signature using HMAC-SHA512 of (URI path +
+    // SHA256(nonce + POST data)) and base64 decoded secret API key
+    let sha_body = format!("{}{}", nonce, body);
+    let sha = Sha256::digest(sha_body.as_bytes());
+
+    let mut mac = HmacSha512::new_from_slice(credentials.private_key())?;
+    let mut hmac_data = uri_path.as_bytes().to_vec();
+    hmac_data.append(&mut sha.to_vec());
+    mac.update(&hmac_data);
+
+    Ok(base64::encode(mac.finalize().into_bytes()))
+}
+
+/// Gets a new increasing nonce value based on the current time.
+pub(crate) fn nonce() -> Result<u64> {
+    let elapsed = SystemTime::now().duration_since(UNIX_EPOCH)?;
+    Ok(elapsed.as_millis() as u64)
 }
 
 /// Gets the client User Agent.
@@ -1,10 +1,11 @@
 use reqwest::header::USER_AGENT;
 use serde::de::DeserializeOwned;
-use std::fmt;
+use std::{fmt, time::Duration};
 
 use crate::{
     client::{self, builder::ClientBuilder},
-    Api, Credentials, Response, Result,
+    keys::{Permission, PermissionReport, PermissionStatus},
+    Api, Credentials, Error, RawResponse, Response, Result,
 };
 
 /// The asynchronous HTTP client used to query the Kraken servers.
@@ -35,30 +36,93 @@ impl Client {
         &self,
         api: Req,
     ) -> Result<Response<Resp>> {
-        let mut api = api.into();
+        let raw = self.send_raw(api).await?;
+        let mut resp: Response<Resp> = raw.json()?;
+        resp.status_code = raw.status_code;
+        Ok(resp)
+    }
+
+    /// Sends the request to the Kraken servers and returns the raw response
+    /// status, headers and body, without decoding it.
+    pub async fn send_raw<Req: Into<Api>>(
+        &self,
+        api: Req,
+    ) -> Result<RawResponse> {
+        let api = api.into();
+        let reservation = self.check_risk(&api)?;
+        let response = self.dispatch(api).await;
+        self.settle_risk(reservation, response.as_ref().ok());
+        response
+    }
+
+    /// Sends the given API without the risk checks, unless replayed by the
+    /// simulated exchange or the cassette.
+    async fn dispatch(&self, api: Api) -> Result<RawResponse> {
+        if let Some(raw) = self.replay(&api)? {
+            return Ok(raw);
+        }
+
+        let recorded = self.to_record(&api);
+        let resp = self.request(api).await?;
+        let raw = RawResponse {
+            status_code: resp.status().as_u16(),
+            headers: resp.headers().clone(),
+            body: resp.bytes().await?.to_vec(),
+        };
+        self.record(recorded, &raw);
+        Ok(raw)
+    }
+
+    /// Sends the request to the Kraken servers and returns the raw response
+    /// body, without decoding it (e.g. for the binary export reports).
+    pub async fn send_bytes<Req: Into<Api>>(
+        &self,
+        api: Req,
+    ) -> Result<Vec<u8>> {
+        let raw = self.send_raw(api).await?;
+        if !raw.is_success() {
+            return Err(Error::Request {
+                err: format!("HTTP status {}", raw.status_code),
+                status: Some(raw.status_code),
+            });
+        }
+        Ok(raw.body)
+    }
+
+    /// Probes a side-effect-free call per permission (e.g. `add_order` with
+    /// `validate=true`) and reports which permissions are granted to the
+    /// client keys. The probes are not checked by the risk guard.
+    pub async fn check_permissions(&self) -> PermissionReport {
+        let mut report = PermissionReport::default();
+        for &permission in &Permission::ALL {
+            // the validate-only probes bypass the risk guard
+            let probe = permission.probe().into();
+            let result = self.dispatch(probe).await.and_then(|raw| raw.json());
+            report.insert(permission, PermissionStatus::of(result));
+        }
+        report
+    }
+
+    /// Sends the request using the given API.
+    async fn request(&self, mut api: Api) -> Result<reqwest::Response> {
+        self.set_base_url(&mut api);
         log::trace!("Sending request {}", api);
 
         let user_agent = self.user_agent.to_owned();
         api.inner.headers.append(USER_AGENT, user_agent);
 
-        let resp = if api.is_public() {
-            self.get(api).await?
+        if api.is_public() {
+            self.get(api).await
         } else {
-            self.post(api).await?
-        };
-
-        let status = resp.status();
-        let mut resp: Response<Resp> = resp.json().await?;
-        resp.status_code = status.as_u16();
-
-        Ok(resp)
+            self.post(api).await
+        }
     }
 
     /// Sends a GET request using the given API.
     async fn get(&self, api: Api) -> Result<reqwest::Response> {
         let resp = self
             .client
-            .get(&api.url())
+            .get(api.url())
             .headers(api.inner.headers)
             .send()
             .await?;
@@ -67,6 +131,11 @@ impl Client {
 
     /// Sends a POST request using the given API.
     async fn post(&self, api: Api) -> Result<reqwest::Response> {
+        let delay = self.rate_delay(&api)?;
+        if delay > Duration::from_secs(0) {
+            tokio::time::sleep(delay).await;
+        }
+
         let url = api.url();
         let (headers, body) = self.make_req_args(api)?;
         let resp = self
@@ -1,10 +1,11 @@
 use reqwest::{blocking, header::USER_AGENT};
 use serde::de::DeserializeOwned;
-use std::fmt;
+use std::{fmt, thread, time::Duration};
 
 use crate::{
     client::{self, builder::ClientBuilder},
-    Api, Credentials, Response, Result,
+    keys::{Permission, PermissionReport, PermissionStatus},
+    Api, Credentials, Error, RawResponse, Response, Result,
 };
 
 /// The blocking HTTP client used to query the Kraken servers.
@@ -35,30 +36,87 @@ impl Client {
         &self,
         api: Req,
     ) -> Result<Response<Resp>> {
-        let mut api = api.into();
+        let raw = self.send_raw(api)?;
+        let mut resp: Response<Resp> = raw.json()?;
+        resp.status_code = raw.status_code;
+        Ok(resp)
+    }
+
+    /// Sends the request to the Kraken servers and returns the raw response
+    /// status, headers and body, without decoding it.
+    pub fn send_raw<Req: Into<Api>>(&self, api: Req) -> Result<RawResponse> {
+        let api = api.into();
+        let reservation = self.check_risk(&api)?;
+        let response = self.dispatch(api);
+        self.settle_risk(reservation, response.as_ref().ok());
+        response
+    }
+
+    /// Sends the given API without the risk checks, unless replayed by the
+    /// simulated exchange or the cassette.
+    fn dispatch(&self, api: Api) -> Result<RawResponse> {
+        if let Some(raw) = self.replay(&api)? {
+            return Ok(raw);
+        }
+
+        let recorded = self.to_record(&api);
+        let resp = self.request(api)?;
+        let raw = RawResponse {
+            status_code: resp.status().as_u16(),
+            headers: resp.headers().clone(),
+            body: resp.bytes()?.to_vec(),
+        };
+        self.record(recorded, &raw);
+        Ok(raw)
+    }
+
+    /// Sends the request to the Kraken servers and returns the raw response
+    /// body, without decoding it (e.g. for the binary export reports).
+    pub fn send_bytes<Req: Into<Api>>(&self, api: Req) -> Result<Vec<u8>> {
+        let raw = self.send_raw(api)?;
+        if !raw.is_success() {
+            return Err(Error::Request {
+                err: format!("HTTP status {}", raw.status_code),
+                status: Some(raw.status_code),
+            });
+        }
+        Ok(raw.body)
+    }
+
+    /// Probes a side-effect-free call per permission (e.g. `add_order` with
+    /// `validate=true`) and reports which permissions are granted to the
+    /// client keys. The probes are not checked by the risk guard.
+    pub fn check_permissions(&self) -> PermissionReport {
+        let mut report = PermissionReport::default();
+        for &permission in &Permission::ALL {
+            // the validate-only probes bypass the risk guard
+            let probe = permission.probe().into();
+            let result = self.dispatch(probe).and_then(|raw| raw.json());
+            report.insert(permission, PermissionStatus::of(result));
+        }
+        report
+    }
+
+    /// Sends the request using the given API.
+    fn request(&self, mut api: Api) -> Result<blocking::Response> {
+        self.set_base_url(&mut api);
         log::trace!("Sending request {}", api);
 
         let user_agent = self.user_agent.to_owned();
         api.inner.headers.append(USER_AGENT, user_agent);
 
-        let resp = if api.is_public() {
-            self.get(api)?
+        if api.is_public() {
+            self.get(api)
         } else {
-            self.post(api)?
-        };
-
-        let status = resp.status();
-        let mut resp: Response<Resp> = resp.json()?;
-        resp.status_code = status.as_u16();
-
-        Ok(resp)
+            self.post(api)
+        }
     }
 
     /// Sends a GET request using the given API.
     fn get(&self, api: Api) -> Result<blocking::Response> {
         let resp = self
             .client
-            .get(&api.url())
+            .get(api.url())
             .headers(api.inner.headers)
             .send()?;
         Ok(resp)
@@ -66,6 +124,11 @@ impl Client {
 
     /// Sends a POST request using the given API.
     fn post(&self, api: Api) -> Result<blocking::Response> {
+        let delay = self.rate_delay(&api)?;
+        if delay > Duration::from_secs(0) {
+            thread::sleep(delay);
+        }
+
         let url = api.url();
         let (headers, body) = self.make_req_args(api)?;
         let resp = self.client.post(&url).headers(headers).body(body).send()?;
@@ -86,4 +149,111 @@ mod tests {
         assert!(client.credentials.is_none());
         Ok(())
     }
+
+    #[test]
+    fn increasing_nonces() -> Result<()> {
+        let client = Client::new(client::user_agent())?;
+        let clone = client.clone();
+        let mut last = 0;
+        for _ in 0..100 {
+            let nonce = client.nonce()?;
+            assert!(nonce > last);
+            last = clone.nonce()?;
+            assert!(last > nonce);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn route_keys() -> Result<()> {
+        use crate::{
+            api,
+            keys::{ApiKey, KeyRouter, Permission},
+        };
+
+        let key = |api_key, permission| -> Result<ApiKey> {
+            let credentials =
+                Credentials::new(api_key, "PHByaXZhdGVfa2V5Pg==")?;
+            Ok(ApiKey::new(credentials, vec![permission]))
+        };
+        let keys = KeyRouter::new()
+            .with_key(key("<funds>", Permission::QueryFunds)?)
+            .with_key(key("<withdraw>", Permission::WithdrawFunds)?);
+        let client = Client::new(client::user_agent())?.with_keys(keys);
+
+        let api_key = |api: Api| -> Result<String> {
+            let (headers, _) = client.make_req_args(api)?;
+            Ok(headers["API-Key"].to_str()?.to_string())
+        };
+        assert_eq!(api_key(api::private::balance().into())?, "<funds>");
+        assert_eq!(api_key(api::private::withdraw().into())?, "<withdraw>");
+        assert!(client
+            .make_req_args(api::private::add_order().into())
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn simulated_permissions() -> Result<()> {
+        use crate::{
+            keys::{Permission, PermissionStatus},
+            sim::SimExchange,
+        };
+
+        let client =
+            Client::new(client::user_agent())?.with_sim(SimExchange::new());
+        let report = client.check_permissions();
+        assert_eq!(
+            report.granted().collect::<Vec<_>>(),
+            vec![
+                Permission::QueryFunds,
+                Permission::QueryOpenOrders,
+                Permission::QueryClosedOrders,
+                Permission::ModifyOrders,
+                Permission::CancelOrders,
+            ]
+        );
+        // the methods not implemented by the simulated exchange
+        assert!(matches!(
+            report.status(Permission::WithdrawFunds),
+            Some(PermissionStatus::Unknown(_))
+        ));
+        assert!(matches!(
+            report.status(Permission::WebSocket),
+            Some(PermissionStatus::Unknown(_))
+        ));
+        Ok(())
+    }
+
+    #[cfg(feature = "testkit")]
+    #[test]
+    fn probes_bypass_risk_guard() -> Result<()> {
+        use crate::{
+            keys::{Permission, PermissionStatus},
+            risk::RiskGuard,
+            testkit::MockServer,
+        };
+        use serde_json::json;
+
+        let server = MockServer::start()?;
+        server.mock("AddOrder", json!({"descr": {"order": "buy"}}));
+        let guard = RiskGuard::new();
+        guard.kill();
+        let client = Client::new(client::user_agent())?
+            .with_base_url(server.url())
+            .with_risk_guard(guard);
+
+        let report = client.check_permissions();
+        assert_eq!(
+            report.status(Permission::ModifyOrders),
+            Some(&PermissionStatus::Granted)
+        );
+        assert!(server
+            .requests()
+            .iter()
+            .any(|r| r.api_method() == "AddOrder"));
+
+        Ok(())
+    }
 }
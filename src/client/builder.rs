@@ -36,6 +36,13 @@ impl ClientBuilder {
         Ok(blocking::Client {
             client: reqwest::blocking::Client::default(),
             credentials: self.credentials,
+            keys: None,
+            clock: None,
+            last_nonce: Default::default(),
+            base_url: None,
+            cassette: None,
+            sim: None,
+            guard: None,
             user_agent: self
                 .user_agent
                 .try_into()
@@ -48,6 +55,13 @@ impl ClientBuilder {
         Ok(Client {
             client: reqwest::Client::default(),
             credentials: self.credentials,
+            keys: None,
+            clock: None,
+            last_nonce: Default::default(),
+            base_url: None,
+            cassette: None,
+            sim: None,
+            guard: None,
             user_agent: self
                 .user_agent
                 .try_into()
@@ -0,0 +1,289 @@
+//! Server clock synchronization.
+//!
+//! The nonce of the private APIs and the absolute `starttm`/`expiretm`
+//! parameters are computed from the local clock. The `ClockSync` samples the
+//! `time` API several times, estimates the offset of the local clock from the
+//! server clock (using the sample with the lowest round trip time), and
+//! corrects the local time accordingly. A client configured with the
+//! `ClockSync` computes its nonces from the corrected time.
+//!
+//! ```no_run
+//! use akkorokamui::{blocking::Client, clock::ClockSync, Credentials};
+//! use anyhow::Result;
+//! use std::time::Duration;
+//!
+//! fn main() -> Result<()> {
+//!     let credentials = Credentials::read("kraken.key")?;
+//!
+//!     let user_agent = "<product>/<product-version>";
+//!     let clock = ClockSync::new();
+//!     let client = Client::with_credentials(user_agent, credentials)?
+//!         .with_clock_sync(clock.clone());
+//!
+//!     let estimate = clock.sync_blocking(&client)?;
+//!     println!("offset {} ms, rtt {:?}", estimate.offset_millis, estimate.rtt);
+//!
+//!     let expiretm = clock.expiry(Duration::from_secs(60));
+//!     println!("expiretm: {}", expiretm);
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use serde::Deserialize;
+use std::{
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    api, blocking, timestamp::Timestamp, Client, Error, Response, Result,
+};
+
+/// Default number of samples of each synchronization.
+const SAMPLES: usize = 5;
+
+/// Default maximum offset before warning.
+const MAX_DRIFT: Duration = Duration::from_secs(1);
+
+/// Server time, as returned by the `time` API.
+#[derive(Debug, Deserialize)]
+struct ServerTime {
+    unixtime: i64,
+}
+
+/// Single sample of the server time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Sample {
+    /// Local time when the request was sent, in milliseconds.
+    sent: i64,
+    /// Round trip time of the request.
+    rtt: Duration,
+    /// Server time, in whole seconds.
+    server: i64,
+}
+
+impl Sample {
+    /// Gets the offset of the server clock from the local clock, in
+    /// milliseconds, assuming the server time was taken halfway through the
+    /// round trip.
+    fn offset_millis(&self) -> i64 {
+        // the server time is truncated to the second, use its midpoint
+        let server = self.server * 1000 + 500;
+        let local = self.sent + self.rtt.as_millis() as i64 / 2;
+        server - local
+    }
+}
+
+/// Estimated offset of the server clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockEstimate {
+    /// Server time minus local time, in milliseconds.
+    pub offset_millis: i64,
+    /// Round trip time of the sample used for the estimate.
+    pub rtt: Duration,
+}
+
+/// Server-corrected clock, shared by all its clones.
+#[derive(Debug, Clone)]
+pub struct ClockSync {
+    /// Server time minus local time, in milliseconds.
+    offset: Arc<AtomicI64>,
+    /// The last nonce returned.
+    last_nonce: Arc<AtomicU64>,
+    /// The number of samples of each synchronization.
+    samples: usize,
+    /// The maximum offset before warning.
+    max_drift: Duration,
+}
+
+impl Default for ClockSync {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClockSync {
+    /// Constructs a new clock without offset, until synchronized.
+    pub fn new() -> Self {
+        Self {
+            offset: Arc::new(AtomicI64::new(0)),
+            last_nonce: Arc::new(AtomicU64::new(0)),
+            samples: SAMPLES,
+            max_drift: MAX_DRIFT,
+        }
+    }
+
+    /// Sets the number of samples of each synchronization.
+    pub fn with_samples(mut self, samples: usize) -> Self {
+        self.samples = samples.max(1);
+        self
+    }
+
+    /// Sets the maximum offset from the server clock before warning.
+    pub fn with_max_drift(mut self, max_drift: Duration) -> Self {
+        self.max_drift = max_drift;
+        self
+    }
+
+    /// Gets the server time minus the local time, in milliseconds.
+    pub fn offset_millis(&self) -> i64 {
+        self.offset.load(Ordering::Relaxed)
+    }
+
+    /// Sets the server time minus the local time, in milliseconds.
+    pub fn set_offset_millis(&self, offset: i64) {
+        self.offset.store(offset, Ordering::Relaxed);
+    }
+
+    /// Gets the server-corrected current time.
+    pub fn now(&self) -> Timestamp {
+        let now = Timestamp::now().as_nanos();
+        let offset = i128::from(self.offset_millis()) * 1_000_000;
+        Timestamp::from_nanos(now + offset)
+    }
+
+    /// Gets the server-corrected time after the given duration (e.g. for the
+    /// `expiretm` parameter).
+    pub fn expiry(&self, after: Duration) -> Timestamp {
+        let now = self.now().as_nanos();
+        Timestamp::from_nanos(now + after.as_nanos() as i128)
+    }
+
+    /// Gets a new nonce from the server-corrected time, always greater than
+    /// the previous ones, even if the offset decreases.
+    pub(crate) fn nonce(&self) -> u64 {
+        let now = (self.now().as_nanos() / 1_000_000).max(0) as u64;
+        let last = self
+            .last_nonce
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+                Some(now.max(last + 1))
+            })
+            .unwrap_or(now);
+        now.max(last + 1)
+    }
+
+    /// Samples the server time and updates the clock offset.
+    pub async fn sync(&self, client: &Client) -> Result<ClockEstimate> {
+        let mut samples = Vec::with_capacity(self.samples);
+        for _ in 0..self.samples {
+            let sent = local_millis();
+            let started = Instant::now();
+            let resp = client.send(api::public::time()).await?;
+            samples.push(sample(sent, started.elapsed(), resp)?);
+        }
+        self.update(&samples)
+    }
+
+    /// Samples the server time and updates the clock offset.
+    pub fn sync_blocking(
+        &self,
+        client: &blocking::Client,
+    ) -> Result<ClockEstimate> {
+        let mut samples = Vec::with_capacity(self.samples);
+        for _ in 0..self.samples {
+            let sent = local_millis();
+            let started = Instant::now();
+            let resp = client.send(api::public::time())?;
+            samples.push(sample(sent, started.elapsed(), resp)?);
+        }
+        self.update(&samples)
+    }
+
+    /// Updates the clock offset from the sample with the lowest round trip
+    /// time, warning if the offset exceeds the maximum drift.
+    fn update(&self, samples: &[Sample]) -> Result<ClockEstimate> {
+        let best = samples
+            .iter()
+            .min_by_key(|s| s.rtt)
+            .ok_or_else(|| Error::internal("no server time samples"))?;
+
+        let estimate = ClockEstimate {
+            offset_millis: best.offset_millis(),
+            rtt: best.rtt,
+        };
+        if estimate.offset_millis.unsigned_abs() as u128
+            > self.max_drift.as_millis()
+        {
+            log::warn!(
+                "Local clock is {} ms off the server clock (rtt {:?})",
+                -estimate.offset_millis,
+                estimate.rtt
+            );
+        }
+
+        self.set_offset_millis(estimate.offset_millis);
+        Ok(estimate)
+    }
+}
+
+/// Gets the local time in milliseconds.
+fn local_millis() -> i64 {
+    (Timestamp::now().as_nanos() / 1_000_000) as i64
+}
+
+/// Constructs the sample of the given `time` response.
+fn sample(
+    sent: i64,
+    rtt: Duration,
+    resp: Response<ServerTime>,
+) -> Result<Sample> {
+    if !resp.error.is_empty() {
+        return Err(Error::Api(resp.error));
+    }
+    let server = resp
+        .result
+        .ok_or_else(|| Error::invalid_message("missing server time"))?
+        .unixtime;
+    Ok(Sample { sent, rtt, server })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate_offset() -> anyhow::Result<()> {
+        let clock = ClockSync::new();
+        let samples = [
+            Sample {
+                sent: 1_625_918_399_000,
+                rtt: Duration::from_millis(800),
+                server: 1_625_918_400,
+            },
+            Sample {
+                sent: 1_625_918_397_700,
+                rtt: Duration::from_millis(200),
+                server: 1_625_918_400,
+            },
+        ];
+
+        let estimate = clock.update(&samples)?;
+        assert_eq!(estimate.rtt, Duration::from_millis(200));
+        // 1_625_918_400_500 - (1_625_918_397_700 + 100)
+        assert_eq!(estimate.offset_millis, 2_700);
+        assert_eq!(clock.clone().offset_millis(), 2_700);
+
+        assert!(clock.update(&[]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn corrected_time() {
+        let clock = ClockSync::new();
+        clock.set_offset_millis(60_000);
+        let delta = clock.now().secs() - Timestamp::now().secs();
+        assert!((59..=61).contains(&delta));
+
+        let expiry = clock.expiry(Duration::from_secs(60));
+        assert!(expiry.secs() - Timestamp::now().secs() >= 119);
+
+        // nonces keep increasing when the offset decreases
+        let nonce = clock.nonce();
+        clock.set_offset_millis(0);
+        assert!(clock.nonce() > nonce);
+    }
+}
@@ -0,0 +1,414 @@
+//! Download of the full public trades history.
+//!
+//! The `ohlc` API only returns the last 720 candles, while the `trades` API
+//! returns up to 1000 trades per request from the `since` cursor. The
+//! `TradeDownloader` walks the `trades` API from a given time up to the time
+//! the download started, passing the `last` cursor of each page as `since`
+//! parameter of the next one, and appends the trades to a CSV file (see the
+//! `market` module). The cursor is saved to a checkpoint file next to it
+//! after each page, so that an interrupted download resumes where it
+//! stopped.
+//!
+//! Two page requests are separated by a delay, and the requests rejected by
+//! the API rate limits are retried with an exponential backoff.
+//!
+//! The candles of any interval can then be rebuilt from the downloaded
+//! trades, beyond the 720 candles limit.
+//!
+//! ```no_run
+//! use akkorokamui::{blocking::Client, download::TradeDownloader, timestamp::Timestamp};
+//! use anyhow::Result;
+//! use std::time::Duration;
+//!
+//! fn main() -> Result<()> {
+//!     let user_agent = "<product>/<product-version>";
+//!     let client = Client::new(user_agent)?;
+//!
+//!     let downloader = TradeDownloader::new("XXBTZEUR", "XXBTZEUR.csv");
+//!     let count = downloader.download(&client, Timestamp::from_secs(1625097600))?;
+//!     println!("{} new trades", count);
+//!
+//!     let candles = downloader.candles(Duration::from_secs(4 * 60 * 60))?;
+//!     println!("{} 4h candles", candles.len());
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    api::{self, ApiBuilder},
+    blocking,
+    market::{self, Candle, PublicTrade, Trades},
+    timestamp::{Since, Timestamp},
+    Client, Error, Response, Result,
+};
+
+/// Default delay between two page requests.
+const PAGE_DELAY: Duration = Duration::from_secs(1);
+
+/// Default delay before the first retry of a rate limited request.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Maximum number of retries of a rate limited request.
+const MAX_RETRIES: u32 = 5;
+
+/// Extension appended to the CSV file name of the checkpoint file.
+const CHECKPOINT_EXTENSION: &str = ".last";
+
+/// Downloader of the trades of a pair to a CSV file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TradeDownloader {
+    /// The pair name of the requests.
+    pair: String,
+    /// The CSV file of the trades.
+    path: PathBuf,
+    /// The delay between two page requests.
+    page_delay: Duration,
+    /// The delay before the first retry of a rate limited request.
+    retry_delay: Duration,
+}
+
+impl TradeDownloader {
+    /// Constructs a new downloader of the trades of the given pair to the
+    /// given CSV file.
+    pub fn new(pair: impl Into<String>, path: impl AsRef<Path>) -> Self {
+        Self {
+            pair: pair.into(),
+            path: path.as_ref().to_path_buf(),
+            page_delay: PAGE_DELAY,
+            retry_delay: RETRY_DELAY,
+        }
+    }
+
+    /// Sets the delay between two page requests.
+    pub fn with_page_delay(mut self, page_delay: Duration) -> Self {
+        self.page_delay = page_delay;
+        self
+    }
+
+    /// Sets the delay before the first retry of a rate limited request,
+    /// doubled for each following retry.
+    pub fn with_retry_delay(mut self, retry_delay: Duration) -> Self {
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    /// Gets the path of the checkpoint file.
+    pub fn checkpoint_path(&self) -> PathBuf {
+        let mut path = OsString::from(self.path.as_os_str());
+        path.push(CHECKPOINT_EXTENSION);
+        path.into()
+    }
+
+    /// Gets the cursor of the last downloaded page, if any.
+    pub fn checkpoint(&self) -> Result<Option<Since>> {
+        let path = self.checkpoint_path();
+        if !path.exists() {
+            return Ok(None);
+        }
+        let cursor = fs::read_to_string(path).map_err(Error::internal)?;
+        cursor.trim().parse().map(Some)
+    }
+
+    /// Downloads the trades from the checkpoint, if any, or from the given
+    /// time, returning the number of new trades.
+    pub fn download(
+        &self,
+        client: &blocking::Client,
+        since: Timestamp,
+    ) -> Result<usize> {
+        let mut walk = self.start(since)?;
+        let mut count = 0;
+        while !walk.done {
+            if walk.pages > 0 {
+                thread::sleep(self.page_delay);
+            }
+
+            let mut retries = 0;
+            let resp = loop {
+                let resp = client.send(walk.page(&self.pair))?;
+                match self.backoff(&resp, retries) {
+                    Some(delay) => thread::sleep(delay),
+                    None => break resp,
+                }
+                retries += 1;
+            };
+            count += self.save(&mut walk, resp)?;
+        }
+        Ok(count)
+    }
+
+    /// Downloads the trades from the checkpoint, if any, or from the given
+    /// time, returning the number of new trades.
+    pub async fn download_async(
+        &self,
+        client: &Client,
+        since: Timestamp,
+    ) -> Result<usize> {
+        let mut walk = self.start(since)?;
+        let mut count = 0;
+        while !walk.done {
+            if walk.pages > 0 {
+                tokio::time::sleep(self.page_delay).await;
+            }
+
+            let mut retries = 0;
+            let resp = loop {
+                let resp = client.send(walk.page(&self.pair)).await?;
+                match self.backoff(&resp, retries) {
+                    Some(delay) => tokio::time::sleep(delay).await,
+                    None => break resp,
+                }
+                retries += 1;
+            };
+            count += self.save(&mut walk, resp)?;
+        }
+        Ok(count)
+    }
+
+    /// Loads the downloaded trades.
+    pub fn trades(&self) -> Result<Vec<PublicTrade>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let file = File::open(&self.path).map_err(Error::internal)?;
+        market::read_trades(BufReader::new(file))
+    }
+
+    /// Rebuilds the candles of the given interval from the downloaded
+    /// trades.
+    pub fn candles(&self, interval: Duration) -> Result<Vec<Candle>> {
+        Ok(market::candles_from_trades(&self.trades()?, interval))
+    }
+
+    /// Starts a walk from the checkpoint, if any, or from the given time.
+    fn start(&self, since: Timestamp) -> Result<Walk> {
+        let cursor = match self.checkpoint()? {
+            Some(cursor) => cursor,
+            None => Since::from(since),
+        };
+        Ok(Walk::new(cursor, Timestamp::now()))
+    }
+
+    /// Gets the delay before retrying the request of the given response, if
+    /// rate limited.
+    fn backoff(
+        &self,
+        resp: &Response<Trades>,
+        retries: u32,
+    ) -> Option<Duration> {
+        let rate_limited = resp.error.iter().any(|e| {
+            e.contains("Rate limit exceeded") || e.contains("Too many requests")
+        });
+        if rate_limited && retries < MAX_RETRIES {
+            log::warn!("Trades download rate limited, retry #{}", retries + 1);
+            Some(self.retry_delay * 2u32.pow(retries))
+        } else {
+            None
+        }
+    }
+
+    /// Appends the trades of the given page to the CSV file and saves the
+    /// checkpoint, returning the number of trades.
+    fn save(&self, walk: &mut Walk, resp: Response<Trades>) -> Result<usize> {
+        if !resp.error.is_empty() {
+            return Err(Error::Api(resp.error));
+        }
+        let page = resp
+            .result
+            .ok_or_else(|| Error::invalid_message("missing trades"))?;
+        let trades = walk.push(page);
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(Error::internal)?;
+        market::write_trades(BufWriter::new(file), &trades)?;
+        fs::write(self.checkpoint_path(), walk.cursor.as_str())
+            .map_err(Error::internal)?;
+
+        log::debug!(
+            "Downloaded {} {} trades up to {}",
+            trades.len(),
+            self.pair,
+            walk.cursor
+        );
+        Ok(trades.len())
+    }
+}
+
+/// The state of a trades walk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Walk {
+    /// The cursor of the next page.
+    cursor: Since,
+    /// The time the walk stops at.
+    until: Timestamp,
+    /// The number of pages received.
+    pages: usize,
+    /// Whether the last page has been reached.
+    done: bool,
+}
+
+impl Walk {
+    /// Constructs the state of a new walk.
+    fn new(cursor: Since, until: Timestamp) -> Self {
+        Self {
+            cursor,
+            until,
+            pages: 0,
+            done: false,
+        }
+    }
+
+    /// Gets the API of the next page.
+    fn page(&self, pair: &str) -> ApiBuilder {
+        api::public::trades()
+            .with("pair", pair)
+            .with("since", &self.cursor)
+    }
+
+    /// Moves the cursor past the given page, returning its trades.
+    fn push(&mut self, page: Trades) -> Vec<PublicTrade> {
+        self.pages += 1;
+        let last = page.last.clone();
+        let trades = page.into_entries();
+
+        let reached = match trades.last() {
+            Some(trade) => trade.time >= self.until,
+            None => true,
+        };
+        self.done = reached || last == self.cursor;
+        self.cursor = last;
+        trades
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use serde_json::json;
+
+    fn page(times: &[f64], last: &str) -> Result<Trades> {
+        let trades: Vec<_> = times
+            .iter()
+            .map(|time| json!(["30000.0", "0.1", time, "b", "m", ""]))
+            .collect();
+        Ok(serde_json::from_value(json!({
+            "XXBTZEUR": trades,
+            "last": last
+        }))?)
+    }
+
+    #[test]
+    fn walk_pages() -> Result<()> {
+        let since = Since::from(Timestamp::from_secs(1_625_918_400));
+        let mut walk = Walk::new(since, Timestamp::from_secs(1_625_918_500));
+        assert_eq!(
+            walk.page("XXBTZEUR")
+                .params
+                .get("since")
+                .map(String::as_str),
+            Some("1625918400")
+        );
+
+        let trades = walk.push(page(&[1625918401.5], "1625918401500000000")?);
+        assert_eq!(trades.len(), 1);
+        assert_eq!(walk.cursor.as_str(), "1625918401500000000");
+        assert!(!walk.done);
+
+        // the trades reached the start of the download
+        walk.push(page(&[1625918450.0, 1625918501.0], "1625918501000000000")?);
+        assert!(walk.done);
+
+        let cursor: Since = "1625918401500000000".parse()?;
+        let mut walk = Walk::new(cursor.clone(), Timestamp::now());
+        assert!(walk.push(page(&[], "1625918401500000000")?).is_empty());
+        assert!(walk.done);
+        assert_eq!(walk.cursor, cursor);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rate_limit_retries() {
+        let downloader = TradeDownloader::new("XXBTZEUR", "trades.csv")
+            .with_retry_delay(Duration::from_secs(1));
+        assert_eq!(
+            downloader.checkpoint_path(),
+            PathBuf::from("trades.csv.last")
+        );
+
+        let resp = Response {
+            error: vec!["EAPI:Rate limit exceeded".into()],
+            result: None,
+            status_code: 200,
+        };
+        assert_eq!(downloader.backoff(&resp, 2), Some(Duration::from_secs(4)));
+        assert_eq!(downloader.backoff(&resp, MAX_RETRIES), None);
+
+        let resp = Response {
+            error: vec!["EQuery:Unknown asset pair".into()],
+            ..resp
+        };
+        assert_eq!(downloader.backoff(&resp, 0), None);
+    }
+
+    #[cfg(feature = "testkit")]
+    #[test]
+    fn resume_download() -> Result<()> {
+        use crate::{client, testkit::MockServer};
+
+        let server = MockServer::start()?;
+        server.mock(
+            "Trades",
+            json!({
+                "XXBTZEUR": [
+                    ["30000.0", "0.1", 1625918400.5, "b", "m", "", 1],
+                    ["30010.0", "0.2", 1625918401.5, "s", "l", "", 2]
+                ],
+                "last": "1625918401500000000"
+            }),
+        );
+        server.mock(
+            "Trades",
+            json!({ "XXBTZEUR": [], "last": "1625918401500000000" }),
+        );
+        let client = blocking::Client::new(client::user_agent())?
+            .with_base_url(server.url());
+
+        let path = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let downloader = TradeDownloader::new("XXBTZEUR", &path)
+            .with_page_delay(Duration::from_millis(0));
+        let since = Timestamp::from_secs(1_625_918_400);
+        assert_eq!(downloader.download(&client, since)?, 2);
+        assert_eq!(
+            downloader.checkpoint()?,
+            Some("1625918401500000000".parse()?)
+        );
+
+        // resumes from the checkpoint
+        assert_eq!(downloader.download(&client, since)?, 0);
+        let requests = server.requests();
+        assert_eq!(requests[2].params["since"], "1625918401500000000");
+
+        let trades = downloader.trades()?;
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[1].trade_id, Some(2));
+
+        fs::remove_file(&path)?;
+        fs::remove_file(downloader.checkpoint_path())?;
+        Ok(())
+    }
+}
@@ -5,16 +5,42 @@ use thiserror::Error;
 /// Crate error enumeration.
 #[derive(Error, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Error {
+    #[error("API error: {}", .0.join(", "))]
+    Api(Vec<String>),
+    #[error(
+        "book checksum mismatch: expected {expected}, computed {computed}"
+    )]
+    ChecksumMismatch { expected: u32, computed: u32 },
+    #[error("invalid amount: {0}")]
+    InvalidAmount(String),
+    #[error("invalid asset pair: {0}")]
+    InvalidAssetPair(String),
     #[error("invalid key: {0}")]
     InvalidKey(String),
+    #[error("invalid key file at line {line}: {err}")]
+    InvalidKeyFile { line: usize, err: String },
+    #[error("invalid message: {0}")]
+    InvalidMessage(String),
     #[error("invalid user agent: {0}")]
     InvalidUserAgent(String),
+    #[error("cannot decode response (status {status}): {err}: {body}")]
+    Decode {
+        err: String,
+        status: u16,
+        body: String,
+    },
     #[error("internal error: {0}")]
     Internal(String),
+    #[error("missing {permission} permission for {method}")]
+    MissingPermission { method: String, permission: String },
+    #[error("order rejected by the risk guard: {0}")]
+    Risk(crate::risk::Violation),
     #[error("request failed: {err}")]
     Request { err: String, status: Option<u16> },
     #[error("not authorized")]
     Unauthorized,
+    #[error("websocket error: {0}")]
+    WebSocket(String),
 }
 
 impl Error {
@@ -23,11 +49,21 @@ impl Error {
         Self::Internal(message.to_string())
     }
 
+    /// Constructs an invalid asset pair error.
+    pub(crate) fn invalid_pair(message: impl fmt::Display) -> Self {
+        Self::InvalidAssetPair(message.to_string())
+    }
+
     /// Constructs an invalid key error.
     pub(crate) fn invalid_key(message: impl fmt::Display) -> Self {
         Self::InvalidKey(message.to_string())
     }
 
+    /// Constructs an invalid message error.
+    pub(crate) fn invalid_message(message: impl fmt::Display) -> Self {
+        Self::InvalidMessage(message.to_string())
+    }
+
     /// Constructs an invalid user agent error.
     pub(crate) fn invalid_agent(message: impl fmt::Display) -> Self {
         Self::InvalidUserAgent(message.to_string())
@@ -54,3 +90,10 @@ impl From<hmac::crypto_mac::InvalidKeyLength> for Error {
         Self::invalid_key(e)
     }
 }
+
+#[cfg(feature = "ws")]
+impl From<tokio_tungstenite::tungstenite::Error> for Error {
+    fn from(e: tokio_tungstenite::tungstenite::Error) -> Self {
+        Self::WebSocket(e.to_string())
+    }
+}
@@ -0,0 +1,486 @@
+//! Export reports workflow: request, poll, download and parse.
+//!
+//! This module is only available with the `export` feature.
+//!
+//! ```no_run
+//! use akkorokamui::{
+//!     export::{ExportJob, ExportedLedger},
+//!     Client, Credentials,
+//! };
+//! use anyhow::Result;
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<()> {
+//!     let credentials = Credentials::read("kraken.key")?;
+//!
+//!     let user_agent = "<product>/<product-version>";
+//!     let client = Client::with_credentials(user_agent, credentials)?;
+//!
+//!     let ledgers: Vec<ExportedLedger> = ExportJob::ledgers("audit")
+//!         .with_start(1609459200)
+//!         .remove_after(true)
+//!         .run(&client)
+//!         .await?;
+//!     println!("{} ledger entries", ledgers.len());
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    fmt,
+    io::{Cursor, Read},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    amount::{serde_amount, serde_opt_amount},
+    api, blocking, Amount, Client, Error, Response, ResponseValue, Result,
+};
+
+/// Default interval between two export status requests.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Kind of export report.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    Hash,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Report {
+    /// The trades history.
+    Trades,
+    /// The ledgers history.
+    Ledgers,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let report = match self {
+            Self::Trades => "trades",
+            Self::Ledgers => "ledgers",
+        };
+        write!(f, "{}", report)
+    }
+}
+
+/// Export report status, as returned by the `export_status` API.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportStatus {
+    /// Report ID.
+    pub id: String,
+    /// Report description.
+    pub descr: String,
+    /// Report format.
+    pub format: String,
+    /// Report kind.
+    pub report: String,
+    /// Status of the report (`Queued`, `Processing` or `Processed`).
+    pub status: String,
+}
+
+/// A single row of an exported trades report.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportedTrade {
+    /// Trade ID.
+    pub txid: String,
+    /// Order responsible for execution of trade.
+    pub ordertxid: String,
+    /// Asset pair.
+    pub pair: String,
+    /// Time of the trade.
+    pub time: String,
+    /// Type of order (buy/sell).
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Order type.
+    pub ordertype: String,
+    /// Average price order was executed at.
+    #[serde(with = "serde_amount")]
+    pub price: Amount,
+    /// Total cost of order.
+    #[serde(with = "serde_amount")]
+    pub cost: Amount,
+    /// Total fee.
+    #[serde(with = "serde_amount")]
+    pub fee: Amount,
+    /// Volume.
+    #[serde(with = "serde_amount")]
+    pub vol: Amount,
+    /// Initial margin (empty for the trades without margin).
+    #[serde(default, with = "serde_opt_amount")]
+    pub margin: Option<Amount>,
+    /// Comma delimited list of miscellaneous info.
+    #[serde(default)]
+    pub misc: String,
+    /// Comma delimited list of ledger IDs.
+    #[serde(default)]
+    pub ledgers: String,
+}
+
+/// A single row of an exported ledgers report.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportedLedger {
+    /// Ledger ID.
+    pub txid: String,
+    /// Reference ID.
+    pub refid: String,
+    /// Time of the ledger entry.
+    pub time: String,
+    /// Type of ledger entry.
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Subtype of ledger entry.
+    #[serde(default)]
+    pub subtype: String,
+    /// Asset class.
+    pub aclass: String,
+    /// Asset.
+    pub asset: String,
+    /// Transaction amount.
+    #[serde(with = "serde_amount")]
+    pub amount: Amount,
+    /// Transaction fee.
+    #[serde(with = "serde_amount")]
+    pub fee: Amount,
+    /// Resulting balance.
+    #[serde(with = "serde_amount")]
+    pub balance: Amount,
+}
+
+/// Export report job.
+///
+/// The job requests a new CSV report, polls its status until it has been
+/// processed, downloads and unzips it, and parses its rows. The report can
+/// optionally be removed from the Kraken servers once downloaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportJob {
+    /// The report kind.
+    report: Report,
+    /// The report description.
+    description: String,
+    /// Comma delimited list of fields to include.
+    fields: Option<String>,
+    /// Unix timestamp of the report start time.
+    start: Option<u64>,
+    /// Unix timestamp of the report end time.
+    end: Option<u64>,
+    /// The interval between two status requests.
+    poll_interval: Duration,
+    /// The maximum time to wait for the report to be processed.
+    timeout: Option<Duration>,
+    /// Whether to remove the report once downloaded.
+    remove: bool,
+}
+
+impl ExportJob {
+    /// Constructs a new trades report job with the given description.
+    pub fn trades(description: impl fmt::Display) -> Self {
+        Self::new(Report::Trades, description)
+    }
+
+    /// Constructs a new ledgers report job with the given description.
+    pub fn ledgers(description: impl fmt::Display) -> Self {
+        Self::new(Report::Ledgers, description)
+    }
+
+    /// Constructs a new report job.
+    fn new(report: Report, description: impl fmt::Display) -> Self {
+        Self {
+            report,
+            description: description.to_string(),
+            fields: None,
+            start: None,
+            end: None,
+            poll_interval: POLL_INTERVAL,
+            timeout: None,
+            remove: false,
+        }
+    }
+
+    /// Sets the comma delimited list of fields to include (all by default).
+    pub fn with_fields(mut self, fields: impl fmt::Display) -> Self {
+        self.fields = Some(fields.to_string());
+        self
+    }
+
+    /// Sets the Unix timestamp of the report start time.
+    pub fn with_start(mut self, start: u64) -> Self {
+        self.start = Some(start);
+        self
+    }
+
+    /// Sets the Unix timestamp of the report end time.
+    pub fn with_end(mut self, end: u64) -> Self {
+        self.end = Some(end);
+        self
+    }
+
+    /// Sets the interval between two status requests.
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Sets the maximum time to wait for the report to be processed.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets whether to remove the report once downloaded.
+    pub fn remove_after(mut self, remove: bool) -> Self {
+        self.remove = remove;
+        self
+    }
+
+    /// Runs the job and returns the parsed report rows.
+    pub async fn run<T: DeserializeOwned>(
+        &self,
+        client: &Client,
+    ) -> Result<Vec<T>> {
+        let resp = client.send(self.add_export()).await?;
+        let id = report_id(resp)?;
+        log::debug!("Requested {} report {}", self.report, id);
+
+        let started = Instant::now();
+        loop {
+            let resp = client.send(self.export_status()).await?;
+            if self.is_processed(resp, &id, started)? {
+                break;
+            }
+            tokio::time::sleep(self.poll_interval).await;
+        }
+
+        let retrieve = api::private::retrieve_export().with("id", &id);
+        let report = client.send_bytes(retrieve).await?;
+        let rows = parse_report(&report)?;
+
+        if self.remove {
+            let resp: ResponseValue = client.send(remove_export(&id)).await?;
+            check(resp)?;
+        }
+
+        Ok(rows)
+    }
+
+    /// Runs the job and returns the parsed report rows, blocking the current
+    /// thread while waiting for the report to be processed.
+    pub fn run_blocking<T: DeserializeOwned>(
+        &self,
+        client: &blocking::Client,
+    ) -> Result<Vec<T>> {
+        let id = report_id(client.send(self.add_export())?)?;
+        log::debug!("Requested {} report {}", self.report, id);
+
+        let started = Instant::now();
+        while !self.is_processed(
+            client.send(self.export_status())?,
+            &id,
+            started,
+        )? {
+            thread::sleep(self.poll_interval);
+        }
+
+        let retrieve = api::private::retrieve_export().with("id", &id);
+        let report = client.send_bytes(retrieve)?;
+        let rows = parse_report(&report)?;
+
+        if self.remove {
+            let resp: ResponseValue = client.send(remove_export(&id))?;
+            check(resp)?;
+        }
+
+        Ok(rows)
+    }
+
+    /// Gets the API to request the report.
+    fn add_export(&self) -> api::ApiBuilder {
+        let mut api = api::private::add_export()
+            .with("report", self.report)
+            .with("format", "CSV")
+            .with("description", &self.description);
+        if let Some(fields) = &self.fields {
+            api.with_mut("fields", fields);
+        }
+        if let Some(start) = self.start {
+            api.with_mut("starttm", start);
+        }
+        if let Some(end) = self.end {
+            api.with_mut("endtm", end);
+        }
+        api
+    }
+
+    /// Gets the API to request the status of the reports.
+    fn export_status(&self) -> api::ApiBuilder {
+        api::private::export_status().with("report", self.report)
+    }
+
+    /// Returns true only if the report with the given ID has been processed.
+    fn is_processed(
+        &self,
+        resp: Response<Vec<ExportStatus>>,
+        id: &str,
+        started: Instant,
+    ) -> Result<bool> {
+        let statuses = check(resp)?;
+        let status = statuses.iter().find(|s| s.id == id).ok_or_else(|| {
+            Error::internal(format!("report {} not found", id))
+        })?;
+        log::trace!("Report {} status: {}", id, status.status);
+
+        if status.status == "Processed" {
+            return Ok(true);
+        }
+        if matches!(self.timeout, Some(t) if started.elapsed() >= t) {
+            return Err(Error::internal(format!("report {} timed out", id)));
+        }
+        Ok(false)
+    }
+}
+
+/// Gets the API to delete the report with the given ID.
+fn remove_export(id: &str) -> api::ApiBuilder {
+    api::private::remove_export()
+        .with("id", id)
+        .with("type", "delete")
+}
+
+/// Gets the ID of a new report.
+fn report_id(resp: ResponseValue) -> Result<String> {
+    let result = check(resp)?;
+    result
+        .get("id")
+        .and_then(|id| id.as_str())
+        .map(String::from)
+        .ok_or_else(|| Error::invalid_message(result))
+}
+
+/// Gets the result of a successful response.
+fn check<T>(resp: Response<T>) -> Result<T> {
+    if !resp.error.is_empty() {
+        return Err(Error::Api(resp.error));
+    }
+    resp.result
+        .ok_or_else(|| Error::invalid_message("missing response result"))
+}
+
+/// Parses the rows of the CSV file contained in the given zip archive.
+///
+/// All the CSV fields are deserialized as strings (see the `amount` module
+/// for numeric fields).
+pub fn parse_report<T: DeserializeOwned>(report: &[u8]) -> Result<Vec<T>> {
+    // errors are returned as JSON instead of the zip archive
+    if let Ok(resp) = serde_json::from_slice::<ResponseValue>(report) {
+        check(resp)?;
+    }
+
+    let mut archive = zip::ZipArchive::new(Cursor::new(report))
+        .map_err(Error::invalid_message)?;
+
+    let mut csv = None;
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(Error::invalid_message)?;
+        if file.name().ends_with(".csv") {
+            let mut content = String::new();
+            file.read_to_string(&mut content)
+                .map_err(Error::invalid_message)?;
+            csv = Some(content);
+            break;
+        }
+    }
+    let csv =
+        csv.ok_or_else(|| Error::invalid_message("no CSV file in the report"))?;
+
+    // rows are deserialized from strings only, so that numeric fields are
+    // not parsed as floats before reaching the `Amount` fields
+    let mut reader = csv::Reader::from_reader(csv.as_bytes());
+    let headers = reader.headers().map_err(Error::invalid_message)?.clone();
+    reader
+        .records()
+        .map(|record| {
+            let record = record.map_err(Error::invalid_message)?;
+            let row: serde_json::Map<String, serde_json::Value> = headers
+                .iter()
+                .zip(record.iter())
+                .map(|(k, v)| (k.to_string(), v.into()))
+                .collect();
+            serde_json::from_value(row.into()).map_err(Error::invalid_message)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::io::Write;
+
+    fn zip_report(name: &str, content: &str) -> Result<Vec<u8>> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file(name, zip::write::FileOptions::default())?;
+        zip.write_all(content.as_bytes())?;
+        Ok(zip.finish()?.into_inner())
+    }
+
+    #[test]
+    fn parse_ledgers() -> Result<()> {
+        let csv = "\"txid\",\"refid\",\"time\",\"type\",\"subtype\",\"aclass\",\"asset\",\"amount\",\"fee\",\"balance\"\n\
+            \"L4UESK-KG3EQ-UFO4T5\",\"TJKLXX-PGMUI-4NTLXU\",\"2021-07-10 12:00:00\",\"trade\",\"\",\"currency\",\"ZGBP\",-24.5000,0.0490,459.9902\n";
+        let report = zip_report("ledgers.csv", csv)?;
+
+        let rows: Vec<ExportedLedger> = parse_report(&report)?;
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].txid, "L4UESK-KG3EQ-UFO4T5");
+        assert_eq!(rows[0].kind, "trade");
+        assert_eq!(rows[0].amount, crate::amount::amount("-24.5"));
+        assert_eq!(rows[0].balance, crate::amount::amount("459.9902"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_errors() -> Result<()> {
+        let error = br#"{"error":["EGeneral:Invalid arguments"]}"#;
+        assert_eq!(
+            parse_report::<ExportedTrade>(error),
+            Err(Error::Api(vec!["EGeneral:Invalid arguments".into()]))
+        );
+        assert!(parse_report::<ExportedTrade>(b"not a zip").is_err());
+
+        let report = zip_report("README.txt", "no report")?;
+        assert_eq!(
+            parse_report::<ExportedTrade>(&report),
+            Err(Error::invalid_message("no CSV file in the report"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn export_params() {
+        let job = ExportJob::trades("report")
+            .with_start(1609459200)
+            .with_fields("all");
+        let api = job.add_export();
+        assert_eq!(
+            api.params.get("report").map(String::as_str),
+            Some("trades")
+        );
+        assert_eq!(
+            api.params.get("starttm").map(String::as_str),
+            Some("1609459200")
+        );
+        assert!(!api.params.contains_key("endtm"));
+    }
+}
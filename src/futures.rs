@@ -0,0 +1,133 @@
+//! [Kraken Futures](https://futures.kraken.com) REST APIs.
+//!
+//! The Futures APIs are served from a different domain than the spot APIs,
+//! return a different response format and use their own authentication
+//! scheme, therefore they are queried with a dedicated client. Private APIs
+//! require a Futures API key pair, which is read from file as any other
+//! `Credentials`.
+//!
+//! ```no_run
+//! use akkorokamui::{futures, Credentials};
+//! use anyhow::Result;
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<()> {
+//!     let credentials = Credentials::read("kraken-futures.key")?;
+//!
+//!     let user_agent = "<product>/<product-version>";
+//!     let client = futures::Client::with_credentials(user_agent, credentials)?;
+//!
+//!     let api = futures::api::accounts();
+//!     let resp: futures::ResponseValue = client.send(api).await?;
+//!     println!("{:?}", resp);
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+pub use api::{Api, ApiBuilder};
+pub use client::{blocking, Client};
+
+pub mod api;
+pub mod client;
+#[cfg(feature = "ws")]
+pub mod ws;
+
+use crate::{Error, Result};
+
+/// Kraken Futures REST API domain.
+const FUTURES_DOMAIN: &str = "https://futures.kraken.com";
+
+/// Kraken Futures API response.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Response<T> {
+    /// Either `success` or `error`.
+    pub result: String,
+    /// The error message (only present if the request failed).
+    pub error: Option<String>,
+    /// The server time of the response.
+    pub server_time: Option<String>,
+    /// Result of API call (may not be present if errors occur).
+    pub content: Option<T>,
+    /// The response HTTP status code.
+    #[serde(skip)]
+    pub status_code: u16,
+}
+
+/// Generic Kraken Futures API response.
+pub type ResponseValue = Response<Value>;
+
+impl<T> Response<T> {
+    /// Returns true only if the Response result is `success` and the HTTP
+    /// status code is within [200, 299].
+    pub fn is_success(&self) -> bool {
+        self.result == "success"
+            && self.status_code >= 200
+            && self.status_code < 300
+    }
+}
+
+impl<T: DeserializeOwned> Response<T> {
+    /// Constructs the response from the JSON object returned by the API,
+    /// where the content fields are at the same level of `result`.
+    pub(crate) fn from_value(value: Value, status_code: u16) -> Result<Self> {
+        let field = |name: &str| {
+            value.get(name).and_then(|v| v.as_str()).map(String::from)
+        };
+
+        let result = field("result").unwrap_or_default();
+        let error = field("error");
+        let server_time = field("serverTime");
+
+        let content = if result == "success" {
+            let content = serde_json::from_value(value)
+                .map_err(Error::invalid_message)?;
+            Some(content)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            result,
+            error,
+            server_time,
+            content,
+            status_code,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::collections::HashMap;
+
+    #[test]
+    fn response_from_value() -> Result<()> {
+        let value = serde_json::json!({
+            "result": "success",
+            "serverTime": "2021-07-10T12:00:00.000Z",
+            "accounts": {"cash": {"type": "cashAccount"}}
+        });
+        let resp: Response<HashMap<String, Value>> =
+            Response::from_value(value, 200)?;
+        assert!(resp.is_success());
+        assert!(resp.content.expect("no content").contains_key("accounts"));
+
+        let value = serde_json::json!({
+            "result": "error",
+            "serverTime": "2021-07-10T12:00:00.000Z",
+            "error": "authenticationError"
+        });
+        let resp: ResponseValue = Response::from_value(value, 200)?;
+        assert!(!resp.is_success());
+        assert_eq!(resp.error.as_deref(), Some("authenticationError"));
+        assert!(resp.content.is_none());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,266 @@
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use reqwest::{header::HeaderMap, Method};
+use std::{collections::HashMap, fmt};
+
+use crate::{api::ApiKind, futures::FUTURES_DOMAIN};
+
+/// Characters percent-encoded in the parameters keys and values, i.e. all
+/// but the unreserved ones.
+const COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// List of Futures endpoints.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Endpoint {
+    // Public Market Data
+    Instruments,
+    Tickers,
+    OrderBook,
+    // Private Account Information
+    Accounts,
+    Fills,
+    OpenPositions,
+    // Private Order Management
+    CancelOrder,
+    SendOrder,
+}
+
+impl Endpoint {
+    /// Gets the kind and the HTTP method of the endpoint.
+    fn kind(self) -> (ApiKind, Method) {
+        match self {
+            Self::Instruments | Self::Tickers | Self::OrderBook => {
+                (ApiKind::Public, Method::GET)
+            }
+            Self::Accounts | Self::Fills | Self::OpenPositions => {
+                (ApiKind::Private, Method::GET)
+            }
+            Self::CancelOrder | Self::SendOrder => {
+                (ApiKind::Private, Method::POST)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_lowercase())
+    }
+}
+
+/// Futures API builder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiBuilder {
+    pub(crate) kind: ApiKind,
+    /// HTTP method.
+    pub(crate) method: Method,
+    /// Kraken Futures domain.
+    pub(crate) domain: String,
+    /// API endpoint.
+    pub(crate) endpoint: String,
+    /// API parameters.
+    pub(crate) params: HashMap<String, String>,
+    /// API headers map.
+    pub(crate) headers: HeaderMap,
+}
+
+impl fmt::Display for ApiBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.method, self.url())?;
+
+        // the parameters of GET requests are already included in the URL query
+        if self.method == Method::POST && !self.params.is_empty() {
+            write!(f, "?{}", self.params())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ApiBuilder {
+    /// Creates new API components for the given endpoint.
+    pub(crate) fn endpoint(endpoint: Endpoint) -> Self {
+        let (kind, method) = endpoint.kind();
+        Self {
+            kind,
+            method,
+            domain: FUTURES_DOMAIN.into(),
+            endpoint: endpoint.to_string(),
+            params: HashMap::default(),
+            headers: HeaderMap::default(),
+        }
+    }
+
+    /// Adds a new parameter to the API.
+    pub fn with(
+        mut self,
+        key: impl fmt::Display,
+        value: impl fmt::Display,
+    ) -> Self {
+        self.params.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Adds a new parameter to the API.
+    pub fn with_mut(
+        &mut self,
+        key: impl fmt::Display,
+        value: impl fmt::Display,
+    ) -> &mut Self {
+        self.params.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Gets the endpoint path used for the Authent header.
+    pub(crate) fn endpoint_path(&self) -> String {
+        format!("/api/v3/{}", self.endpoint)
+    }
+
+    /// Gets the API URL.
+    pub(crate) fn url(&self) -> String {
+        let mut url =
+            format!("{}/derivatives{}", self.domain, self.endpoint_path());
+
+        if self.method == Method::GET && !self.params.is_empty() {
+            url.push_str(&format!("?{}", self.params()));
+        }
+
+        url
+    }
+
+    /// Gets the URL encoded list of parameters, sorted by key, which is
+    /// both sent and signed.
+    pub(crate) fn params(&self) -> String {
+        let mut params: Vec<_> = self.params.iter().collect();
+        params.sort();
+        params
+            .into_iter()
+            .map(|(key, value)| {
+                format!(
+                    "{}={}",
+                    utf8_percent_encode(key, COMPONENT),
+                    utf8_percent_encode(value, COMPONENT)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+}
+
+/// A single Kraken Futures API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Api {
+    pub(crate) inner: ApiBuilder,
+}
+
+impl Api {
+    /// Returns true only if this is a public API.
+    pub fn is_public(&self) -> bool {
+        self.inner.kind == ApiKind::Public
+    }
+
+    /// Returns true only if this is a private API.
+    pub fn is_private(&self) -> bool {
+        self.inner.kind == ApiKind::Private
+    }
+
+    /// Gets the API URL.
+    pub fn url(&self) -> String {
+        self.inner.url()
+    }
+}
+
+impl From<ApiBuilder> for Api {
+    fn from(inner: ApiBuilder) -> Self {
+        Self { inner }
+    }
+}
+
+impl fmt::Display for Api {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.inner)
+    }
+}
+
+/// Get all the contracts and indices.
+pub fn instruments() -> ApiBuilder {
+    ApiBuilder::endpoint(Endpoint::Instruments)
+}
+
+/// Get ticker info of all the contracts and indices.
+pub fn tickers() -> ApiBuilder {
+    ApiBuilder::endpoint(Endpoint::Tickers)
+}
+
+/// Get the order book of a contract.
+pub fn orderbook() -> ApiBuilder {
+    ApiBuilder::endpoint(Endpoint::OrderBook)
+}
+
+/// Get the accounts balances and margin info.
+pub fn accounts() -> ApiBuilder {
+    ApiBuilder::endpoint(Endpoint::Accounts)
+}
+
+/// Get the last filled orders.
+pub fn fills() -> ApiBuilder {
+    ApiBuilder::endpoint(Endpoint::Fills)
+}
+
+/// Get open positions.
+pub fn open_positions() -> ApiBuilder {
+    ApiBuilder::endpoint(Endpoint::OpenPositions)
+}
+
+/// Send a new order.
+pub fn send_order() -> ApiBuilder {
+    ApiBuilder::endpoint(Endpoint::SendOrder)
+}
+
+/// Cancel an open order.
+pub fn cancel_order() -> ApiBuilder {
+    ApiBuilder::endpoint(Endpoint::CancelOrder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn api_url() {
+        let api = orderbook().with("symbol", "PI_XBTUSD");
+        assert_eq!(
+            api.url(),
+            "https://futures.kraken.com/derivatives/api/v3/orderbook?symbol=PI_XBTUSD"
+        );
+        assert_eq!(api.endpoint_path(), "/api/v3/orderbook");
+
+        let api: Api = send_order().with("symbol", "PI_XBTUSD").into();
+        assert!(api.is_private());
+        assert_eq!(
+            api.url(),
+            "https://futures.kraken.com/derivatives/api/v3/sendorder"
+        );
+        assert!(Api::from(open_positions()).is_private());
+        assert!(Api::from(instruments()).is_public());
+    }
+
+    #[test]
+    fn encoded_params() {
+        let api = send_order()
+            .with("cliOrdId", "a&b=c+d%")
+            .with("triggerSignal", "mark price");
+        assert_eq!(
+            api.params(),
+            "cliOrdId=a%26b%3Dc%2Bd%25&triggerSignal=mark%20price"
+        );
+        assert_eq!(
+            api.to_string(),
+            "POST https://futures.kraken.com/derivatives/api/v3/sendorder\
+             ?cliOrdId=a%26b%3Dc%2Bd%25&triggerSignal=mark%20price"
+        );
+    }
+}
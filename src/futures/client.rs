@@ -0,0 +1,137 @@
+use hmac::{Hmac, Mac, NewMac};
+use reqwest::header::{HeaderMap, HeaderValue};
+use sha2::{Digest, Sha256, Sha512};
+use std::fmt;
+
+use crate::{client, futures::Api, Credentials, Error, Result};
+
+pub use r#async::Client;
+
+pub mod r#async;
+pub mod blocking;
+
+/// The HTTP client used to query the Kraken Futures servers.
+///
+/// # Note
+/// The default client will only able to query public APIs. In order to query
+/// private APIs you need to construct the client with your Futures
+/// credentials.
+#[derive(Clone)]
+pub struct HttpClient<T> {
+    /// The HTTP client implementation.
+    client: T,
+    /// The credentials to use for private APIs.
+    credentials: Option<Credentials>,
+    /// The User-Agent header used for each request.
+    user_agent: HeaderValue,
+}
+
+impl<T> fmt::Display for HttpClient<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} <{}>",
+            self.user_agent.to_str().unwrap_or("User-Agent N/A"),
+            client::user_agent()
+        )
+    }
+}
+
+impl<T> HttpClient<T> {
+    /// Constructs a new client with the given HTTP client implementation.
+    fn with_client(
+        client: T,
+        user_agent: impl fmt::Display,
+        credentials: Option<Credentials>,
+    ) -> Result<Self> {
+        let user_agent = HeaderValue::from_str(&user_agent.to_string())
+            .map_err(Error::invalid_agent)?;
+        Ok(Self {
+            client,
+            credentials,
+            user_agent,
+        })
+    }
+
+    /// Builds the private request headers and the data to send, which is
+    /// either the POST body or the GET query.
+    fn make_req_args(&self, api: Api) -> Result<(HeaderMap, String)> {
+        debug_assert!(!api.is_public());
+        let nonce = client::nonce()?.to_string();
+        let post_data = api.inner.params();
+        let endpoint_path = api.inner.endpoint_path();
+
+        let credentials =
+            self.credentials.as_ref().ok_or(Error::Unauthorized)?;
+        let authent = authent(credentials, &post_data, &nonce, &endpoint_path)?;
+
+        let mut headers: HeaderMap = api.inner.headers;
+        headers.insert("APIKey", credentials.api_key_header()?);
+        headers.insert(
+            "Nonce",
+            HeaderValue::from_str(&nonce).map_err(Error::internal)?,
+        );
+        headers.insert("Authent", authent);
+
+        Ok((headers, post_data))
+    }
+}
+
+/// Generates the Authent header value.
+fn authent(
+    credentials: &Credentials,
+    post_data: &str,
+    nonce: &str,
+    endpoint_path: &str,
+) -> Result<HeaderValue> {
+    // Authent = Base64 encoded HMAC-SHA512 of SHA256(postData + nonce +
+    // endpointPath) and the base64 decoded secret API key
+    let message = format!("{}{}{}", post_data, nonce, endpoint_path);
+    let b64 = sign(credentials, &message)?;
+    HeaderValue::from_str(&b64).map_err(Error::internal)
+}
+
+/// Signs the given message with the HMAC-SHA512 of its SHA256 digest and the
+/// base64 decoded secret API key, as required by the Futures APIs.
+pub(crate) fn sign(credentials: &Credentials, message: &str) -> Result<String> {
+    type HmacSha512 = Hmac<Sha512>;
+
+    let sha = Sha256::digest(message.as_bytes());
+    let mut mac = HmacSha512::new_from_slice(credentials.private_key())?;
+    mac.update(&sha);
+
+    Ok(base64::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::tests::DummyCredentials;
+    use anyhow::Result;
+
+    #[test]
+    fn authent_signature() -> Result<()> {
+        let dummy = DummyCredentials::new()?;
+        let credentials = Credentials::read(&dummy.path)?;
+
+        let post_data = "orderType=lmt&symbol=PI_XBTUSD";
+        let endpoint = "/api/v3/sendorder";
+        let sign = authent(&credentials, post_data, "1625918400000", endpoint)?;
+
+        type HmacSha512 = Hmac<Sha512>;
+        let sha = Sha256::digest(
+            b"orderType=lmt&symbol=PI_XBTUSD1625918400000/api/v3/sendorder",
+        );
+        let mut mac = HmacSha512::new_from_slice(credentials.private_key())
+            .map_err(Error::from)?;
+        mac.update(&sha);
+        let expected = base64::encode(mac.finalize().into_bytes());
+        assert_eq!(sign.to_str()?, expected);
+
+        let other =
+            authent(&credentials, post_data, "1625918400001", endpoint)?;
+        assert_ne!(sign, other);
+
+        Ok(())
+    }
+}
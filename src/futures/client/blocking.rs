@@ -0,0 +1,79 @@
+use reqwest::{blocking, header::USER_AGENT, Method};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::fmt;
+
+use crate::{
+    futures::{client, Api, Response},
+    Credentials, RawResponse, Result,
+};
+
+/// The blocking HTTP client used to query the Kraken Futures servers.
+pub type Client = client::HttpClient<blocking::Client>;
+
+impl Client {
+    /// Constructs a new blocking Client that can only be used for public APIs.
+    pub fn new(user_agent: impl fmt::Display) -> Result<Self> {
+        Self::with_client(blocking::Client::default(), user_agent, None)
+    }
+
+    /// Constructs a new blocking Client with the given Futures credentials.
+    pub fn with_credentials(
+        user_agent: impl fmt::Display,
+        credentials: impl Into<Credentials>,
+    ) -> Result<Self> {
+        let credentials = Some(credentials.into());
+        Self::with_client(blocking::Client::default(), user_agent, credentials)
+    }
+
+    /// Sends the request to the Kraken Futures servers.
+    pub fn send<Req: Into<Api>, Resp: DeserializeOwned>(
+        &self,
+        api: Req,
+    ) -> Result<Response<Resp>> {
+        let mut api = api.into();
+        log::trace!("Sending request {}", api);
+
+        let user_agent = self.user_agent.to_owned();
+        api.inner.headers.append(USER_AGENT, user_agent);
+
+        let url = api.url();
+        let method = api.inner.method.clone();
+        let req = if api.is_public() {
+            self.client.request(method, url).headers(api.inner.headers)
+        } else {
+            let is_post = method == Method::POST;
+            let (headers, data) = self.make_req_args(api)?;
+            let req = self.client.request(method, url).headers(headers);
+            if is_post {
+                req.header("Content-Type", "application/x-www-form-urlencoded")
+                    .body(data)
+            } else {
+                req
+            }
+        };
+
+        let resp = req.send()?;
+        let raw = RawResponse {
+            status_code: resp.status().as_u16(),
+            headers: resp.headers().clone(),
+            body: resp.bytes()?.to_vec(),
+        };
+        Response::from_value(raw.json::<Value>()?, raw.status_code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client;
+    use anyhow::Result;
+
+    #[test]
+    fn client_builder() -> Result<()> {
+        let client = Client::new(client::user_agent())?;
+        assert_eq!(client.user_agent.to_str()?, client::user_agent());
+        assert!(client.credentials.is_none());
+        Ok(())
+    }
+}
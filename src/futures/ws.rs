@@ -0,0 +1,385 @@
+//! Kraken Futures Websockets feeds.
+//!
+//! This module is only available with the `ws` feature.
+//!
+//! ```no_run
+//! use akkorokamui::{futures::ws::{Feed, WsClient}, Credentials};
+//! use anyhow::Result;
+//! use futures_util::StreamExt;
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<()> {
+//!     let credentials = Credentials::read("kraken-futures.key")?;
+//!
+//!     let mut feeds = WsClient::with_credentials(credentials)
+//!         .subscribe(Feed::Ticker, &["PI_XBTUSD"])
+//!         .subscribe(Feed::Fills, &[] as &[&str])
+//!         .stream();
+//!
+//!     while let Some(message) = feeds.next().await {
+//!         println!("{:?}", message?);
+//!     }
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use futures_util::{stream, Stream};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{fmt, time::Duration};
+
+use crate::{
+    futures::client,
+    ws::{Reconnect, Socket},
+    Credentials, Error, Result,
+};
+
+/// Kraken Futures Websockets API URL.
+const FUTURES_WS_URL: &str = "wss://futures.kraken.com/ws/v1";
+
+/// Default interval of the keep-alive pings, as the server closes the
+/// connections idle for about 60 seconds.
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// List of Futures Websockets feeds.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    Hash,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Feed {
+    // Public Feeds
+    Book,
+    Ticker,
+    Trade,
+    // Private Feeds
+    Fills,
+    OpenOrders,
+    OpenPositions,
+    Balances,
+}
+
+impl Feed {
+    /// Returns true only if the feed requires authentication.
+    pub fn is_private(self) -> bool {
+        !matches!(self, Self::Book | Self::Ticker | Self::Trade)
+    }
+}
+
+impl fmt::Display for Feed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let feed = match self {
+            Self::Book => "book",
+            Self::Ticker => "ticker",
+            Self::Trade => "trade",
+            Self::Fills => "fills",
+            Self::OpenOrders => "open_orders",
+            Self::OpenPositions => "open_positions",
+            Self::Balances => "balances",
+        };
+        write!(f, "{}", feed)
+    }
+}
+
+/// The Futures Websockets client.
+///
+/// The client subscribes to the given feeds as soon as it connects, and
+/// automatically reconnects (and subscribes again) when the connection is
+/// lost, according to its reconnection policy.
+#[derive(Clone)]
+pub struct WsClient {
+    /// The Websockets API URL.
+    url: String,
+    /// The credentials to use for private feeds.
+    credentials: Option<Credentials>,
+    /// The feeds to subscribe to, with their product IDs.
+    subscriptions: Vec<(Feed, Vec<String>)>,
+    /// The reconnection policy.
+    reconnect: Reconnect,
+    /// The interval of the keep-alive pings.
+    ping_interval: Duration,
+}
+
+impl Default for WsClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WsClient {
+    /// Constructs a new client that can only subscribe to public feeds.
+    pub fn new() -> Self {
+        Self {
+            url: FUTURES_WS_URL.into(),
+            credentials: None,
+            subscriptions: Vec::new(),
+            reconnect: Reconnect::default(),
+            ping_interval: PING_INTERVAL,
+        }
+    }
+
+    /// Constructs a new client with the given Futures credentials.
+    pub fn with_credentials(credentials: impl Into<Credentials>) -> Self {
+        Self {
+            credentials: Some(credentials.into()),
+            ..Self::new()
+        }
+    }
+
+    /// Sets the reconnection policy.
+    pub fn with_reconnect(mut self, reconnect: Reconnect) -> Self {
+        self.reconnect = reconnect;
+        self
+    }
+
+    /// Sets the interval of the keep-alive pings sent while no message is
+    /// received (30 seconds by default).
+    pub fn with_ping_interval(mut self, interval: Duration) -> Self {
+        self.ping_interval = interval;
+        self
+    }
+
+    /// Subscribes to the given feed for the given products (private feeds
+    /// are not bound to any product).
+    pub fn subscribe(
+        mut self,
+        feed: Feed,
+        product_ids: &[impl fmt::Display],
+    ) -> Self {
+        let product_ids = product_ids.iter().map(|p| p.to_string()).collect();
+        self.subscriptions.push((feed, product_ids));
+        self
+    }
+
+    /// Connects to the Websockets API and returns the stream of messages of
+    /// all the subscribed feeds.
+    ///
+    /// Connection errors are yielded by the stream, which ends only once the
+    /// maximum number of reconnection attempts has been reached, or after
+    /// an authentication error (i.e. `Unauthorized` or `InvalidKey`), which
+    /// is never retried. The `error` and `alert` events (e.g. a rejected
+    /// subscription) are yielded as `WebSocket` errors.
+    pub fn stream(self) -> impl Stream<Item = Result<Value>> + Unpin {
+        let state = State {
+            client: self,
+            socket: None,
+            attempt: 0,
+            done: false,
+        };
+
+        Box::pin(stream::unfold(state, |mut state| async move {
+            loop {
+                if state.done {
+                    return None;
+                }
+                let socket = match state.socket.as_mut() {
+                    Some(socket) => socket,
+                    None => match state.connect().await {
+                        Ok(true) => continue,
+                        Ok(false) => return None,
+                        Err(e) => {
+                            state.done = is_fatal(&e);
+                            return Some((Err(e), state));
+                        }
+                    },
+                };
+
+                let interval = state.client.ping_interval;
+                match socket.recv_keepalive(interval).await {
+                    Some(Ok(message)) => {
+                        return Some((event_error(message), state))
+                    }
+                    Some(Err(e)) => {
+                        state.socket = None;
+                        return Some((Err(e), state));
+                    }
+                    None => {
+                        log::debug!("Futures Websockets connection closed");
+                        state.socket = None;
+                    }
+                }
+            }
+        }))
+    }
+
+    /// Authenticates (if needed) and subscribes to all the feeds.
+    async fn handshake(&self, socket: &mut Socket) -> Result<()> {
+        let private = self.subscriptions.iter().any(|(f, _)| f.is_private());
+        let auth = if private {
+            let credentials =
+                self.credentials.as_ref().ok_or(Error::Unauthorized)?;
+            let api_key = api_key(credentials)?;
+            let challenge = challenge(socket, &api_key).await?;
+            let signed = client::sign(credentials, &challenge)?;
+            Some((api_key, challenge, signed))
+        } else {
+            None
+        };
+
+        for (feed, product_ids) in &self.subscriptions {
+            let mut message = json!({
+                "event": "subscribe",
+                "feed": feed,
+            });
+            if !product_ids.is_empty() {
+                message["product_ids"] = json!(product_ids);
+            }
+            if let (true, Some((api_key, challenge, signed))) =
+                (feed.is_private(), &auth)
+            {
+                message["api_key"] = json!(api_key);
+                message["original_challenge"] = json!(challenge);
+                message["signed_challenge"] = json!(signed);
+            }
+            socket.send(&message).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The state of the feeds stream.
+struct State {
+    client: WsClient,
+    socket: Option<Socket>,
+    attempt: u32,
+    /// Whether the stream ended after an error that cannot be retried.
+    done: bool,
+}
+
+impl State {
+    /// Connects and subscribes to the feeds, waiting for the reconnection
+    /// delay if this is not the first attempt. Returns false only if no more
+    /// attempts are allowed.
+    async fn connect(&mut self) -> Result<bool> {
+        if self.attempt > 0 {
+            match self.client.reconnect.delay(self.attempt - 1) {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => return Ok(false),
+            }
+        }
+
+        // the private feeds cannot be subscribed without credentials
+        let private = self
+            .client
+            .subscriptions
+            .iter()
+            .any(|(f, _)| f.is_private());
+        if private && self.client.credentials.is_none() {
+            return Err(Error::Unauthorized);
+        }
+
+        self.attempt += 1;
+        let mut socket = Socket::connect(&self.client.url).await?;
+        self.client.handshake(&mut socket).await?;
+        self.socket = Some(socket);
+        self.attempt = 0;
+        Ok(true)
+    }
+}
+
+/// Returns true only if the given connection error cannot be fixed by
+/// reconnecting.
+fn is_fatal(error: &Error) -> bool {
+    matches!(
+        error,
+        Error::Unauthorized
+            | Error::InvalidKey(_)
+            | Error::InvalidKeyFile { .. }
+    )
+}
+
+/// Turns the `error` and `alert` events into errors.
+fn event_error(message: Value) -> Result<Value> {
+    match message.get("event").and_then(|e| e.as_str()) {
+        Some("error") | Some("alert") => {
+            Err(Error::WebSocket(message.to_string()))
+        }
+        _ => Ok(message),
+    }
+}
+
+/// Gets the API key as string.
+fn api_key(credentials: &Credentials) -> Result<String> {
+    Ok(credentials.api_key().to_string())
+}
+
+/// Requests a new challenge to sign for the private feeds.
+async fn challenge(socket: &mut Socket, api_key: &str) -> Result<String> {
+    let request = json!({ "event": "challenge", "api_key": api_key });
+    socket.send(&request).await?;
+
+    while let Some(message) = socket.recv().await {
+        let message = message?;
+        match message.get("event").and_then(|e| e.as_str()) {
+            Some("challenge") => {
+                return message
+                    .get("message")
+                    .and_then(|m| m.as_str())
+                    .map(String::from)
+                    .ok_or_else(|| Error::invalid_message(message));
+            }
+            // the API key was rejected
+            Some("error") | Some("alert") => {
+                return Err(Error::invalid_key(message))
+            }
+            _ => continue,
+        }
+    }
+
+    Err(Error::WebSocket("connection closed".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feeds() -> anyhow::Result<()> {
+        assert!(!Feed::Book.is_private());
+        assert!(Feed::OpenPositions.is_private());
+        assert_eq!(Feed::OpenOrders.to_string(), "open_orders");
+        assert_eq!(serde_json::to_value(Feed::Balances)?, "balances");
+
+        let client = WsClient::new()
+            .subscribe(Feed::Book, &["PI_XBTUSD", "PI_ETHUSD"])
+            .subscribe(Feed::Fills, &[] as &[&str]);
+        assert_eq!(client.subscriptions.len(), 2);
+        assert_eq!(client.subscriptions[0].1, vec!["PI_XBTUSD", "PI_ETHUSD"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn errors() {
+        assert!(is_fatal(&Error::Unauthorized));
+        assert!(is_fatal(&Error::invalid_key("rejected")));
+        assert!(!is_fatal(&Error::WebSocket("connection reset".into())));
+
+        let ticker = json!({ "feed": "ticker", "product_id": "PI_XBTUSD" });
+        assert_eq!(event_error(ticker.clone()), Ok(ticker));
+        let error =
+            json!({ "event": "error", "message": "Invalid product id" });
+        assert!(matches!(event_error(error), Err(Error::WebSocket(_))));
+    }
+
+    #[tokio::test]
+    async fn unauthorized_ends_stream() {
+        use futures_util::StreamExt;
+
+        let mut client = WsClient::new().subscribe(Feed::Fills, &[] as &[&str]);
+        client.url = "ws://127.0.0.1:1".into();
+        let mut feeds = client.stream();
+        assert_eq!(feeds.next().await, Some(Err(Error::Unauthorized)));
+        assert_eq!(feeds.next().await, None);
+    }
+}
@@ -0,0 +1,575 @@
+//! Paginated history of ledgers, trades and closed orders.
+//!
+//! The `ledgers`, `trades_history` and `closed_orders` APIs return at most 50
+//! entries per request, newest first. The `Paginator` walks all the pages
+//! within the requested time range (`start`/`end` parameters) using the `ofs`
+//! offset, skipping the entries already returned when new ones arrive while
+//! walking. The entries of each page are returned newest first, sorted by
+//! their `time` (`closetm` for the closed orders), then by ID.
+//!
+//! The paginator waits between two requests, and retries the rate limited
+//! requests with an exponential backoff. A client configured with
+//! `with_keys` also counts each page in the rate counter of the routed key
+//! and waits as needed before sending it, in which case the page delay can be
+//! reduced with `with_page_delay`.
+//!
+//! ```no_run
+//! use akkorokamui::{blocking::Client, history::{LedgerEntry, Paginator}, Credentials};
+//! use anyhow::Result;
+//!
+//! fn main() -> Result<()> {
+//!     let credentials = Credentials::read("kraken.key")?;
+//!
+//!     let user_agent = "<product>/<product-version>";
+//!     let client = Client::with_credentials(user_agent, credentials)?;
+//!
+//!     let ledgers = Paginator::ledgers().with("start", 1609459200);
+//!     for entry in ledgers.iter::<LedgerEntry>(&client) {
+//!         let (id, entry) = entry?;
+//!         println!("{}: {} {}", id, entry.amount, entry.asset);
+//!     }
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use futures_util::{stream, Stream};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    cmp::Ordering,
+    collections::{HashSet, VecDeque},
+    fmt, thread,
+    time::Duration,
+};
+
+use crate::{
+    amount::serde_amount,
+    api::{self, ApiBuilder},
+    blocking,
+    timestamp::Timestamp,
+    Amount, Asset, Client, Error, ResponseValue, Result,
+};
+
+/// Default delay between two page requests.
+const PAGE_DELAY: Duration = Duration::from_secs(2);
+
+/// Default delay before the first retry of a rate limited request.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Maximum number of retries of a rate limited request.
+const MAX_RETRIES: u32 = 5;
+
+/// Ledger entry, as returned by the `ledgers` API.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    /// Reference ID.
+    pub refid: String,
+    /// Unix timestamp of the ledger entry.
+    pub time: Timestamp,
+    /// Type of ledger entry.
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Subtype of ledger entry.
+    #[serde(default)]
+    pub subtype: String,
+    /// Asset class.
+    pub aclass: String,
+    /// Asset.
+    pub asset: Asset<'static>,
+    /// Transaction amount.
+    #[serde(with = "serde_amount")]
+    pub amount: Amount,
+    /// Transaction fee.
+    #[serde(with = "serde_amount")]
+    pub fee: Amount,
+    /// Resulting balance.
+    #[serde(with = "serde_amount")]
+    pub balance: Amount,
+}
+
+/// Trade info, as returned by the `trades_history` API.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TradeInfo {
+    /// Order responsible for execution of trade.
+    pub ordertxid: String,
+    /// Position responsible for execution of trade.
+    #[serde(default)]
+    pub postxid: String,
+    /// Asset pair.
+    pub pair: String,
+    /// Unix timestamp of the trade.
+    pub time: Timestamp,
+    /// Type of order (buy/sell).
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Order type.
+    pub ordertype: String,
+    /// Average price order was executed at.
+    #[serde(with = "serde_amount")]
+    pub price: Amount,
+    /// Total cost of order.
+    #[serde(with = "serde_amount")]
+    pub cost: Amount,
+    /// Total fee.
+    #[serde(with = "serde_amount")]
+    pub fee: Amount,
+    /// Volume.
+    #[serde(with = "serde_amount")]
+    pub vol: Amount,
+    /// Initial margin.
+    #[serde(default, with = "serde_amount")]
+    pub margin: Amount,
+    /// Comma delimited list of miscellaneous info.
+    #[serde(default)]
+    pub misc: String,
+}
+
+/// Order description info.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OrderDescription {
+    /// Asset pair.
+    pub pair: String,
+    /// Type of order (buy/sell).
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Order type.
+    pub ordertype: String,
+    /// Primary price.
+    pub price: String,
+    /// Secondary price.
+    pub price2: String,
+    /// Amount of leverage.
+    pub leverage: String,
+    /// Order description.
+    pub order: String,
+    /// Conditional close order description.
+    #[serde(default)]
+    pub close: String,
+}
+
+/// Order info, as returned by the `open_orders`, `closed_orders` and
+/// `query_orders` APIs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrderInfo {
+    /// Referral order transaction ID that created this order.
+    pub refid: Option<String>,
+    /// User reference ID.
+    pub userref: Option<i64>,
+    /// Status of order.
+    pub status: String,
+    /// Unix timestamp of when order was placed.
+    pub opentm: Timestamp,
+    /// Unix timestamp of order start time (or 0 if not set).
+    pub starttm: Timestamp,
+    /// Unix timestamp of order end time (or 0 if not set).
+    pub expiretm: Timestamp,
+    /// Unix timestamp of when order was closed (closed orders only).
+    pub closetm: Option<Timestamp>,
+    /// Order description info.
+    pub descr: OrderDescription,
+    /// Volume of order.
+    #[serde(with = "serde_amount")]
+    pub vol: Amount,
+    /// Volume executed.
+    #[serde(with = "serde_amount")]
+    pub vol_exec: Amount,
+    /// Total cost.
+    #[serde(with = "serde_amount")]
+    pub cost: Amount,
+    /// Total fee.
+    #[serde(with = "serde_amount")]
+    pub fee: Amount,
+    /// Average price.
+    #[serde(with = "serde_amount")]
+    pub price: Amount,
+    /// Stop price.
+    #[serde(default, with = "serde_amount")]
+    pub stopprice: Amount,
+    /// Triggered limit price.
+    #[serde(default, with = "serde_amount")]
+    pub limitprice: Amount,
+    /// Comma delimited list of miscellaneous info.
+    #[serde(default)]
+    pub misc: String,
+    /// Comma delimited list of order flags.
+    #[serde(default)]
+    pub oflags: String,
+    /// Additional info on status (closed orders only).
+    pub reason: Option<String>,
+}
+
+/// Paginated history API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Paginator {
+    /// The API to query for each page.
+    api: ApiBuilder,
+    /// The response result field containing the entries.
+    field: &'static str,
+    /// The entry field of the time the entries are sorted by.
+    time_field: &'static str,
+    /// The delay between two page requests.
+    page_delay: Duration,
+    /// The delay before the first retry of a rate limited request.
+    retry_delay: Duration,
+}
+
+impl Paginator {
+    /// Walks the `ledgers` history.
+    pub fn ledgers() -> Self {
+        Self::new(api::private::ledgers(), "ledger", "time")
+    }
+
+    /// Walks the `trades_history` history.
+    pub fn trades_history() -> Self {
+        Self::new(api::private::trades_history(), "trades", "time")
+    }
+
+    /// Walks the `closed_orders` history.
+    pub fn closed_orders() -> Self {
+        Self::new(api::private::closed_orders(), "closed", "closetm")
+    }
+
+    /// Constructs a new paginator of the given API.
+    fn new(
+        api: ApiBuilder,
+        field: &'static str,
+        time_field: &'static str,
+    ) -> Self {
+        Self {
+            api,
+            field,
+            time_field,
+            page_delay: PAGE_DELAY,
+            retry_delay: RETRY_DELAY,
+        }
+    }
+
+    /// Adds a new parameter to the API (e.g. `start` and `end`).
+    pub fn with(
+        mut self,
+        key: impl fmt::Display,
+        value: impl fmt::Display,
+    ) -> Self {
+        self.api.with_mut(key, value);
+        self
+    }
+
+    /// Sets the delay between two page requests.
+    pub fn with_page_delay(mut self, page_delay: Duration) -> Self {
+        self.page_delay = page_delay;
+        self
+    }
+
+    /// Sets the delay before the first retry of a rate limited request,
+    /// doubled for each following retry.
+    pub fn with_retry_delay(mut self, retry_delay: Duration) -> Self {
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    /// Returns the iterator over all the entries and their IDs.
+    pub fn iter<T: DeserializeOwned>(
+        self,
+        client: &blocking::Client,
+    ) -> Pages<'_, T> {
+        Pages {
+            client,
+            state: PageState::new(self),
+        }
+    }
+
+    /// Returns the stream of all the entries and their IDs.
+    pub fn stream<'c, T: DeserializeOwned + 'c>(
+        self,
+        client: &'c Client,
+    ) -> impl Stream<Item = Result<(String, T)>> + Unpin + 'c {
+        let state = PageState::new(self);
+
+        Box::pin(stream::unfold(state, move |mut state| async move {
+            loop {
+                if let Some(entry) = state.buffer.pop_front() {
+                    return Some((Ok(entry), state));
+                }
+                if state.done {
+                    return None;
+                }
+                if let Some(delay) = state.delay() {
+                    tokio::time::sleep(delay).await;
+                }
+
+                let result = match client.send(state.page()).await {
+                    Ok(resp) => state.push(resp),
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = result {
+                    state.done = true;
+                    return Some((Err(e), state));
+                }
+            }
+        }))
+    }
+}
+
+/// Blocking iterator over all the entries of a paginated history.
+pub struct Pages<'c, T> {
+    client: &'c blocking::Client,
+    state: PageState<T>,
+}
+
+impl<'c, T: DeserializeOwned> Iterator for Pages<'c, T> {
+    type Item = Result<(String, T)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.state.buffer.pop_front() {
+                return Some(Ok(entry));
+            }
+            if self.state.done {
+                return None;
+            }
+            if let Some(delay) = self.state.delay() {
+                thread::sleep(delay);
+            }
+
+            let result = self
+                .client
+                .send(self.state.page())
+                .and_then(|resp| self.state.push(resp));
+            if let Err(e) = result {
+                self.state.done = true;
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+/// The state of a history walk.
+struct PageState<T> {
+    paginator: Paginator,
+    /// The offset of the next page.
+    ofs: usize,
+    /// The IDs of the entries already returned.
+    seen: HashSet<String>,
+    /// The entries of the last page not returned yet.
+    buffer: VecDeque<(String, T)>,
+    /// The number of retries of the current page.
+    retries: u32,
+    /// Whether the last page has been reached.
+    done: bool,
+}
+
+impl<T: DeserializeOwned> PageState<T> {
+    /// Constructs the state of a new walk.
+    fn new(paginator: Paginator) -> Self {
+        Self {
+            paginator,
+            ofs: 0,
+            seen: HashSet::new(),
+            buffer: VecDeque::new(),
+            retries: 0,
+            done: false,
+        }
+    }
+
+    /// Gets the delay before the next request, if any: the backoff delay of
+    /// a rate limited page, or the page delay after the first page.
+    fn delay(&self) -> Option<Duration> {
+        if self.retries > 0 {
+            Some(self.paginator.retry_delay * 2u32.pow(self.retries - 1))
+        } else if self.ofs > 0 {
+            Some(self.paginator.page_delay)
+        } else {
+            None
+        }
+    }
+
+    /// Gets the API of the next page.
+    fn page(&self) -> ApiBuilder {
+        self.paginator.api.clone().with("ofs", self.ofs)
+    }
+
+    /// Adds the entries of the given page response that have not been
+    /// returned yet, newest first. A rate limited page is requested again,
+    /// up to the maximum number of retries.
+    fn push(&mut self, resp: ResponseValue) -> Result<()> {
+        if !resp.error.is_empty() {
+            let rate_limited = resp.error.iter().any(|e| {
+                e.contains("Rate limit exceeded")
+                    || e.contains("Too many requests")
+            });
+            if rate_limited && self.retries < MAX_RETRIES {
+                self.retries += 1;
+                log::warn!(
+                    "History page rate limited, retry #{}",
+                    self.retries
+                );
+                return Ok(());
+            }
+            return Err(Error::Api(resp.error));
+        }
+        self.retries = 0;
+
+        let result = resp.result.unwrap_or_default();
+        let count = result.get("count").and_then(|c| c.as_u64());
+        let mut entries: Vec<(String, Value)> =
+            match result.get(self.paginator.field) {
+                Some(Value::Object(entries)) => {
+                    entries.clone().into_iter().collect()
+                }
+                _ => Vec::new(),
+            };
+        // the entries object is not ordered by time
+        let time_field = self.paginator.time_field;
+        let time =
+            |entry: &Value| entry.get(time_field).and_then(Value::as_f64);
+        entries.sort_by(|(a_id, a), (b_id, b)| {
+            let by_time = time(b).partial_cmp(&time(a));
+            by_time
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a_id.cmp(b_id))
+        });
+
+        self.ofs += entries.len();
+        let remaining = matches!(count, Some(c) if (self.ofs as u64) < c);
+        self.done = entries.is_empty() || !remaining;
+
+        for (id, entry) in entries {
+            if self.seen.insert(id.clone()) {
+                let entry = serde_json::from_value(entry)
+                    .map_err(Error::invalid_message)?;
+                self.buffer.push_back((id, entry));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use serde_json::json;
+
+    fn page(ids: &[&str], count: u64) -> ResponseValue {
+        let times = vec![1625918400.1234; ids.len()];
+        timed_page(ids, &times, count)
+    }
+
+    fn timed_page(ids: &[&str], times: &[f64], count: u64) -> ResponseValue {
+        let entries: serde_json::Map<String, Value> = ids
+            .iter()
+            .zip(times)
+            .map(|(id, time)| {
+                let entry = json!({
+                    "refid": format!("R{}", id),
+                    "time": time,
+                    "type": "trade",
+                    "subtype": "",
+                    "aclass": "currency",
+                    "asset": "XXBT",
+                    "amount": "0.1000000000",
+                    "fee": "0.0000000000",
+                    "balance": "1.0000000000"
+                });
+                (id.to_string(), entry)
+            })
+            .collect();
+
+        ResponseValue {
+            error: Vec::new(),
+            result: Some(json!({ "ledger": entries, "count": count })),
+            status_code: 200,
+        }
+    }
+
+    #[test]
+    fn dedupe_pages() -> Result<()> {
+        let mut state = PageState::<LedgerEntry>::new(Paginator::ledgers());
+        assert_eq!(
+            state.page().params.get("ofs").map(String::as_str),
+            Some("0")
+        );
+
+        state.push(page(&["L1", "L2", "L3"], 5))?;
+        assert_eq!(state.ofs, 3);
+        assert!(!state.done);
+
+        // a new entry arrived, shifting L3 into the second page
+        state.push(page(&["L3", "L4", "L5"], 6))?;
+        assert_eq!(state.ofs, 6);
+        assert!(state.done);
+
+        let ids: Vec<String> =
+            state.buffer.drain(..).map(|(id, _)| id).collect();
+        assert_eq!(ids, vec!["L1", "L2", "L3", "L4", "L5"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn sort_pages_by_time() -> Result<()> {
+        let mut state = PageState::<LedgerEntry>::new(Paginator::ledgers());
+        // the same time is ordered by ID
+        let ids = ["LA", "LB", "LC", "LD", "LE"];
+        let times = [
+            1625918402.0,
+            1625918400.0,
+            1625918403.0,
+            1625918401.0,
+            1625918400.0,
+        ];
+        state.push(timed_page(&ids, &times, 5))?;
+
+        let ids: Vec<String> =
+            state.buffer.drain(..).map(|(id, _)| id).collect();
+        assert_eq!(ids, vec!["LC", "LA", "LD", "LB", "LE"]);
+
+        // the closed orders are sorted by their close time
+        let mut state = PageState::<Value>::new(Paginator::closed_orders());
+        let order = |opentm: f64, closetm: f64| json!({ "status": "closed", "opentm": opentm, "closetm": closetm });
+        state.push(ResponseValue {
+            error: Vec::new(),
+            result: Some(json!({
+                "closed": {
+                    "OA": order(1625918400.0, 1625918401.0),
+                    "OB": order(1625918402.0, 1625918403.0),
+                    "OC": order(1625918399.0, 1625918404.0),
+                },
+                "count": 3
+            })),
+            status_code: 200,
+        })?;
+
+        let ids: Vec<String> =
+            state.buffer.drain(..).map(|(id, _)| id).collect();
+        assert_eq!(ids, vec!["OC", "OB", "OA"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn page_errors() {
+        let mut state = PageState::<LedgerEntry>::new(
+            Paginator::ledgers().with_retry_delay(Duration::from_secs(1)),
+        );
+        let rate_limited = || ResponseValue {
+            error: vec!["EAPI:Rate limit exceeded".into()],
+            result: None,
+            status_code: 200,
+        };
+        for retry in 0..MAX_RETRIES {
+            assert_eq!(state.push(rate_limited()), Ok(()));
+            assert_eq!(state.delay(), Some(Duration::from_secs(1 << retry)));
+            assert_eq!(state.ofs, 0);
+        }
+        assert_eq!(
+            state.push(rate_limited()),
+            Err(Error::Api(vec!["EAPI:Rate limit exceeded".into()]))
+        );
+
+        state.push(page(&[], 0)).expect("empty page");
+        assert!(state.done);
+    }
+}
@@ -0,0 +1,686 @@
+//! Multiple API keys with routing by permission.
+//!
+//! Kraken API keys are created with a set of permissions (e.g. query funds,
+//! create orders, withdraw funds). The `KeyRouter` holds several keys, each
+//! tagged with its permissions, and a client configured with it signs each
+//! private request with the key granting the permission required by the
+//! method, preferring the key with the fewest permissions.
+//!
+//! Each key has its own nonce source and its own API rate counter: the
+//! client waits until the counter of the selected key decays enough before
+//! sending the request. The clients signing with their single credentials
+//! (i.e. without `with_keys`) do not count the requests, and never wait.
+//!
+//! The `check_permissions` method of the clients probes a side-effect-free
+//! call per permission (e.g. `add_order` with `validate=true`) and reports
+//! which permissions are granted to the configured keys, so that deploys can
+//! fail fast with `PermissionReport::require`.
+//!
+//! ```no_run
+//! use akkorokamui::{
+//!     api,
+//!     blocking::Client,
+//!     keys::{ApiKey, KeyRouter, Permission},
+//!     Keyring, ResponseValue,
+//! };
+//! use anyhow::Result;
+//!
+//! fn main() -> Result<()> {
+//!     let keyring = Keyring::read("kraken.json")?;
+//!     let get = |name| keyring.get(name).cloned().expect("missing key set");
+//!
+//!     let keys = KeyRouter::new()
+//!         .with_key(ApiKey::new(get("readonly"), vec![Permission::QueryFunds]))
+//!         .with_key(ApiKey::new(
+//!             get("trading"),
+//!             vec![Permission::ModifyOrders, Permission::CancelOrders],
+//!         ))
+//!         .with_key(ApiKey::new(get("funding"), vec![Permission::WithdrawFunds]));
+//!
+//!     let user_agent = "<product>/<product-version>";
+//!     let client = Client::new(user_agent)?.with_keys(keys);
+//!
+//!     // fail fast if the keys lack trading rights
+//!     let report = client.check_permissions();
+//!     println!("{}", report);
+//!     report.require(&[Permission::ModifyOrders, Permission::CancelOrders])?;
+//!
+//!     // signed with the "readonly" key
+//!     let balance: ResponseValue = client.send(api::private::balance())?;
+//!     println!("{:?}", balance);
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    api::{self, ApiBuilder},
+    Credentials, Error, ResponseValue, Result,
+};
+
+/// Permission of an API key.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Deserialize,
+    Serialize,
+    Ord,
+    PartialOrd,
+    Hash,
+)]
+pub enum Permission {
+    /// Query the balances and the trade volume.
+    QueryFunds,
+    /// Query the deposit methods, addresses and status.
+    DepositFunds,
+    /// Withdraw and transfer funds.
+    WithdrawFunds,
+    /// Query the open orders, positions and orders info.
+    QueryOpenOrders,
+    /// Query the closed orders and the trades history.
+    QueryClosedOrders,
+    /// Create and modify orders.
+    ModifyOrders,
+    /// Cancel and close orders.
+    CancelOrders,
+    /// Query the ledger entries.
+    QueryLedger,
+    /// Request and retrieve the export reports.
+    ExportData,
+    /// Access the websockets API.
+    WebSocket,
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let permission = match self {
+            Self::QueryFunds => "query-funds",
+            Self::DepositFunds => "deposit-funds",
+            Self::WithdrawFunds => "withdraw-funds",
+            Self::QueryOpenOrders => "query-open-orders",
+            Self::QueryClosedOrders => "query-closed-orders",
+            Self::ModifyOrders => "modify-orders",
+            Self::CancelOrders => "cancel-orders",
+            Self::QueryLedger => "query-ledger",
+            Self::ExportData => "export-data",
+            Self::WebSocket => "websocket",
+        };
+        f.pad(permission)
+    }
+}
+
+impl Permission {
+    /// All the permissions, in probing order.
+    pub const ALL: [Self; 10] = [
+        Self::QueryFunds,
+        Self::DepositFunds,
+        Self::WithdrawFunds,
+        Self::QueryOpenOrders,
+        Self::QueryClosedOrders,
+        Self::ModifyOrders,
+        Self::CancelOrders,
+        Self::QueryLedger,
+        Self::ExportData,
+        Self::WebSocket,
+    ];
+
+    /// Gets the permission required by the given private method (e.g.
+    /// "AddOrder"), if known.
+    pub fn required(method: &str) -> Option<Self> {
+        let permission = match method {
+            "Balance" | "TradeBalance" | "TradeVolume" => Self::QueryFunds,
+            "DepositMethods" | "DepositAddresses" | "DepositStatus" => {
+                Self::DepositFunds
+            }
+            "Withdraw" | "WithdrawInfo" | "WithdrawStatus"
+            | "WithdrawCancel" | "WalletTransfer" => Self::WithdrawFunds,
+            "OpenOrders" | "QueryOrders" | "OpenPositions" => {
+                Self::QueryOpenOrders
+            }
+            "ClosedOrders" | "TradesHistory" | "QueryTrades" => {
+                Self::QueryClosedOrders
+            }
+            "AddOrder" | "AddOrderBatch" | "EditOrder" => Self::ModifyOrders,
+            "CancelOrder" | "CancelAll" | "CancelAllOrdersAfter" => {
+                Self::CancelOrders
+            }
+            "Ledgers" | "QueryLedgers" => Self::QueryLedger,
+            "AddExport" | "ExportStatus" | "RetrieveExport"
+            | "RemoveExport" => Self::ExportData,
+            "GetWebSocketsToken" => Self::WebSocket,
+            _ => return None,
+        };
+        Some(permission)
+    }
+
+    /// Gets the side-effect-free API call requiring this permission.
+    pub(crate) fn probe(self) -> ApiBuilder {
+        match self {
+            Self::QueryFunds => api::private::balance(),
+            Self::DepositFunds => {
+                api::private::deposit_methods().with("asset", "XBT")
+            }
+            Self::WithdrawFunds => api::private::withdraw_info()
+                .with("asset", "XBT")
+                .with("key", PROBE_ID)
+                .with("amount", "0"),
+            Self::QueryOpenOrders => api::private::open_orders(),
+            Self::QueryClosedOrders => api::private::closed_orders(),
+            // only validated, never submitted
+            Self::ModifyOrders => api::private::add_order()
+                .with("pair", "XXBTZUSD")
+                .with("type", "buy")
+                .with("ordertype", "limit")
+                .with("price", "1")
+                .with("volume", "0.0001")
+                .with("validate", true),
+            // no order can match the probe ID
+            Self::CancelOrders => {
+                api::private::cancel_order().with("txid", PROBE_ID)
+            }
+            Self::QueryLedger => {
+                api::private::query_ledgers().with("id", PROBE_ID)
+            }
+            Self::ExportData => {
+                api::private::export_status().with("report", "trades")
+            }
+            Self::WebSocket => api::private::get_websockets_token(),
+        }
+    }
+}
+
+/// Verification tier of the account, which sets the API rate limits.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Deserialize,
+    Serialize,
+    Ord,
+    PartialOrd,
+    Hash,
+)]
+pub enum Tier {
+    #[default]
+    Starter,
+    Intermediate,
+    Pro,
+}
+
+impl Tier {
+    /// Gets the maximum API counter.
+    fn max_counter(self) -> f64 {
+        match self {
+            Self::Starter => 15.0,
+            Self::Intermediate | Self::Pro => 20.0,
+        }
+    }
+
+    /// Gets the API counter decrease per second.
+    fn decay(self) -> f64 {
+        match self {
+            Self::Starter => 0.33,
+            Self::Intermediate => 0.5,
+            Self::Pro => 1.0,
+        }
+    }
+}
+
+/// Gets the API counter increase of the given private method.
+fn cost(method: &str) -> f64 {
+    match method {
+        "Ledgers" | "QueryLedgers" | "TradesHistory" | "QueryTrades" => 2.0,
+        // the trading methods have their own rate limits
+        "AddOrder"
+        | "AddOrderBatch"
+        | "EditOrder"
+        | "CancelOrder"
+        | "CancelAll"
+        | "CancelAllOrdersAfter" => 0.0,
+        _ => 1.0,
+    }
+}
+
+/// API rate counter of a key.
+#[derive(Debug, Clone, Copy)]
+struct RateCounter {
+    /// The counter value, including the requests still waiting.
+    value: f64,
+    /// When the counter was last updated.
+    updated: Instant,
+}
+
+impl RateCounter {
+    /// Decreases the counter by the time elapsed since the last update.
+    fn decay(&mut self, tier: Tier, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated);
+        self.value =
+            (self.value - elapsed.as_secs_f64() * tier.decay()).max(0.0);
+        self.updated = now;
+    }
+
+    /// Increases the counter by the given cost, returning how long to wait
+    /// before sending the request.
+    fn acquire(&mut self, tier: Tier, cost: f64, now: Instant) -> Duration {
+        self.decay(tier, now);
+        self.value += cost;
+        let excess = self.value - tier.max_counter();
+        if excess > 0.0 {
+            Duration::from_secs_f64(excess / tier.decay())
+        } else {
+            Duration::from_secs(0)
+        }
+    }
+}
+
+/// API key tagged with its permissions.
+#[derive(Debug, Clone)]
+pub struct ApiKey {
+    credentials: Credentials,
+    permissions: BTreeSet<Permission>,
+    tier: Tier,
+    /// The last nonce used with this key.
+    last_nonce: Arc<AtomicU64>,
+    counter: Arc<Mutex<RateCounter>>,
+}
+
+impl ApiKey {
+    /// Constructs a new key with the given permissions, and the `Starter`
+    /// tier rate limits.
+    pub fn new(
+        credentials: impl Into<Credentials>,
+        permissions: impl IntoIterator<Item = Permission>,
+    ) -> Self {
+        Self {
+            credentials: credentials.into(),
+            permissions: permissions.into_iter().collect(),
+            tier: Tier::default(),
+            last_nonce: Arc::new(AtomicU64::new(0)),
+            counter: Arc::new(Mutex::new(RateCounter {
+                value: 0.0,
+                updated: Instant::now(),
+            })),
+        }
+    }
+
+    /// Sets the tier of the rate limits.
+    pub fn with_tier(mut self, tier: Tier) -> Self {
+        self.tier = tier;
+        self
+    }
+
+    /// Gets the key credentials.
+    pub fn credentials(&self) -> &Credentials {
+        &self.credentials
+    }
+
+    /// Gets the key permissions.
+    pub fn permissions(&self) -> &BTreeSet<Permission> {
+        &self.permissions
+    }
+
+    /// Returns true only if the key has the given permission.
+    pub fn allows(&self, permission: Permission) -> bool {
+        self.permissions.contains(&permission)
+    }
+
+    /// Gets the current API rate counter of the key.
+    pub fn counter(&self) -> f64 {
+        let mut counter = lock(&self.counter);
+        counter.decay(self.tier, Instant::now());
+        counter.value
+    }
+
+    /// Gets a new nonce, not lower than the given time and always greater
+    /// than the previous nonces of this key.
+    pub(crate) fn nonce(&self, now: u64) -> u64 {
+        let last = self
+            .last_nonce
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+                Some(now.max(last + 1))
+            })
+            .unwrap_or(now);
+        now.max(last + 1)
+    }
+
+    /// Counts a request of the given method, returning how long to wait
+    /// before sending it.
+    pub(crate) fn acquire(&self, method: &str) -> Duration {
+        lock(&self.counter).acquire(self.tier, cost(method), Instant::now())
+    }
+}
+
+/// Locks the given counter, even if poisoned.
+fn lock(
+    counter: &Mutex<RateCounter>,
+) -> std::sync::MutexGuard<'_, RateCounter> {
+    counter.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Set of API keys, selected by the permission required by each method.
+#[derive(Debug, Clone, Default)]
+pub struct KeyRouter {
+    keys: Vec<ApiKey>,
+}
+
+impl KeyRouter {
+    /// Constructs a new router without keys.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the given key.
+    pub fn with_key(mut self, key: ApiKey) -> Self {
+        self.keys.push(key);
+        self
+    }
+
+    /// Gets the keys.
+    pub fn keys(&self) -> &[ApiKey] {
+        &self.keys
+    }
+
+    /// Gets the key with the fewest permissions among the keys granting the
+    /// permission required by the given private method (e.g. "Withdraw").
+    ///
+    /// Any key can be used for the methods without known permission.
+    pub fn route(&self, method: &str) -> Result<&ApiKey> {
+        let required = Permission::required(method);
+        self.keys
+            .iter()
+            .filter(|key| match required {
+                Some(permission) => key.allows(permission),
+                None => true,
+            })
+            .min_by_key(|key| key.permissions.len())
+            .ok_or_else(|| match required {
+                Some(permission) => Error::MissingPermission {
+                    method: method.to_string(),
+                    permission: permission.to_string(),
+                },
+                None => Error::Unauthorized,
+            })
+    }
+}
+
+/// Identifier of the probes not matching any order, ledger or withdrawal key.
+const PROBE_ID: &str = "AKKOROKAMUI-PERMISSION-PROBE";
+
+/// Error returned by Kraken when the key lacks the permission of the method.
+const PERMISSION_DENIED: &str = "EGeneral:Permission denied";
+
+/// Outcome of the probe of a permission.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PermissionStatus {
+    /// The probe passed the permission check, even if the call failed for
+    /// other reasons: any error other than the permission denied, `EAPI`,
+    /// `EService`, temporary lockout and unknown method errors counts as
+    /// granted (e.g. `EOrder:Unknown order`, `EOrder:Insufficient funds` or
+    /// `EGeneral:Invalid arguments`).
+    Granted,
+    /// The probe was denied, or no key has the permission.
+    Denied,
+    /// The probe failed before reaching the permission check (e.g. network
+    /// failure, invalid key or nonce, or a method unknown to the simulated
+    /// exchange).
+    Unknown(Error),
+}
+
+impl PermissionStatus {
+    /// Classifies the result of a probe.
+    pub(crate) fn of(result: Result<ResponseValue>) -> Self {
+        let errors = match result {
+            Ok(resp) => resp.error,
+            Err(Error::MissingPermission { .. }) => return Self::Denied,
+            Err(e) => return Self::Unknown(e),
+        };
+        if errors.iter().any(|e| e.starts_with(PERMISSION_DENIED)) {
+            Self::Denied
+        } else if errors.iter().any(|e| {
+            e.starts_with("EAPI:")
+                || e.starts_with("EService:")
+                || e.starts_with("EGeneral:Temporary lockout")
+                || e.starts_with("EGeneral:Unknown method")
+        }) {
+            Self::Unknown(Error::Api(errors))
+        } else {
+            Self::Granted
+        }
+    }
+}
+
+impl fmt::Display for PermissionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Granted => write!(f, "granted"),
+            Self::Denied => write!(f, "denied"),
+            Self::Unknown(e) => write!(f, "unknown ({})", e),
+        }
+    }
+}
+
+/// Permissions of the configured keys, as probed by `check_permissions`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PermissionReport {
+    statuses: BTreeMap<Permission, PermissionStatus>,
+}
+
+impl fmt::Display for PermissionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (permission, status) in &self.statuses {
+            writeln!(f, "{:<20} {}", permission, status)?;
+        }
+        Ok(())
+    }
+}
+
+impl PermissionReport {
+    /// Sets the status of the given permission.
+    pub(crate) fn insert(
+        &mut self,
+        permission: Permission,
+        status: PermissionStatus,
+    ) {
+        self.statuses.insert(permission, status);
+    }
+
+    /// Gets the status of the given permission, if probed.
+    pub fn status(&self, permission: Permission) -> Option<&PermissionStatus> {
+        self.statuses.get(&permission)
+    }
+
+    /// Returns true only if the given permission is granted.
+    pub fn is_granted(&self, permission: Permission) -> bool {
+        self.status(permission) == Some(&PermissionStatus::Granted)
+    }
+
+    /// Gets the granted permissions.
+    pub fn granted(&self) -> impl Iterator<Item = Permission> + '_ {
+        self.statuses
+            .iter()
+            .filter(|(_, status)| **status == PermissionStatus::Granted)
+            .map(|(permission, _)| *permission)
+    }
+
+    /// Fails with the first of the given permissions not granted: a
+    /// `MissingPermission` error if denied, or the error of its probe.
+    pub fn require(&self, permissions: &[Permission]) -> Result<()> {
+        for &permission in permissions {
+            match self.status(permission) {
+                Some(PermissionStatus::Granted) => {}
+                Some(PermissionStatus::Unknown(e)) => return Err(e.clone()),
+                Some(PermissionStatus::Denied) | None => {
+                    return Err(Error::MissingPermission {
+                        method: permission.probe().method,
+                        permission: permission.to_string(),
+                    })
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn key(permissions: Vec<Permission>) -> Result<ApiKey> {
+        let credentials =
+            Credentials::new("<api_key>", "PHByaXZhdGVfa2V5Pg==")?;
+        Ok(ApiKey::new(credentials, permissions))
+    }
+
+    #[test]
+    fn route_least_privilege() -> Result<()> {
+        let keys = KeyRouter::new()
+            .with_key(key(vec![
+                Permission::QueryFunds,
+                Permission::ModifyOrders,
+                Permission::CancelOrders,
+            ])?)
+            .with_key(key(vec![Permission::QueryFunds])?)
+            .with_key(key(vec![Permission::WithdrawFunds])?);
+
+        let permissions = |method| -> Result<Vec<Permission>> {
+            let key = keys.route(method)?;
+            Ok(key.permissions().iter().copied().collect())
+        };
+        assert_eq!(permissions("Balance")?, vec![Permission::QueryFunds]);
+        assert_eq!(permissions("Withdraw")?, vec![Permission::WithdrawFunds]);
+        assert_eq!(permissions("AddOrder")?.len(), 3);
+        assert_eq!(
+            keys.route("AddExport").err(),
+            Some(Error::MissingPermission {
+                method: "AddExport".into(),
+                permission: "export-data".into(),
+            })
+        );
+        assert_eq!(
+            KeyRouter::new().route("Custom").err(),
+            Some(Error::Unauthorized)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn separate_nonces() -> Result<()> {
+        let (first, second) = (key(vec![])?, key(vec![])?);
+        assert_eq!(first.nonce(100), 100);
+        assert_eq!(first.nonce(100), 101);
+        assert_eq!(first.clone().nonce(50), 102);
+        assert_eq!(second.nonce(100), 100);
+        Ok(())
+    }
+
+    #[test]
+    fn rate_counter() {
+        let now = Instant::now();
+        let mut counter = RateCounter {
+            value: 0.0,
+            updated: now,
+        };
+        let tier = Tier::Pro;
+        for _ in 0..10 {
+            assert_eq!(counter.acquire(tier, 2.0, now), Duration::from_secs(0));
+        }
+        assert_eq!(counter.acquire(tier, 1.0, now), Duration::from_secs(1));
+        assert_eq!(counter.acquire(tier, 1.0, now), Duration::from_secs(2));
+
+        let later = now + Duration::from_secs(10);
+        assert_eq!(counter.acquire(tier, 1.0, later), Duration::from_secs(0));
+        assert_eq!(format!("{:.1}", counter.value), "13.0");
+    }
+
+    #[test]
+    fn permission_report() -> Result<()> {
+        let resp = |errors: &[&str]| {
+            Ok(ResponseValue {
+                error: errors.iter().map(|e| e.to_string()).collect(),
+                result: None,
+                status_code: 200,
+            })
+        };
+        let mut report = PermissionReport::default();
+        report.insert(Permission::QueryFunds, PermissionStatus::of(resp(&[])));
+        report.insert(
+            Permission::CancelOrders,
+            PermissionStatus::of(resp(&["EOrder:Unknown order"])),
+        );
+        report.insert(
+            Permission::ModifyOrders,
+            PermissionStatus::of(resp(&["EGeneral:Permission denied"])),
+        );
+        report.insert(
+            Permission::WithdrawFunds,
+            PermissionStatus::of(Err(Error::Request {
+                err: "timeout".into(),
+                status: None,
+            })),
+        );
+        report.insert(
+            Permission::QueryLedger,
+            PermissionStatus::of(resp(&["EAPI:Invalid nonce"])),
+        );
+        report.insert(
+            Permission::ExportData,
+            PermissionStatus::of(resp(&["EGeneral:Unknown method"])),
+        );
+        // the probe failed after the permission check
+        report.insert(
+            Permission::QueryOpenOrders,
+            PermissionStatus::of(resp(&["EGeneral:Invalid arguments"])),
+        );
+
+        assert_eq!(
+            report.granted().collect::<Vec<_>>(),
+            vec![
+                Permission::QueryFunds,
+                Permission::QueryOpenOrders,
+                Permission::CancelOrders
+            ]
+        );
+        assert!(report
+            .require(&[Permission::QueryFunds, Permission::CancelOrders])
+            .is_ok());
+        assert_eq!(
+            report.require(&[Permission::ModifyOrders]).err(),
+            Some(Error::MissingPermission {
+                method: "AddOrder".into(),
+                permission: "modify-orders".into(),
+            })
+        );
+        assert!(matches!(
+            report.require(&[Permission::WithdrawFunds]),
+            Err(Error::Request { .. })
+        ));
+        assert_eq!(
+            report.status(Permission::QueryLedger),
+            Some(&PermissionStatus::Unknown(Error::Api(vec![
+                "EAPI:Invalid nonce".into()
+            ])))
+        );
+        assert!(report.to_string().contains("modify-orders        denied"));
+
+        Ok(())
+    }
+}
@@ -19,6 +19,26 @@
 //! akkorokamui = { version = "0.5", features = ["native-tls"], default-features = false }
 //! ```
 //!
+//! The export reports workflow is available with the `export` optional feature,
+//! while the Websockets feeds clients are available with the `ws` optional
+//! feature:
+//!
+//! ```toml
+//! akkorokamui = { version = "0.5", features = ["export", "ws"] }
+//! ```
+//!
+//! Prices, volumes and balances of the typed models are `Amount`s, stored as
+//! `f64` by default: use the `rust_decimal` optional feature to store exact
+//! decimals instead (see the `amount` module). The `Amount` API is the same
+//! with and without the feature.
+//!
+//! Timestamps can be converted from and to `chrono` and `time` types with the
+//! `chrono` and `time` optional features (see the `timestamp` module).
+//!
+//! The `testkit` optional feature provides an in-process mock Kraken server
+//! to test clients and bots without network access (see the `testkit`
+//! module).
+//!
 //! ## Examples
 //!
 //! ### Create a client without credentials (server time)
@@ -140,7 +160,7 @@
 //!     struct Trade {
 //!         price: String,
 //!         volume: String,
-//!         time: f32,
+//!         time: f64,
 //!         buy_sell: String,
 //!         market_limit: String,
 //!         miscellaneous: String,
@@ -190,8 +210,12 @@
 //! [Kraken support page](https://support.kraken.com/hc/en-us/articles/360000919966-How-to-generate-an-API-key-pair)
 //! to learn how to generate these keys).
 //!
-//! The keys must be stored in a single file, where the first line contains the
-//! public API key and the second line contains the private key.
+//! The keys can be stored in a single file, where the first line contains the
+//! public API key and the second line contains the private key. They can also
+//! be read from the `KRAKEN_API_KEY` and `KRAKEN_PRIVATE_KEY` environment
+//! variables with `Credentials::from_env`, from the named key sets of a JSON
+//! (or TOML, with the `toml` optional feature) file with `Keyring`, or from
+//! your own secret store implementing `CredentialProvider`.
 //!
 //! ```no_run
 //! use akkorokamui::{api, Asset, Client, Credentials, Response};
@@ -227,12 +251,10 @@
 //!
 //! ```no_run
 //! use akkorokamui::{
-//!     api, Asset, AssetPair, blocking::Client, Credentials, Order, OrderType,
-//!     Response, ResponseValue,
+//!     api, blocking::Client, registry::AssetRegistry, Asset, Credentials,
+//!     Order, OrderType, ResponseValue,
 //! };
 //! use anyhow::{bail, Result};
-//! use serde::Deserialize;
-//! use std::collections::HashMap;
 //!
 //! fn main() -> Result<()> {
 //!     let keys_path = "kraken.key";
@@ -241,9 +263,9 @@
 //!     let user_agent = "<product>/<product-version>";
 //!     let client = Client::with_credentials(user_agent, credentials)?;
 //!
-//!     let asset_pairs = get_asset_pairs(&client)?;
-//!     let pair = Asset::new("XXRP").pair("ZGBP");
-//!     let xrp_gbp = if let Some(name) = asset_pairs.get(&pair) {
+//!     let registry = AssetRegistry::load_blocking(&client)?;
+//!     let pair = Asset::new("XRP").pair("GBP");
+//!     let xrp_gbp = if let Some((name, _)) = registry.asset_pair(&pair) {
 //!         name
 //!     } else {
 //!         bail!("{} asset pair name not found", pair)
@@ -268,33 +290,45 @@
 //!
 //!     Ok(())
 //! }
-//!
-//! fn get_asset_pairs<'a>(client: &Client) -> Result<HashMap<AssetPair<'a>, String>> {
-//!     type AssetPairs<'a> = HashMap<String, AssetPair<'a>>;
-//!
-//!     let api = api::public::asset_pairs();
-//!     let resp: Response<AssetPairs> = client.send(api)?;
-//!
-//!     if let Some(result) = resp.result {
-//!         Ok(result
-//!             .into_iter()
-//!             .map(|(k, v)| (v.base.pair(v.quote), k))
-//!             .collect())
-//!     } else {
-//!         bail!("Cannot get asset pairs: {:?}", resp.error);
-//!     }
-//! }
 //! ```
 
-pub use api::{Api, Response, ResponseValue};
+pub use amount::Amount;
+pub use api::{Api, RawResponse, Response, ResponseValue};
 pub use assets::{Asset, AssetPair};
-pub use auth::Credentials;
+pub use auth::{
+    CredentialProvider, Credentials, EnvVars, KeyFile, Keyring, API_KEY_VAR,
+    PRIVATE_KEY_VAR,
+};
+pub use book::OrderBook;
 pub use client::{blocking, Client};
 pub use error::Error;
-pub use order::{Order, OrderType};
+pub use order::{Order, OrderRequest, OrderType};
 
+pub mod amount;
 pub mod api;
+pub mod backtest;
+pub mod book;
+pub mod cassette;
 pub mod client;
+pub mod clock;
+pub mod download;
+#[cfg(feature = "export")]
+pub mod export;
+pub mod futures;
+pub mod history;
+pub mod keys;
+pub mod market;
+pub mod poll;
+pub mod registry;
+pub mod risk;
+pub mod sim;
+pub mod switch;
+#[cfg(feature = "testkit")]
+pub mod testkit;
+pub mod timestamp;
+pub mod tracker;
+#[cfg(feature = "ws")]
+pub mod ws;
 
 mod assets;
 mod auth;
@@ -307,17 +341,25 @@ type Result<T> = std::result::Result<T, Error>;
 /// Kraken REST API domain.
 const KRAKEN_DOMAIN: &str = "https://api.kraken.com";
 
-#[cfg(test)]
+#[cfg(all(test, feature = "testkit"))]
 mod tests {
     use super::*;
     use anyhow::Result;
+    use api::ApiBuilder;
     use client::{self, Client};
-
-    #[tokio::test]
-    async fn server_time() -> Result<()> {
-        let client = Client::new(client::user_agent())?;
-
-        let api = api::public::time();
+    use serde_json::{json, Value};
+    use testkit::{MockRequest, MockServer};
+
+    /// Sends the given API to a mock server answering its method with the
+    /// given result, and returns the response and the request received.
+    async fn send_mocked(
+        api: ApiBuilder,
+        result: Value,
+    ) -> Result<(ResponseValue, MockRequest)> {
+        let server = MockServer::start()?;
+        server.mock(&api.method, result);
+        let client =
+            Client::new(client::user_agent())?.with_base_url(server.url());
         println!("{}", api);
 
         let resp: ResponseValue = client.send(api).await?;
@@ -325,27 +367,41 @@ mod tests {
         assert!(resp.is_success());
         assert!(resp.result.is_some());
 
+        let mut requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        Ok((resp, requests.remove(0)))
+    }
+
+    #[tokio::test]
+    async fn server_time() -> Result<()> {
+        let api = api::public::time();
+        let result = json!({
+            "unixtime": 1625918400,
+            "rfc1123": "Sat, 10 Jul 21 12:00:00 +0000"
+        });
+        let (_, request) = send_mocked(api, result).await?;
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/0/public/Time");
+
         Ok(())
     }
 
     #[tokio::test]
     async fn system_status() -> Result<()> {
-        let client = Client::new(client::user_agent())?;
-
         let api = api::public::system_status();
-        println!("{}", api);
-
-        let resp: ResponseValue = client.send(api).await?;
-        println!("{:?}", resp);
-        assert!(resp.is_success());
-        assert!(resp.result.is_some());
+        let result =
+            json!({"status": "online", "timestamp": "2021-07-10T12:00:00Z"});
+        let (resp, _) = send_mocked(api, result).await?;
+        assert_eq!(
+            resp.result.map(|r| r["status"].clone()),
+            Some(json!("online"))
+        );
 
         Ok(())
     }
 
     #[tokio::test]
     async fn assets_info() -> Result<()> {
-        let client = Client::new(client::user_agent())?;
         let assets =
             [Asset::new("XXBT"), Asset::new("ZEUR"), Asset::new("XETH")];
 
@@ -355,110 +411,122 @@ mod tests {
             .collect::<Vec<String>>()
             .join(",");
         let api = api::public::assets().with("asset", asset);
-        println!("{}", api);
-
-        let resp: ResponseValue = client.send(api).await?;
-        println!("{:?}", resp);
-        assert!(resp.is_success());
-        assert!(resp.result.is_some());
+        let result = json!({
+            "XXBT": {
+                "aclass": "currency",
+                "altname": "XBT",
+                "decimals": 10,
+                "display_decimals": 5
+            }
+        });
+        let (_, request) = send_mocked(api, result).await?;
+        assert_eq!(request.params["asset"], "XXBT,ZEUR,XETH");
 
         Ok(())
     }
 
     #[tokio::test]
     async fn asset_pairs() -> Result<()> {
-        let client = Client::new(client::user_agent())?;
-
         let asset_pair = Asset::new("XXBT").pair("ZEUR");
         let api = api::public::asset_pairs().with("pair", &asset_pair);
-        println!("{}", api);
-
-        let resp: ResponseValue = client.send(api).await?;
-        println!("{:?}", resp);
-        assert!(resp.is_success());
-        assert!(resp.result.is_some());
+        let result = json!({
+            "XXBTZEUR": {
+                "altname": "XBTEUR",
+                "wsname": "XBT/EUR",
+                "base": "XXBT",
+                "quote": "ZEUR"
+            }
+        });
+        let (_, request) = send_mocked(api, result).await?;
+        assert_eq!(request.params["pair"], "XXBTZEUR");
 
         Ok(())
     }
 
     #[tokio::test]
     async fn ticker_info() -> Result<()> {
-        let client = Client::new(client::user_agent())?;
-
         let asset_pair = Asset::new("XXBT").pair("ZEUR");
         let api = api::public::ticker().with("pair", &asset_pair);
-        println!("{}", api);
-
-        let resp: ResponseValue = client.send(api).await?;
-        println!("{:?}", resp);
-        assert!(resp.is_success());
-        assert!(resp.result.is_some());
+        let result = json!({
+            "XXBTZEUR": {
+                "a": ["30000.1", "1", "1.000"],
+                "b": ["30000.0", "2", "2.000"],
+                "c": ["30000.0", "0.1"]
+            }
+        });
+        let (_, request) = send_mocked(api, result).await?;
+        assert_eq!(request.path, "/0/public/Ticker");
+        assert_eq!(request.params["pair"], "XXBTZEUR");
 
         Ok(())
     }
 
     #[tokio::test]
     async fn ohlc() -> Result<()> {
-        let client = Client::new(client::user_agent())?;
-
         let asset_pair = Asset::new("XXBT").pair("ZGBP");
         let api = api::public::ohlc().with("pair", &asset_pair);
-        println!("{}", api);
-
-        let resp: ResponseValue = client.send(api).await?;
-        println!("{:?}", resp);
-        assert!(resp.is_success());
-        assert!(resp.result.is_some());
+        let result = json!({
+            "XXBTZGBP": [[
+                1625918400, "25000.0", "25100.0", "24900.0", "25050.0",
+                "25010.0", "1.5", 42
+            ]],
+            "last": 1625918400
+        });
+        let (_, request) = send_mocked(api, result).await?;
+        assert_eq!(request.params["pair"], "XXBTZGBP");
 
         Ok(())
     }
 
     #[tokio::test]
     async fn depth() -> Result<()> {
-        let client = Client::new(client::user_agent())?;
-
         let asset_pair = Asset::new("XXBT").pair("ZGBP");
         let api = api::public::depth()
             .with("pair", &asset_pair)
             .with("count", 2);
-        println!("{}", api);
-
-        let resp: ResponseValue = client.send(api).await?;
-        println!("{:?}", resp);
-        assert!(resp.is_success());
-        assert!(resp.result.is_some());
+        let result = json!({
+            "XXBTZGBP": {
+                "asks": [
+                    ["25001.0", "1.000", 1625918400],
+                    ["25002.0", "0.500", 1625918400]
+                ],
+                "bids": [
+                    ["25000.0", "2.000", 1625918400],
+                    ["24999.0", "0.100", 1625918400]
+                ]
+            }
+        });
+        let (_, request) = send_mocked(api, result).await?;
+        assert_eq!(request.params["pair"], "XXBTZGBP");
+        assert_eq!(request.params["count"], "2");
 
         Ok(())
     }
 
     #[tokio::test]
     async fn trades() -> Result<()> {
-        let client = Client::new(client::user_agent())?;
-
         let asset_pair = Asset::new("XXBT").pair("ZUSD");
         let api = api::public::trades().with("pair", &asset_pair);
-        println!("{}", api);
-
-        let resp: ResponseValue = client.send(api).await?;
-        println!("{:?}", resp);
-        assert!(resp.is_success());
-        assert!(resp.result.is_some());
+        let result = json!({
+            "XXBTZUSD": [["33000.0", "0.010", 1625918400.1234, "b", "l", ""]],
+            "last": "1625918400123400000"
+        });
+        let (_, request) = send_mocked(api, result).await?;
+        assert_eq!(request.params["pair"], "XXBTZUSD");
 
         Ok(())
     }
 
     #[tokio::test]
     async fn spread() -> Result<()> {
-        let client = Client::new(client::user_agent())?;
-
         let asset_pair = Asset::new("XXBT").pair("ZUSD");
         let api = api::public::spread().with("pair", &asset_pair);
-        println!("{}", api);
-
-        let resp: ResponseValue = client.send(api).await?;
-        println!("{:?}", resp);
-        assert!(resp.is_success());
-        assert!(resp.result.is_some());
+        let result = json!({
+            "XXBTZUSD": [[1625918400, "32999.9", "33000.0"]],
+            "last": 1625918400
+        });
+        let (_, request) = send_mocked(api, result).await?;
+        assert_eq!(request.params["pair"], "XXBTZUSD");
 
         Ok(())
     }
@@ -0,0 +1,531 @@
+//! Typed public market data.
+//!
+//! The `ohlc`, `trades` and `spread` APIs return their entries as arrays
+//! (e.g. `["30000.0", "0.1", 1625918400.1234, "b", "l", "", 42]`), which are
+//! parsed into the `Candle`, `PublicTrade` and `Spread` types. Candles and
+//! trades can also be read from and written to CSV files, one entry per
+//! line, either in the format written by this crate or in the format of the
+//! Kraken downloadable history (OHLCVT files with
+//! `time,open,high,low,close,volume,count` lines, and trade files with
+//! `time,price,volume` lines).
+//!
+//! ```no_run
+//! use akkorokamui::{api, blocking::Client, market::Ohlc, Response};
+//! use anyhow::Result;
+//!
+//! fn main() -> Result<()> {
+//!     let user_agent = "<product>/<product-version>";
+//!     let client = Client::new(user_agent)?;
+//!
+//!     let api = api::public::ohlc().with("pair", "XXBTZEUR").with("interval", 60);
+//!     let resp: Response<Ohlc> = client.send(api)?;
+//!     if let Some(ohlc) = resp.result {
+//!         for candle in ohlc.into_entries() {
+//!             println!("{}: {}", candle.time, candle.close);
+//!         }
+//!     }
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use serde::{de, Deserialize, Deserializer};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    io::{BufRead, Write},
+    str::FromStr,
+    time::Duration,
+};
+
+use crate::{
+    timestamp::{Since, Timestamp},
+    Amount, Error, Order, OrderType, Result,
+};
+
+/// Single OHLC candle, as returned by the `ohlc` API.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Candle {
+    /// The candle start time.
+    pub time: Timestamp,
+    /// The first trade price.
+    pub open: Amount,
+    /// The highest trade price.
+    pub high: Amount,
+    /// The lowest trade price.
+    pub low: Amount,
+    /// The last trade price.
+    pub close: Amount,
+    /// The volume weighted average price.
+    pub vwap: Amount,
+    /// The traded volume.
+    pub volume: Amount,
+    /// The number of trades.
+    pub count: u64,
+}
+
+impl Candle {
+    /// Parses a candle in the form `[time, open, high, low, close, vwap,
+    /// volume, count]`.
+    fn from_entry(entry: &[Value]) -> Result<Self> {
+        let field = |index| entry_field(entry, index);
+        Ok(Self {
+            time: field(0)?.parse()?,
+            open: parse_amount(&field(1)?)?,
+            high: parse_amount(&field(2)?)?,
+            low: parse_amount(&field(3)?)?,
+            close: parse_amount(&field(4)?)?,
+            vwap: parse_amount(&field(5)?)?,
+            volume: parse_amount(&field(6)?)?,
+            count: parse_count(&field(7)?)?,
+        })
+    }
+
+    /// Parses a CSV line, either `time,open,high,low,close,vwap,volume,count`
+    /// or the Kraken OHLCVT `time,open,high,low,close,volume,count`, whose
+    /// vwap is unknown and set to the close price.
+    fn from_csv(line: &str) -> Result<Self> {
+        let fields: Vec<_> = line.split(',').map(str::trim).collect();
+        let (vwap, volume, count) = match fields.len() {
+            8 => (fields[5], fields[6], fields[7]),
+            7 => (fields[4], fields[5], fields[6]),
+            _ => return Err(invalid_line(line)),
+        };
+        Ok(Self {
+            time: fields[0].parse()?,
+            open: parse_amount(fields[1])?,
+            high: parse_amount(fields[2])?,
+            low: parse_amount(fields[3])?,
+            close: parse_amount(fields[4])?,
+            vwap: parse_amount(vwap)?,
+            volume: parse_amount(volume)?,
+            count: parse_count(count)?,
+        })
+    }
+
+    /// Formats the candle as CSV line.
+    fn csv_line(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{}",
+            self.time,
+            self.open,
+            self.high,
+            self.low,
+            self.close,
+            self.vwap,
+            self.volume,
+            self.count
+        )
+    }
+}
+
+impl<'de> Deserialize<'de> for Candle {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let entry = Vec::<Value>::deserialize(deserializer)?;
+        Candle::from_entry(&entry).map_err(de::Error::custom)
+    }
+}
+
+/// Single public trade, as returned by the `trades` API.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PublicTrade {
+    /// The trade price.
+    pub price: Amount,
+    /// The traded volume.
+    pub volume: Amount,
+    /// The trade time.
+    pub time: Timestamp,
+    /// The side of the taker.
+    pub side: Order,
+    /// Market or limit order type of the taker.
+    pub ordertype: OrderType,
+    /// Miscellaneous info.
+    pub misc: String,
+    /// The trade ID, if returned by the API.
+    pub trade_id: Option<u64>,
+}
+
+impl PublicTrade {
+    /// Parses a trade in the form `[price, volume, time, side, ordertype,
+    /// misc, trade_id]`, where the trade ID is optional.
+    fn from_entry(entry: &[Value]) -> Result<Self> {
+        let field = |index| entry_field(entry, index);
+        let trade_id = match entry.get(6) {
+            Some(_) => Some(parse_count(&field(6)?)?),
+            None => None,
+        };
+        Ok(Self {
+            price: parse_amount(&field(0)?)?,
+            volume: parse_amount(&field(1)?)?,
+            time: field(2)?.parse()?,
+            side: parse_side(&field(3)?)?,
+            ordertype: parse_ordertype(&field(4)?)?,
+            misc: field(5)?,
+            trade_id,
+        })
+    }
+
+    /// Parses a CSV line, either `time,price,volume,side,ordertype,trade_id`
+    /// or the Kraken `time,price,volume`, whose side is unknown and set to
+    /// buy.
+    fn from_csv(line: &str) -> Result<Self> {
+        let fields: Vec<_> = line.split(',').map(str::trim).collect();
+        let (side, ordertype, trade_id) = match fields.len() {
+            6 => (
+                parse_side(fields[3])?,
+                parse_ordertype(fields[4])?,
+                match fields[5] {
+                    "" => None,
+                    id => Some(parse_count(id)?),
+                },
+            ),
+            3 => (Order::Buy, OrderType::Market, None),
+            _ => return Err(invalid_line(line)),
+        };
+        Ok(Self {
+            price: parse_amount(fields[1])?,
+            volume: parse_amount(fields[2])?,
+            time: fields[0].parse()?,
+            side,
+            ordertype,
+            misc: String::new(),
+            trade_id,
+        })
+    }
+
+    /// Formats the trade as CSV line.
+    fn csv_line(&self) -> String {
+        let side = match self.side {
+            Order::Buy => "b",
+            Order::Sell => "s",
+        };
+        let ordertype = match self.ordertype {
+            OrderType::Limit => "l",
+            _ => "m",
+        };
+        let trade_id = self.trade_id.map(|id| id.to_string());
+        format!(
+            "{},{},{},{},{},{}",
+            self.time,
+            self.price,
+            self.volume,
+            side,
+            ordertype,
+            trade_id.unwrap_or_default()
+        )
+    }
+}
+
+impl<'de> Deserialize<'de> for PublicTrade {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let entry = Vec::<Value>::deserialize(deserializer)?;
+        PublicTrade::from_entry(&entry).map_err(de::Error::custom)
+    }
+}
+
+/// Single best bid and ask, as returned by the `spread` API.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Spread {
+    /// The spread time.
+    pub time: Timestamp,
+    /// The best bid price.
+    pub bid: Amount,
+    /// The best ask price.
+    pub ask: Amount,
+}
+
+impl<'de> Deserialize<'de> for Spread {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let entry = Vec::<Value>::deserialize(deserializer)?;
+        let field = |index| entry_field(&entry, index);
+        let spread = || -> Result<Self> {
+            Ok(Self {
+                time: field(0)?.parse()?,
+                bid: parse_amount(&field(1)?)?,
+                ask: parse_amount(&field(2)?)?,
+            })
+        };
+        spread().map_err(de::Error::custom)
+    }
+}
+
+/// Result of the `ohlc` API.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Ohlc {
+    /// The candles by pair name, oldest first.
+    #[serde(flatten)]
+    pub pairs: HashMap<String, Vec<Candle>>,
+    /// The cursor of the next request, as `since` parameter.
+    pub last: Since,
+}
+
+impl Ohlc {
+    /// Gets the candles of the requested pair, whose name may differ from
+    /// the one of the request (e.g. `XXBTZEUR` for `XBTEUR`).
+    pub fn into_entries(self) -> Vec<Candle> {
+        self.pairs
+            .into_iter()
+            .next()
+            .map(|p| p.1)
+            .unwrap_or_default()
+    }
+}
+
+/// Result of the `trades` API.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Trades {
+    /// The trades by pair name, oldest first.
+    #[serde(flatten)]
+    pub pairs: HashMap<String, Vec<PublicTrade>>,
+    /// The cursor of the next request, as `since` parameter.
+    pub last: Since,
+}
+
+impl Trades {
+    /// Gets the trades of the requested pair, whose name may differ from the
+    /// one of the request (e.g. `XXBTZEUR` for `XBTEUR`).
+    pub fn into_entries(self) -> Vec<PublicTrade> {
+        self.pairs
+            .into_iter()
+            .next()
+            .map(|p| p.1)
+            .unwrap_or_default()
+    }
+}
+
+/// Rebuilds the candles of the given interval from the given trades, sorted
+/// by time. The intervals without trades have no candle.
+pub fn candles_from_trades(
+    trades: &[PublicTrade],
+    interval: Duration,
+) -> Vec<Candle> {
+    let interval = interval.as_secs().max(1) as i64;
+    let zero = Amount::default();
+
+    let mut candles: Vec<Candle> = Vec::new();
+    // the total cost of the trades of the last candle
+    let mut cost = zero;
+    for trade in trades {
+        let secs = trade.time.secs();
+        let time = Timestamp::from_secs(secs - secs.rem_euclid(interval));
+        match candles.last_mut() {
+            Some(candle) if candle.time == time => {
+                if trade.price > candle.high {
+                    candle.high = trade.price;
+                }
+                if trade.price < candle.low {
+                    candle.low = trade.price;
+                }
+                candle.close = trade.price;
+                candle.volume += trade.volume;
+                candle.count += 1;
+            }
+            _ => {
+                cost = zero;
+                candles.push(Candle {
+                    time,
+                    open: trade.price,
+                    high: trade.price,
+                    low: trade.price,
+                    close: trade.price,
+                    vwap: trade.price,
+                    volume: trade.volume,
+                    count: 1,
+                });
+            }
+        }
+
+        cost += trade.price * trade.volume;
+        if let Some(candle) = candles.last_mut() {
+            if candle.volume > zero {
+                candle.vwap = cost / candle.volume;
+            }
+        }
+    }
+    candles
+}
+
+/// Reads the candles of the given CSV, skipping the empty lines and the
+/// header, if any.
+pub fn read_candles(reader: impl BufRead) -> Result<Vec<Candle>> {
+    read_csv(reader, Candle::from_csv)
+}
+
+/// Writes the given candles as CSV, without header.
+pub fn write_candles(mut writer: impl Write, candles: &[Candle]) -> Result<()> {
+    for candle in candles {
+        writeln!(writer, "{}", candle.csv_line()).map_err(Error::internal)?;
+    }
+    writer.flush().map_err(Error::internal)
+}
+
+/// Reads the trades of the given CSV, skipping the empty lines and the
+/// header, if any.
+pub fn read_trades(reader: impl BufRead) -> Result<Vec<PublicTrade>> {
+    read_csv(reader, PublicTrade::from_csv)
+}
+
+/// Writes the given trades as CSV, without header.
+pub fn write_trades(
+    mut writer: impl Write,
+    trades: &[PublicTrade],
+) -> Result<()> {
+    for trade in trades {
+        writeln!(writer, "{}", trade.csv_line()).map_err(Error::internal)?;
+    }
+    writer.flush().map_err(Error::internal)
+}
+
+/// Parses the lines of the given CSV.
+fn read_csv<T>(
+    reader: impl BufRead,
+    parse: impl Fn(&str) -> Result<T>,
+) -> Result<Vec<T>> {
+    let mut entries = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.map_err(Error::internal)?;
+        let line = line.trim();
+        let is_header =
+            index == 0 && line.starts_with(|c: char| c.is_ascii_alphabetic());
+        if line.is_empty() || is_header {
+            continue;
+        }
+        entries.push(parse(line)?);
+    }
+    Ok(entries)
+}
+
+/// Gets the given field of an API entry as string.
+fn entry_field(entry: &[Value], index: usize) -> Result<String> {
+    match entry.get(index) {
+        Some(Value::String(s)) => Ok(s.clone()),
+        Some(Value::Number(n)) => Ok(n.to_string()),
+        _ => Err(Error::invalid_message(format!(
+            "invalid market entry: {:?}",
+            entry
+        ))),
+    }
+}
+
+fn parse_amount(amount: &str) -> Result<Amount> {
+    Amount::from_str(amount).map_err(|_| Error::invalid_message(amount))
+}
+
+fn parse_count(count: &str) -> Result<u64> {
+    count.parse().map_err(|_| Error::invalid_message(count))
+}
+
+fn parse_side(side: &str) -> Result<Order> {
+    match side {
+        "b" => Ok(Order::Buy),
+        "s" => Ok(Order::Sell),
+        _ => Err(Error::invalid_message(side)),
+    }
+}
+
+fn parse_ordertype(ordertype: &str) -> Result<OrderType> {
+    match ordertype {
+        "m" => Ok(OrderType::Market),
+        "l" => Ok(OrderType::Limit),
+        _ => Err(Error::invalid_message(ordertype)),
+    }
+}
+
+fn invalid_line(line: &str) -> Error {
+    Error::invalid_message(format!("invalid CSV line: {}", line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{amount::amount, Response};
+    use anyhow::Result;
+
+    #[test]
+    fn typed_responses() -> Result<()> {
+        let ohlc = r#"{"error":[],"result":{"XXBTZEUR":[
+            [1625918400,"29000.0","29500.0","28900.0","29400.0","29250.5","12.5",321]
+        ],"last":1625918400}}"#;
+        let resp: Response<Ohlc> = serde_json::from_str(ohlc)?;
+        let ohlc = resp.result.unwrap();
+        assert_eq!(ohlc.last.as_str(), "1625918400");
+        let candles = ohlc.into_entries();
+        assert_eq!(candles[0].time, Timestamp::from_secs(1_625_918_400));
+        assert_eq!(candles[0].high, amount("29500"));
+        assert_eq!(candles[0].count, 321);
+
+        let trades = r#"{"error":[],"result":{"XXBTZEUR":[
+            ["29400.0","0.1",1625918400.1234,"s","l","",42],
+            ["29401.0","0.2",1625918401.5,"b","m",""]
+        ],"last":"1625918401500000000"}}"#;
+        let resp: Response<Trades> = serde_json::from_str(trades)?;
+        let trades = resp.result.unwrap().into_entries();
+        assert_eq!(trades[0].side, Order::Sell);
+        assert_eq!(trades[0].ordertype, OrderType::Limit);
+        assert_eq!(trades[0].time.to_string(), "1625918400.1234");
+        assert_eq!(trades[0].trade_id, Some(42));
+        assert_eq!(trades[1].trade_id, None);
+
+        let spread: Spread =
+            serde_json::from_str(r#"[1625918400,"29399.9","29400.1"]"#)?;
+        assert_eq!(spread.ask, amount("29400.1"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn csv_round_trip() -> Result<()> {
+        let kraken = "1625918400,29000,29500,28900,29400,12.5,321\n\n";
+        let candles = read_candles(kraken.as_bytes())?;
+        assert_eq!(candles[0].vwap, amount("29400"));
+
+        let mut csv = Vec::new();
+        write_candles(&mut csv, &candles)?;
+        assert_eq!(read_candles(csv.as_slice())?, candles);
+
+        let kraken = "time,price,volume\n1625918400.5,29400.0,0.1\n";
+        let trades = read_trades(kraken.as_bytes())?;
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].price, amount("29400"));
+
+        let mut csv = Vec::new();
+        write_trades(&mut csv, &trades)?;
+        assert_eq!(read_trades(csv.as_slice())?, trades);
+
+        assert!(read_trades("1625918400,29400".as_bytes()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn rebuild_candles() -> Result<()> {
+        let csv = "1625918400.5,100,1\n\
+            1625918459,110,1\n\
+            1625918460,90,2\n\
+            1625918700,95,1\n";
+        let trades = read_trades(csv.as_bytes())?;
+
+        let candles = candles_from_trades(&trades, Duration::from_secs(60));
+        assert_eq!(candles.len(), 3);
+        assert_eq!(candles[0].time, Timestamp::from_secs(1_625_918_400));
+        assert_eq!(candles[0].open, amount("100"));
+        assert_eq!(candles[0].high, amount("110"));
+        assert_eq!(candles[0].close, amount("110"));
+        assert_eq!(candles[0].vwap, amount("105"));
+        assert_eq!(candles[0].count, 2);
+        assert_eq!(candles[2].time, Timestamp::from_secs(1_625_918_700));
+
+        let candles = candles_from_trades(&trades, Duration::from_secs(3600));
+        assert_eq!(candles.len(), 1);
+        assert_eq!(candles[0].time, Timestamp::from_secs(1_625_918_400));
+        assert_eq!(candles[0].low, amount("90"));
+        assert_eq!(candles[0].volume, amount("5"));
+
+        Ok(())
+    }
+}
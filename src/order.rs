@@ -1,6 +1,11 @@
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
+use crate::{
+    api::{self, ApiBuilder},
+    Amount,
+};
+
 /// Order to buy or sell the asset.
 #[derive(
     Debug,
@@ -77,3 +82,64 @@ impl fmt::Display for OrderType {
         write!(f, "{}", order_type)
     }
 }
+
+/// Typed `add_order` request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OrderRequest {
+    /// Buy or sell.
+    pub order: Order,
+    /// The order type.
+    pub ordertype: OrderType,
+    /// The order volume, in base asset.
+    pub volume: Amount,
+    /// The primary price, if required by the order type.
+    pub price: Option<Amount>,
+    /// The user reference ID.
+    pub userref: Option<i64>,
+}
+
+impl OrderRequest {
+    /// Constructs a new market order request.
+    pub fn market(order: Order, volume: Amount) -> Self {
+        Self {
+            order,
+            ordertype: OrderType::Market,
+            volume,
+            price: None,
+            userref: None,
+        }
+    }
+
+    /// Constructs a new limit order request.
+    pub fn limit(order: Order, volume: Amount, price: Amount) -> Self {
+        Self {
+            order,
+            ordertype: OrderType::Limit,
+            volume,
+            price: Some(price),
+            userref: None,
+        }
+    }
+
+    /// Sets the user reference ID.
+    pub fn with_userref(mut self, userref: i64) -> Self {
+        self.userref = Some(userref);
+        self
+    }
+
+    /// Gets the `add_order` API of this request for the given pair.
+    pub fn api(&self, pair: impl fmt::Display) -> ApiBuilder {
+        let mut api = api::private::add_order()
+            .with("pair", pair)
+            .with("type", self.order)
+            .with("ordertype", self.ordertype)
+            .with("volume", self.volume);
+        if let Some(price) = self.price {
+            api.with_mut("price", price);
+        }
+        if let Some(userref) = self.userref {
+            api.with_mut("userref", userref);
+        }
+        api
+    }
+}
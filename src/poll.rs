@@ -0,0 +1,396 @@
+//! Incremental polling of the public market data.
+//!
+//! The `ohlc`, `trades` and `spread` APIs return the entries since the
+//! `since` parameter, and the `last` cursor to pass as `since` parameter of
+//! the next request. The `Poller` requests the API at a fixed interval,
+//! carries the cursor forward and yields only the new entries, either as a
+//! blocking iterator or as a stream.
+//!
+//! The last candle returned by the `ohlc` API is still forming, and is only
+//! yielded once closed. The entries returned again at the cursor boundary
+//! are yielded only once.
+//!
+//! A failed request yields its error, and the polling continues from the
+//! same cursor at the next interval.
+//!
+//! ```no_run
+//! use akkorokamui::{blocking::Client, poll::Poller};
+//! use anyhow::Result;
+//! use std::time::Duration;
+//!
+//! fn main() -> Result<()> {
+//!     let user_agent = "<product>/<product-version>";
+//!     let client = Client::new(user_agent)?;
+//!
+//!     let poller = Poller::ohlc("XXBTZEUR", 1).with_interval(Duration::from_secs(30));
+//!     for candle in poller.iter(&client) {
+//!         let candle = candle?;
+//!         println!("{}: {}", candle.time, candle.close);
+//!     }
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use futures_util::{stream, Stream};
+use serde::{de::DeserializeOwned, Deserialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    marker::PhantomData,
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    api::{self, ApiBuilder},
+    blocking,
+    market::{Candle, PublicTrade, Spread},
+    timestamp::{Since, Timestamp},
+    Client, Error, Response, Result,
+};
+
+/// Default delay between two requests.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Entry of the polled APIs.
+pub trait PollEntry: DeserializeOwned + Clone + PartialEq {
+    /// Gets the entry time.
+    fn time(&self) -> Timestamp;
+}
+
+impl PollEntry for Candle {
+    fn time(&self) -> Timestamp {
+        self.time
+    }
+}
+
+impl PollEntry for PublicTrade {
+    fn time(&self) -> Timestamp {
+        self.time
+    }
+}
+
+impl PollEntry for Spread {
+    fn time(&self) -> Timestamp {
+        self.time
+    }
+}
+
+/// Result of the polled APIs.
+#[derive(Debug, Deserialize)]
+#[serde(bound = "T: DeserializeOwned")]
+struct Page<T> {
+    /// The entries by pair name, oldest first.
+    #[serde(flatten)]
+    pairs: HashMap<String, Vec<T>>,
+    /// The cursor of the next request.
+    last: Since,
+}
+
+/// Poller of a public market data API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Poller<T> {
+    /// The API to request, without cursor.
+    api: ApiBuilder,
+    /// The delay between two requests.
+    interval: Duration,
+    /// The cursor of the first request, if any.
+    since: Option<Since>,
+    /// Whether the last entry of each response is still forming.
+    forming_last: bool,
+    entries: PhantomData<T>,
+}
+
+impl Poller<Candle> {
+    /// Polls the candles of the given pair and interval (in minutes).
+    pub fn ohlc(pair: &str, interval: u32) -> Self {
+        let api = api::public::ohlc()
+            .with("pair", pair)
+            .with("interval", interval);
+        Self::new(api, true)
+    }
+}
+
+impl Poller<PublicTrade> {
+    /// Polls the trades of the given pair.
+    pub fn trades(pair: &str) -> Self {
+        Self::new(api::public::trades().with("pair", pair), false)
+    }
+}
+
+impl Poller<Spread> {
+    /// Polls the spreads of the given pair.
+    pub fn spread(pair: &str) -> Self {
+        Self::new(api::public::spread().with("pair", pair), false)
+    }
+}
+
+impl<T: PollEntry> Poller<T> {
+    /// Constructs a new poller of the given API.
+    fn new(api: ApiBuilder, forming_last: bool) -> Self {
+        Self {
+            api,
+            interval: POLL_INTERVAL,
+            since: None,
+            forming_last,
+            entries: PhantomData,
+        }
+    }
+
+    /// Sets the delay between two requests.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Starts polling from the given cursor, instead of the entries returned
+    /// without `since` parameter.
+    pub fn with_since(mut self, since: impl Into<Since>) -> Self {
+        self.since = Some(since.into());
+        self
+    }
+
+    /// Returns the endless iterator over the new entries.
+    pub fn iter(self, client: &blocking::Client) -> Polls<'_, T> {
+        Polls {
+            client,
+            state: PollState::new(self),
+        }
+    }
+
+    /// Returns the endless stream of the new entries.
+    pub fn stream<'c>(
+        self,
+        client: &'c Client,
+    ) -> impl Stream<Item = Result<T>> + Unpin + 'c
+    where
+        T: 'c,
+    {
+        let state = PollState::new(self);
+
+        Box::pin(stream::unfold(state, move |mut state| async move {
+            loop {
+                if let Some(entry) = state.buffer.pop_front() {
+                    return Some((Ok(entry), state));
+                }
+                if state.polls > 0 {
+                    tokio::time::sleep(state.poller.interval).await;
+                }
+
+                let result = match client.send(state.poll()).await {
+                    Ok(resp) => state.push(resp),
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = result {
+                    return Some((Err(e), state));
+                }
+            }
+        }))
+    }
+}
+
+/// Blocking endless iterator over the new entries of a poller.
+pub struct Polls<'c, T> {
+    client: &'c blocking::Client,
+    state: PollState<T>,
+}
+
+impl<'c, T> Polls<'c, T> {
+    /// Gets the cursor of the next request, if any.
+    pub fn cursor(&self) -> Option<&Since> {
+        self.state.cursor.as_ref()
+    }
+}
+
+impl<'c, T: PollEntry> Iterator for Polls<'c, T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.state.buffer.pop_front() {
+                return Some(Ok(entry));
+            }
+            if self.state.polls > 0 {
+                thread::sleep(self.state.poller.interval);
+            }
+
+            let result = self
+                .client
+                .send(self.state.poll())
+                .and_then(|resp| self.state.push(resp));
+            if let Err(e) = result {
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+/// The state of a poller.
+struct PollState<T> {
+    poller: Poller<T>,
+    /// The number of requests sent.
+    polls: usize,
+    /// The cursor of the next request, if any.
+    cursor: Option<Since>,
+    /// The time of the last entry returned.
+    last_time: Option<Timestamp>,
+    /// The entries returned at the last entry time.
+    boundary: Vec<T>,
+    /// The new entries not returned yet.
+    buffer: VecDeque<T>,
+}
+
+impl<T: PollEntry> PollState<T> {
+    /// Constructs the state of a new poller.
+    fn new(poller: Poller<T>) -> Self {
+        Self {
+            cursor: poller.since.clone(),
+            poller,
+            polls: 0,
+            last_time: None,
+            boundary: Vec::new(),
+            buffer: VecDeque::new(),
+        }
+    }
+
+    /// Gets the API of the next request.
+    fn poll(&mut self) -> ApiBuilder {
+        self.polls += 1;
+        let mut api = self.poller.api.clone();
+        if let Some(cursor) = &self.cursor {
+            api.with_mut("since", cursor);
+        }
+        api
+    }
+
+    /// Adds the new entries of the given response and moves the cursor.
+    fn push(&mut self, resp: Response<Page<T>>) -> Result<()> {
+        if !resp.error.is_empty() {
+            return Err(Error::Api(resp.error));
+        }
+        let page = resp
+            .result
+            .ok_or_else(|| Error::invalid_message("missing result"))?;
+
+        let mut entries = page
+            .pairs
+            .into_iter()
+            .next()
+            .map(|p| p.1)
+            .unwrap_or_default();
+        if self.poller.forming_last {
+            entries.pop();
+        }
+
+        for entry in entries {
+            let time = entry.time();
+            match self.last_time {
+                Some(last) if time < last => continue,
+                Some(last) if time == last => {
+                    if self.boundary.contains(&entry) {
+                        continue;
+                    }
+                }
+                _ => {
+                    self.last_time = Some(time);
+                    self.boundary.clear();
+                }
+            }
+            self.boundary.push(entry.clone());
+            self.buffer.push_back(entry);
+        }
+
+        self.cursor = Some(page.last);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::amount;
+    use anyhow::Result;
+    use serde_json::{json, Value};
+
+    fn response(entries: Value, last: u64) -> Result<Response<Page<Candle>>> {
+        let resp = json!({
+            "error": [],
+            "result": { "XXBTZEUR": entries, "last": last }
+        });
+        Ok(serde_json::from_value(resp)?)
+    }
+
+    fn candle(time: u64, close: &str) -> Value {
+        json!([time, "1", "2", "1", close, "1.5", "10", 5])
+    }
+
+    #[test]
+    fn new_candles() -> Result<()> {
+        let mut state = PollState::new(Poller::ohlc("XXBTZEUR", 1));
+        assert!(!state.poll().params.contains_key("since"));
+
+        let entries = json!([
+            candle(1625918400, "2"),
+            candle(1625918460, "3"),
+            candle(1625918520, "4")
+        ]);
+        state.push(response(entries, 1625918460)?)?;
+        let closes: Vec<_> = state.buffer.drain(..).map(|c| c.close).collect();
+        assert_eq!(closes, vec![amount("2"), amount("3")]);
+        assert_eq!(
+            state.poll().params.get("since").map(String::as_str),
+            Some("1625918460")
+        );
+
+        // the last closed candle is returned again, with the closed one
+        let entries = json!([
+            candle(1625918460, "3"),
+            candle(1625918520, "5"),
+            candle(1625918580, "5")
+        ]);
+        state.push(response(entries, 1625918520)?)?;
+        let closes: Vec<_> = state.buffer.drain(..).map(|c| c.close).collect();
+        assert_eq!(closes, vec![amount("5")]);
+
+        let resp = Response {
+            error: vec!["EService:Unavailable".into()],
+            result: None,
+            status_code: 200,
+        };
+        assert!(state.push(resp).is_err());
+        assert_eq!(state.cursor, Some("1625918520".parse()?));
+
+        Ok(())
+    }
+
+    #[test]
+    fn boundary_trades() -> Result<()> {
+        let poller = Poller::trades("XXBTZEUR")
+            .with_since(Timestamp::from_secs(1_625_918_400));
+        let mut state = PollState::new(poller);
+        assert_eq!(
+            state.poll().params.get("since").map(String::as_str),
+            Some("1625918400")
+        );
+
+        let page = |trades: Value| -> Result<Response<Page<PublicTrade>>> {
+            Ok(serde_json::from_value(json!({
+                "error": [],
+                "result": { "XXBTZEUR": trades, "last": "1625918401000000000" }
+            }))?)
+        };
+        state.push(page(json!([
+            ["1", "0.1", 1625918401, "b", "m", "", 1],
+            ["2", "0.1", 1625918401, "b", "m", "", 2]
+        ]))?)?;
+        state.push(page(json!([
+            ["2", "0.1", 1625918401, "b", "m", "", 2],
+            ["3", "0.1", 1625918401, "s", "m", "", 3]
+        ]))?)?;
+
+        let ids: Vec<_> = state.buffer.drain(..).map(|t| t.trade_id).collect();
+        assert_eq!(ids, vec![Some(1), Some(2), Some(3)]);
+
+        Ok(())
+    }
+}
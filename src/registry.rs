@@ -0,0 +1,469 @@
+//! Registry of the Kraken assets and asset pairs.
+//!
+//! Kraken refers to the same asset with different names: the REST APIs use
+//! legacy names with `X` (crypto) and `Z` (fiat) prefixes (e.g. `XXBT`,
+//! `ZEUR`), the alternate names drop them (e.g. `XBT`, `EUR`), the Websockets
+//! APIs name the asset pairs with a slash (e.g. `XBT/EUR`) and most of the
+//! world calls bitcoin `BTC`. The `AssetRegistry` is loaded from the `assets`
+//! and `asset_pairs` APIs and resolves any of these names to the REST ones.
+//!
+//! ```no_run
+//! use akkorokamui::{blocking::Client, registry::AssetRegistry};
+//! use anyhow::Result;
+//!
+//! fn main() -> Result<()> {
+//!     let user_agent = "<product>/<product-version>";
+//!     let client = Client::new(user_agent)?;
+//!
+//!     let registry = AssetRegistry::load_blocking(&client)?;
+//!     if let Some((name, pair)) = registry.pair("BTC/EUR") {
+//!         println!("{} ({:?}): {} decimals", name, pair.wsname, pair.pair_decimals);
+//!     }
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    amount::{self, serde_amount, serde_opt_amount},
+    api, blocking, Amount, Asset, AssetPair, Client, Error, Response, Result,
+};
+
+/// Common asset names that differ from the Kraken alternate names.
+pub(crate) const ALIASES: &[(&str, &str)] = &[("BTC", "XBT"), ("DOGE", "XDG")];
+
+/// Asset info, as returned by the `assets` API.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AssetInfo {
+    /// Asset class.
+    pub aclass: String,
+    /// Alternate name.
+    pub altname: String,
+    /// Scaling decimal places for record keeping.
+    pub decimals: u32,
+    /// Scaling decimal places for output display.
+    pub display_decimals: u32,
+    /// Status of asset (e.g. `enabled`).
+    #[serde(default)]
+    pub status: Option<String>,
+}
+
+/// Fee schedule tier, as returned by the `asset_pairs` API.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(from = "RawFeeTier", into = "RawFeeTier")]
+pub struct FeeTier {
+    /// Minimum 30 days trade volume of the tier.
+    pub volume: Amount,
+    /// Fee in percent.
+    pub percent: Amount,
+}
+
+/// Fee schedule tier, as `[volume, percent]` array.
+#[derive(Serialize, Deserialize)]
+struct RawFeeTier(
+    #[serde(with = "serde_amount")] Amount,
+    #[serde(with = "serde_amount")] Amount,
+);
+
+impl From<RawFeeTier> for FeeTier {
+    fn from(RawFeeTier(volume, percent): RawFeeTier) -> Self {
+        Self { volume, percent }
+    }
+}
+
+impl From<FeeTier> for RawFeeTier {
+    fn from(tier: FeeTier) -> Self {
+        Self(tier.volume, tier.percent)
+    }
+}
+
+/// Asset pair info, as returned by the `asset_pairs` API.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PairInfo {
+    /// Alternate pair name.
+    pub altname: String,
+    /// Websockets pair name (if available).
+    #[serde(default)]
+    pub wsname: Option<String>,
+    /// Asset class of base component.
+    pub aclass_base: String,
+    /// Asset ID of base component.
+    pub base: Asset<'static>,
+    /// Asset class of quote component.
+    pub aclass_quote: String,
+    /// Asset ID of quote component.
+    pub quote: Asset<'static>,
+    /// Scaling decimal places for pair.
+    pub pair_decimals: u32,
+    /// Scaling decimal places for cost.
+    #[serde(default)]
+    pub cost_decimals: Option<u32>,
+    /// Scaling decimal places for volume.
+    pub lot_decimals: u32,
+    /// Amount to multiply lot volume by to get currency volume.
+    #[serde(default)]
+    pub lot_multiplier: Option<u32>,
+    /// Minimum price increment.
+    #[serde(default, with = "serde_opt_amount")]
+    pub tick_size: Option<Amount>,
+    /// Minimum order size (in terms of base currency).
+    #[serde(default, with = "serde_opt_amount")]
+    pub ordermin: Option<Amount>,
+    /// Minimum order cost (in terms of quote currency).
+    #[serde(default, with = "serde_opt_amount")]
+    pub costmin: Option<Amount>,
+    /// Taker fee schedule.
+    #[serde(default)]
+    pub fees: Vec<FeeTier>,
+    /// Maker fee schedule.
+    #[serde(default)]
+    pub fees_maker: Vec<FeeTier>,
+    /// Volume discount currency.
+    #[serde(default)]
+    pub fee_volume_currency: Option<String>,
+    /// Amounts of leverage available when buying.
+    #[serde(default)]
+    pub leverage_buy: Vec<u32>,
+    /// Amounts of leverage available when selling.
+    #[serde(default)]
+    pub leverage_sell: Vec<u32>,
+    /// Margin call level.
+    #[serde(default)]
+    pub margin_call: Option<u32>,
+    /// Stop-out/liquidation margin level.
+    #[serde(default)]
+    pub margin_stop: Option<u32>,
+    /// Status of asset pair (e.g. `online`).
+    #[serde(default)]
+    pub status: Option<String>,
+}
+
+impl PairInfo {
+    /// Gets the asset pair of base and quote assets.
+    pub fn asset_pair(&self) -> AssetPair<'static> {
+        self.base.clone().pair(self.quote.clone())
+    }
+
+    /// Rounds the given price to the nearest valid increment (the tick size
+    /// if available, the pair decimals otherwise).
+    pub fn round_price(&self, price: Amount) -> String {
+        let price = match self.tick_size {
+            Some(tick) if tick > Amount::default() => {
+                amount::round_to(price, tick)
+            }
+            _ => price,
+        };
+        format!("{:.*}", self.pair_decimals as usize, price)
+    }
+
+    /// Rounds the given volume down to the lot decimals, so that the order
+    /// never exceeds the given volume.
+    pub fn round_volume(&self, volume: Amount) -> String {
+        let volume = amount::floor_dp(volume, self.lot_decimals);
+        format!("{:.*}", self.lot_decimals as usize, volume)
+    }
+
+    /// Returns true only if an order of the given price and volume satisfies
+    /// the minimum order size and cost.
+    pub fn meets_minimums(&self, price: Amount, volume: Amount) -> bool {
+        let min_volume = self.ordermin.unwrap_or_default();
+        let min_cost = self.costmin.unwrap_or_default();
+        volume >= min_volume && price * volume >= min_cost
+    }
+
+    /// Gets the taker fee in percent for the given 30 days trade volume.
+    pub fn taker_fee(&self, volume: Amount) -> Option<Amount> {
+        fee(&self.fees, volume)
+    }
+
+    /// Gets the maker fee in percent for the given 30 days trade volume.
+    pub fn maker_fee(&self, volume: Amount) -> Option<Amount> {
+        fee(&self.fees_maker, volume)
+    }
+}
+
+/// Gets the fee in percent of the tier matching the given volume.
+fn fee(tiers: &[FeeTier], volume: Amount) -> Option<Amount> {
+    tiers
+        .iter()
+        .rev()
+        .find(|tier| tier.volume <= volume)
+        .map(|tier| tier.percent)
+}
+
+/// Registry of the Kraken assets and asset pairs.
+#[derive(Debug, Clone)]
+pub struct AssetRegistry {
+    /// The assets by REST name.
+    assets: HashMap<String, AssetInfo>,
+    /// The asset pairs by REST name.
+    pairs: HashMap<String, PairInfo>,
+    /// The REST asset names by any known (upper case) name.
+    asset_names: HashMap<String, String>,
+    /// The REST asset pair names by any known (upper case) name.
+    pair_names: HashMap<String, String>,
+    /// The REST asset pair names by base and quote REST asset names.
+    pair_assets: HashMap<(String, String), String>,
+    /// When the registry has been loaded.
+    loaded_at: Instant,
+}
+
+impl AssetRegistry {
+    /// Constructs a new registry of the given assets and asset pairs, both
+    /// keyed by REST name.
+    pub fn new(
+        assets: HashMap<String, AssetInfo>,
+        pairs: HashMap<String, PairInfo>,
+    ) -> Self {
+        let mut asset_names = HashMap::new();
+        for (name, info) in &assets {
+            asset_names.insert(name.to_uppercase(), name.clone());
+            asset_names.insert(info.altname.to_uppercase(), name.clone());
+        }
+        for (alias, altname) in ALIASES {
+            if let Some(name) = asset_names.get(*altname).cloned() {
+                asset_names.entry(alias.to_string()).or_insert(name);
+            }
+        }
+
+        let mut pair_names = HashMap::new();
+        let mut pair_assets = HashMap::new();
+        for (name, info) in &pairs {
+            pair_names.insert(name.to_uppercase(), name.clone());
+            pair_names.insert(info.altname.to_uppercase(), name.clone());
+            if let Some(wsname) = &info.wsname {
+                pair_names.insert(wsname.to_uppercase(), name.clone());
+            }
+            let key = (info.base.to_string(), info.quote.to_string());
+            pair_assets.insert(key, name.clone());
+        }
+
+        Self {
+            assets,
+            pairs,
+            asset_names,
+            pair_names,
+            pair_assets,
+            loaded_at: Instant::now(),
+        }
+    }
+
+    /// Loads the registry from the `assets` and `asset_pairs` APIs.
+    pub async fn load(client: &Client) -> Result<Self> {
+        let assets = client.send(api::public::assets()).await?;
+        let pairs = client.send(api::public::asset_pairs()).await?;
+        Ok(Self::new(result(assets)?, result(pairs)?))
+    }
+
+    /// Loads the registry from the `assets` and `asset_pairs` APIs.
+    pub fn load_blocking(client: &blocking::Client) -> Result<Self> {
+        let assets = client.send(api::public::assets())?;
+        let pairs = client.send(api::public::asset_pairs())?;
+        Ok(Self::new(result(assets)?, result(pairs)?))
+    }
+
+    /// Returns true only if the registry has been loaded more than the given
+    /// time ago, and should be loaded again.
+    pub fn is_stale(&self, max_age: Duration) -> bool {
+        self.loaded_at.elapsed() > max_age
+    }
+
+    /// Resolves the given asset name (REST name, alternate name or common
+    /// alias such as `BTC`) to its REST name and info.
+    pub fn asset(&self, name: &str) -> Option<(Asset<'static>, &AssetInfo)> {
+        let name = self.asset_names.get(&name.to_uppercase())?;
+        let info = self.assets.get(name)?;
+        Some((Asset::new(name.clone()), info))
+    }
+
+    /// Gets the REST name of the given asset.
+    pub fn canonical_asset(&self, asset: &Asset) -> Option<Asset<'static>> {
+        self.asset(&asset.to_string()).map(|(asset, _)| asset)
+    }
+
+    /// Resolves the given asset pair name (REST name, alternate name,
+    /// Websockets name or `BASE/QUOTE` with any known asset names) to its
+    /// REST name and info.
+    pub fn pair(&self, name: &str) -> Option<(&str, &PairInfo)> {
+        let upper = name.to_uppercase();
+        let name = match self.pair_names.get(&upper) {
+            Some(name) => name,
+            None => {
+                let (base, quote) = upper.split_once('/')?;
+                self.pair_assets.get(&(
+                    self.asset(base)?.0.to_string(),
+                    self.asset(quote)?.0.to_string(),
+                ))?
+            }
+        };
+        self.pairs
+            .get_key_value(name)
+            .map(|(name, info)| (name.as_str(), info))
+    }
+
+    /// Resolves the given asset pair, whose assets can have any known name,
+    /// to its REST name and info.
+    pub fn asset_pair(&self, pair: &AssetPair) -> Option<(&str, &PairInfo)> {
+        let base = self.canonical_asset(&pair.base)?;
+        let quote = self.canonical_asset(&pair.quote)?;
+        let name = self
+            .pair_assets
+            .get(&(base.to_string(), quote.to_string()))?;
+        self.pairs
+            .get_key_value(name)
+            .map(|(name, info)| (name.as_str(), info))
+    }
+
+    /// Gets the asset pair (with REST asset names) of the given asset pair
+    /// name.
+    pub fn canonical_pair(&self, name: &str) -> Option<AssetPair<'static>> {
+        self.pair(name).map(|(_, info)| info.asset_pair())
+    }
+
+    /// Gets all the assets by REST name.
+    pub fn assets(&self) -> &HashMap<String, AssetInfo> {
+        &self.assets
+    }
+
+    /// Gets all the asset pairs by REST name.
+    pub fn pairs(&self) -> &HashMap<String, PairInfo> {
+        &self.pairs
+    }
+}
+
+/// Gets the result of the given response.
+fn result<T: DeserializeOwned>(resp: Response<T>) -> Result<T> {
+    if !resp.error.is_empty() {
+        return Err(Error::Api(resp.error));
+    }
+    resp.result
+        .ok_or_else(|| Error::invalid_message("missing result"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::amount;
+    use anyhow::Result;
+    use serde_json::json;
+
+    fn registry() -> Result<AssetRegistry> {
+        let assets = json!({
+            "XXBT": {
+                "aclass": "currency",
+                "altname": "XBT",
+                "decimals": 10,
+                "display_decimals": 5,
+                "status": "enabled"
+            },
+            "ZEUR": {
+                "aclass": "currency",
+                "altname": "EUR",
+                "decimals": 4,
+                "display_decimals": 2
+            }
+        });
+        let pairs = json!({
+            "XXBTZEUR": {
+                "altname": "XBTEUR",
+                "wsname": "XBT/EUR",
+                "aclass_base": "currency",
+                "base": "XXBT",
+                "aclass_quote": "currency",
+                "quote": "ZEUR",
+                "pair_decimals": 1,
+                "cost_decimals": 5,
+                "lot_decimals": 8,
+                "lot_multiplier": 1,
+                "leverage_buy": [2, 3, 4, 5],
+                "leverage_sell": [2, 3, 4, 5],
+                "fees": [[0, 0.26], [50000, 0.24], [100000, 0.22]],
+                "fees_maker": [[0, 0.16], [50000, 0.14], [100000, 0.12]],
+                "fee_volume_currency": "ZUSD",
+                "margin_call": 80,
+                "margin_stop": 40,
+                "ordermin": "0.0001",
+                "costmin": "0.5",
+                "tick_size": "0.1",
+                "status": "online"
+            }
+        });
+
+        Ok(AssetRegistry::new(
+            serde_json::from_value(assets)?,
+            serde_json::from_value(pairs)?,
+        ))
+    }
+
+    #[test]
+    fn resolve_assets() -> Result<()> {
+        let registry = registry()?;
+
+        for name in &["XXBT", "XBT", "BTC", "btc"] {
+            let (asset, info) = registry.asset(name).expect("asset");
+            assert_eq!(asset, Asset::new("XXBT"));
+            assert_eq!(info.decimals, 10);
+            assert_eq!(info.status.as_deref(), Some("enabled"));
+        }
+        assert_eq!(
+            registry.canonical_asset(&Asset::new("EUR")),
+            Some(Asset::new("ZEUR"))
+        );
+        assert!(registry.asset("ETH").is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_pairs() -> Result<()> {
+        let registry = registry()?;
+
+        for name in &["XXBTZEUR", "XBTEUR", "XBT/EUR", "BTC/EUR", "btc/zeur"] {
+            let (pair, info) = registry.pair(name).expect("pair");
+            assert_eq!(pair, "XXBTZEUR");
+            assert_eq!(info.wsname.as_deref(), Some("XBT/EUR"));
+        }
+
+        let pair = Asset::new("BTC").pair("EUR");
+        let (name, _) = registry.asset_pair(&pair).expect("pair");
+        assert_eq!(name, "XXBTZEUR");
+        assert_eq!(
+            registry.canonical_pair("XBT/EUR"),
+            Some(Asset::new("XXBT").pair("ZEUR"))
+        );
+        assert!(registry.pair("ETH/EUR").is_none());
+        assert!(!registry.is_stale(Duration::from_secs(60)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn pair_info() -> Result<()> {
+        let registry = registry()?;
+        let (_, info) = registry.pair("XBT/EUR").expect("pair");
+
+        assert_eq!(info.leverage_buy, vec![2, 3, 4, 5]);
+        assert_eq!(info.margin_call, Some(80));
+        assert_eq!(info.taker_fee(amount("0")), Some(amount("0.26")));
+        assert_eq!(info.taker_fee(amount("75000")), Some(amount("0.24")));
+        assert_eq!(info.maker_fee(amount("1000000")), Some(amount("0.12")));
+        assert_eq!(info.maker_fee(amount("-1")), None);
+
+        assert_eq!(info.round_price(amount("30123.456")), "30123.5");
+        assert_eq!(info.round_price(amount("30123.44")), "30123.4");
+        assert_eq!(info.round_volume(amount("0.123456789")), "0.12345678");
+        assert_eq!(info.round_volume(amount("0.29")), "0.29000000");
+
+        assert!(info.meets_minimums(amount("30000"), amount("0.0001")));
+        assert!(!info.meets_minimums(amount("30000"), amount("0.00001")));
+        assert!(!info.meets_minimums(amount("1000"), amount("0.0001")));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,812 @@
+//! Pre-trade risk guard.
+//!
+//! A client configured with the `RiskGuard` checks each order placement
+//! (`add_order`, `add_order_batch` and `edit_order`) against the guard limits
+//! before signing and sending it, and fails with an `Error::Risk` of the
+//! violated limit: the maximum notional of each order and of the open orders
+//! of each pair, the maximum number of open orders, the allowed pairs, and
+//! the price band around the last price of the pair. Each order of a batch
+//! (given with the `orders[<index>][<parameter>]` parameters) is checked as
+//! a single order, and the whole batch is rejected if any order is. The kill
+//! switch blocks all the order placements, while still allowing the cancels.
+//!
+//! The guard does not query the exchange: the last prices, the number of
+//! open orders and the notional of the open orders of each pair are supplied
+//! with `set_last_price`, `set_open_orders` and `set_pair_notional` (e.g.
+//! from the `ticker` and `open_orders` APIs or the websocket feeds). Each new
+//! order passing the checks is counted as open while it is sent, and is only
+//! kept once accepted by the exchange. The order edits replace an open order:
+//! they are not counted, they are not subject to the maximum number of open
+//! orders and to the pair notional, and their notional is only checked when
+//! the edit includes the volume.
+//!
+//! The pairs are matched by their assets, so that the legacy and alternate
+//! names of a pair (e.g. `XXBTZEUR` and `XBTEUR`) share the same limits and
+//! last price. The names that cannot be split into assets (e.g. `USDCUSD`)
+//! are matched exactly.
+//!
+//! ```
+//! use akkorokamui::{
+//!     api, blocking::Client, risk::RiskGuard, sim::SimExchange, Error,
+//!     ResponseValue,
+//! };
+//! use anyhow::Result;
+//!
+//! fn main() -> Result<()> {
+//!     let guard = RiskGuard::new()
+//!         .with_allowed_pairs(vec!["XXBTZEUR"])
+//!         .with_max_notional("5000".parse()?)
+//!         .with_price_band("0.05".parse()?);
+//!     guard.set_last_price("XXBTZEUR", "30000".parse()?);
+//!
+//!     let user_agent = "<product>/<product-version>";
+//!     let client = Client::new(user_agent)?
+//!         .with_sim(SimExchange::new())
+//!         .with_risk_guard(guard.clone());
+//!
+//!     let api = api::private::add_order()
+//!         .with("pair", "XXBTZEUR")
+//!         .with("type", "buy")
+//!         .with("ordertype", "limit")
+//!         .with("price", "30000")
+//!         .with("volume", "1");
+//!     let resp: Result<ResponseValue, Error> = client.send(api);
+//!     assert!(matches!(resp, Err(Error::Risk(_))));
+//!
+//!     guard.kill();
+//!     let resp: ResponseValue = client.send(api::private::cancel_all())?;
+//!     println!("{:?}", resp);
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    sync::{Arc, Mutex, MutexGuard},
+};
+use thiserror::Error;
+
+use crate::{
+    Amount, Api, AssetPair, Error, RawResponse, ResponseValue, Result,
+};
+
+/// Limit violated by an order placement.
+#[derive(Error, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Violation {
+    #[error("order placement halted by the kill switch")]
+    Killed,
+    #[error("pair {0} not allowed")]
+    PairNotAllowed(String),
+    #[error("order notional {notional} above the limit {limit}")]
+    MaxNotional { notional: String, limit: String },
+    #[error("{pair} open orders notional {notional} above the limit {limit}")]
+    MaxPairNotional {
+        pair: String,
+        notional: String,
+        limit: String,
+    },
+    #[error("{open} open orders, at the limit {limit}")]
+    MaxOpenOrders { open: usize, limit: usize },
+    #[error("price {price} outside the band around the last price {last}")]
+    PriceBand { price: String, last: String },
+    #[error("no last price of pair {0}")]
+    NoLastPrice(String),
+    #[error("invalid order: {0}")]
+    InvalidOrder(String),
+}
+
+/// Limits of the guard.
+#[derive(Debug, Clone, Default)]
+struct Limits {
+    /// The maximum notional of each order.
+    max_notional: Option<Amount>,
+    /// The maximum notional of the open orders of each pair.
+    pair_notional: HashMap<String, Amount>,
+    max_open_orders: Option<usize>,
+    /// The allowed pairs, or all pairs if none.
+    allowed_pairs: Option<HashSet<String>>,
+    /// The maximum distance from the last price, as fraction of it.
+    price_band: Option<Amount>,
+}
+
+/// State fed to the guard.
+#[derive(Debug, Default)]
+struct State {
+    killed: bool,
+    open_orders: usize,
+    /// The notional of the open orders of each pair.
+    pair_notional: HashMap<String, Amount>,
+    last_prices: HashMap<String, Amount>,
+}
+
+/// The new orders of a placement, counted as open until its response.
+#[derive(Debug, Default)]
+pub(crate) struct Reservation {
+    /// The pair and the notional, if known, of each order.
+    orders: Vec<(String, Option<Amount>)>,
+}
+
+impl Reservation {
+    /// Gets the notional of the orders of the given pair.
+    fn pair_notional(&self, pair: &str) -> Amount {
+        self.orders
+            .iter()
+            .filter(|(p, _)| p == pair)
+            .filter_map(|(_, notional)| *notional)
+            .sum()
+    }
+}
+
+/// The parameters of a single order, or of an order of a batch.
+struct OrderParams<'a> {
+    params: &'a HashMap<String, String>,
+    /// The index of the order in the batch, if any.
+    index: Option<usize>,
+}
+
+impl<'a> OrderParams<'a> {
+    /// Gets the given order parameter, or the batch one (e.g. `pair`).
+    fn get(&self, name: &str) -> Option<&'a str> {
+        self.index
+            .and_then(|i| self.params.get(&format!("orders[{}][{}]", i, name)))
+            .or_else(|| self.params.get(name))
+            .map(String::as_str)
+    }
+}
+
+/// Pre-trade checks of the order placements.
+#[derive(Debug, Clone, Default)]
+pub struct RiskGuard {
+    limits: Limits,
+    state: Arc<Mutex<State>>,
+}
+
+impl RiskGuard {
+    /// Constructs a new guard without limits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the maximum notional (volume times price, in quote asset) of
+    /// each order.
+    pub fn with_max_notional(mut self, limit: Amount) -> Self {
+        self.limits.max_notional = Some(limit);
+        self
+    }
+
+    /// Sets the maximum notional of all the open orders of the given pair,
+    /// including the new order.
+    pub fn with_pair_notional(mut self, pair: &str, limit: Amount) -> Self {
+        self.limits.pair_notional.insert(normalize(pair), limit);
+        self
+    }
+
+    /// Sets the maximum number of open orders.
+    pub fn with_max_open_orders(mut self, limit: usize) -> Self {
+        self.limits.max_open_orders = Some(limit);
+        self
+    }
+
+    /// Only allows the orders of the given pairs.
+    pub fn with_allowed_pairs<'a>(
+        mut self,
+        pairs: impl IntoIterator<Item = &'a str>,
+    ) -> Self {
+        let pairs = pairs.into_iter().map(normalize).collect();
+        self.limits.allowed_pairs = Some(pairs);
+        self
+    }
+
+    /// Only allows the limit prices within the given fraction (e.g. 0.05 for
+    /// 5%) of the last price of the pair, rejecting the orders of the pairs
+    /// without last price.
+    pub fn with_price_band(mut self, band: Amount) -> Self {
+        self.limits.price_band = Some(band);
+        self
+    }
+
+    /// Sets the last price of the given pair, used for the price band and
+    /// the notional of the market orders.
+    pub fn set_last_price(&self, pair: &str, price: Amount) {
+        self.lock().last_prices.insert(normalize(pair), price);
+    }
+
+    /// Sets the current number of open orders.
+    pub fn set_open_orders(&self, open_orders: usize) {
+        self.lock().open_orders = open_orders;
+    }
+
+    /// Gets the number of open orders, including the orders placed since
+    /// the last `set_open_orders`.
+    pub fn open_orders(&self) -> usize {
+        self.lock().open_orders
+    }
+
+    /// Sets the current notional of the open orders of the given pair.
+    pub fn set_pair_notional(&self, pair: &str, notional: Amount) {
+        self.lock().pair_notional.insert(normalize(pair), notional);
+    }
+
+    /// Gets the notional of the open orders of the given pair, including
+    /// the orders placed since the last `set_pair_notional`.
+    pub fn pair_notional(&self, pair: &str) -> Amount {
+        let state = self.lock();
+        state
+            .pair_notional
+            .get(&normalize(pair))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Blocks all the order placements, still allowing the cancels.
+    pub fn kill(&self) {
+        self.lock().killed = true;
+    }
+
+    /// Allows the order placements again.
+    pub fn resume(&self) {
+        self.lock().killed = false;
+    }
+
+    /// Returns true only if the kill switch is active.
+    pub fn is_killed(&self) -> bool {
+        self.lock().killed
+    }
+
+    /// Checks the given API against the limits, if an order placement, and
+    /// counts its new orders as open unless only validated.
+    pub(crate) fn check(&self, api: &Api) -> Result<Reservation> {
+        let (batched, edit) = match api.inner.method.as_str() {
+            "AddOrder" => (false, false),
+            "EditOrder" => (false, true),
+            "AddOrderBatch" => (true, false),
+            _ => return Ok(Reservation::default()),
+        };
+
+        let mut state = self.lock();
+        if state.killed {
+            return Err(Error::Risk(Violation::Killed));
+        }
+        let params = &api.inner.params;
+        let orders = if batched {
+            batch(params).map_err(Error::Risk)?
+        } else {
+            vec![OrderParams {
+                params,
+                index: None,
+            }]
+        };
+        let mut reservation = Reservation::default();
+        for order in &orders {
+            let reserved = self
+                .check_order(&state, &reservation, order, edit)
+                .map_err(Error::Risk)?;
+            if !edit {
+                reservation.orders.push(reserved);
+            }
+        }
+
+        let validate =
+            params.get("validate").map(String::as_str) == Some("true");
+        if validate {
+            return Ok(Reservation::default());
+        }
+        for (pair, notional) in &reservation.orders {
+            state.open_orders += 1;
+            if let Some(notional) = notional {
+                *state.pair_notional.entry(pair.clone()).or_default() +=
+                    *notional;
+            }
+        }
+        Ok(reservation)
+    }
+
+    /// Keeps the reserved orders accepted by the given response, if any, as
+    /// open, releasing the rejected ones.
+    pub(crate) fn settle(
+        &self,
+        reservation: Reservation,
+        response: Option<&RawResponse>,
+    ) {
+        if reservation.orders.is_empty() {
+            return;
+        }
+        let accepted = accepted(reservation.orders.len(), response);
+
+        let mut state = self.lock();
+        for ((pair, notional), accepted) in
+            reservation.orders.into_iter().zip(accepted)
+        {
+            if accepted {
+                continue;
+            }
+            state.open_orders = state.open_orders.saturating_sub(1);
+            if let (Some(open), Some(notional)) =
+                (state.pair_notional.get_mut(&pair), notional)
+            {
+                *open = if *open > notional {
+                    *open - notional
+                } else {
+                    Amount::default()
+                };
+            }
+        }
+    }
+
+    /// Checks the new order (or the edit of an open order) with the given
+    /// parameters, after the orders already reserved by its batch, returning
+    /// its pair and notional.
+    fn check_order(
+        &self,
+        state: &State,
+        reserved: &Reservation,
+        order: &OrderParams,
+        edit: bool,
+    ) -> std::result::Result<(String, Option<Amount>), Violation> {
+        let pair = order
+            .get("pair")
+            .ok_or_else(|| Violation::InvalidOrder("missing pair".into()))?;
+        let key = normalize(pair);
+        if let Some(allowed) = &self.limits.allowed_pairs {
+            if !allowed.contains(&key) {
+                return Err(Violation::PairNotAllowed(pair.to_string()));
+            }
+        }
+
+        if let Some(limit) = self.limits.max_open_orders.filter(|_| !edit) {
+            let open = state.open_orders + reserved.orders.len();
+            if open >= limit {
+                return Err(Violation::MaxOpenOrders { open, limit });
+            }
+        }
+
+        let last = state.last_prices.get(&key).copied();
+        let price =
+            order.get("price").map(|p| parse(p, "price")).transpose()?;
+        if let Some(band) = self.limits.price_band {
+            let last =
+                last.ok_or_else(|| Violation::NoLastPrice(pair.to_string()))?;
+            if let Some(price) = price {
+                let distance = if price > last {
+                    price - last
+                } else {
+                    last - price
+                };
+                if distance > last * band {
+                    return Err(Violation::PriceBand {
+                        price: price.to_string(),
+                        last: last.to_string(),
+                    });
+                }
+            }
+        }
+
+        let volume = order
+            .get("volume")
+            .map(|v| parse(v, "volume"))
+            .transpose()?;
+        // the market orders are valued at the last price
+        let price = match order.get("ordertype") {
+            Some("market") => last,
+            _ => price.or(last),
+        };
+        let notional = match (volume, price) {
+            (Some(volume), Some(price)) => Some(volume * price),
+            _ => None,
+        };
+        let required = || match (volume, notional) {
+            (None, _) => Err(Violation::InvalidOrder("missing volume".into())),
+            (_, None) => Err(Violation::NoLastPrice(pair.to_string())),
+            (_, Some(notional)) => Ok(notional),
+        };
+
+        // the volume of the order is unknown when only its price is edited
+        if let Some(limit) = self.limits.max_notional {
+            if !edit || volume.is_some() {
+                let notional = required()?;
+                if notional > limit {
+                    return Err(Violation::MaxNotional {
+                        notional: notional.to_string(),
+                        limit: limit.to_string(),
+                    });
+                }
+            }
+        }
+
+        if let Some(&limit) = self.limits.pair_notional.get(&key) {
+            if !edit {
+                let open =
+                    state.pair_notional.get(&key).copied().unwrap_or_default();
+                let notional =
+                    open + reserved.pair_notional(&key) + required()?;
+                if notional > limit {
+                    return Err(Violation::MaxPairNotional {
+                        pair: pair.to_string(),
+                        notional: notional.to_string(),
+                        limit: limit.to_string(),
+                    });
+                }
+            }
+        }
+
+        Ok((key, notional))
+    }
+
+    /// Locks the guard state, even if poisoned.
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Gets the parameters of the orders of the given batch, in order.
+fn batch(
+    params: &HashMap<String, String>,
+) -> std::result::Result<Vec<OrderParams<'_>>, Violation> {
+    let indexes: BTreeSet<usize> = params
+        .keys()
+        .filter_map(|key| key.strip_prefix("orders["))
+        .filter_map(|key| key.split_once(']'))
+        .map(|(index, _)| {
+            index.parse().map_err(|_| {
+                Violation::InvalidOrder(format!(
+                    "invalid order index {}",
+                    index
+                ))
+            })
+        })
+        .collect::<std::result::Result<_, _>>()?;
+    if indexes.is_empty() {
+        return Err(Violation::InvalidOrder("missing orders".into()));
+    }
+    Ok(indexes
+        .into_iter()
+        .map(|index| OrderParams {
+            params,
+            index: Some(index),
+        })
+        .collect())
+}
+
+/// Gets whether each of the given number of orders has been accepted by the
+/// given response, where the orders of a batch are rejected one by one.
+fn accepted(count: usize, response: Option<&RawResponse>) -> Vec<bool> {
+    let resp = response
+        .filter(|raw| raw.is_success())
+        .and_then(|raw| raw.json::<ResponseValue>().ok());
+    let resp = match resp {
+        Some(resp) if resp.error.is_empty() => resp,
+        _ => return vec![false; count],
+    };
+    let orders = resp.get("orders").and_then(Value::as_array);
+    (0..count)
+        .map(|i| match orders.and_then(|orders| orders.get(i)) {
+            Some(order) => order.get("error").is_none(),
+            None => true,
+        })
+        .collect()
+}
+
+/// Gets the name of the given pair matching its legacy and alternate names
+/// (e.g. `XBT/EUR` for `XXBTZEUR` and `XBTEUR`), or the name itself if it
+/// cannot be split into assets.
+fn normalize(pair: &str) -> String {
+    let assets: AssetPair = match pair.parse() {
+        Ok(assets) => assets,
+        Err(_) => return pair.to_string(),
+    };
+    let (base, quote) = (assets.base.to_string(), assets.quote.to_string());
+    // only the legacy names are split into two four characters assets
+    let legacy = !pair.contains('/') && base.len() == 4 && quote.len() == 4;
+    if legacy {
+        format!("{}/{}", &base[1..], &quote[1..])
+    } else {
+        format!("{}/{}", base, quote)
+    }
+}
+
+/// Parses the given order parameter.
+fn parse(value: &str, name: &str) -> std::result::Result<Amount, Violation> {
+    value.parse().map_err(|_| {
+        Violation::InvalidOrder(format!("invalid {}: {}", name, value))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        amount::amount,
+        api::{self, ApiBuilder},
+        blocking::Client,
+        client,
+        sim::SimExchange,
+    };
+    use reqwest::header::HeaderMap;
+    use serde_json::json;
+
+    fn order(ordertype: &str, volume: &str, price: Option<&str>) -> Api {
+        let mut api: ApiBuilder = api::private::add_order()
+            .with("pair", "XXBTZEUR")
+            .with("type", "buy")
+            .with("ordertype", ordertype)
+            .with("volume", volume);
+        if let Some(price) = price {
+            api.with_mut("price", price);
+        }
+        api.into()
+    }
+
+    fn response(body: Value) -> RawResponse {
+        RawResponse {
+            status_code: 200,
+            headers: HeaderMap::new(),
+            body: body.to_string().into_bytes(),
+        }
+    }
+
+    fn violation(guard: &RiskGuard, api: &Api) -> Option<Violation> {
+        match guard.check(api) {
+            Err(Error::Risk(violation)) => Some(violation),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn order_limits() {
+        let guard = RiskGuard::new()
+            .with_allowed_pairs(vec!["XXBTZEUR"])
+            .with_max_notional(amount("1000"))
+            .with_pair_notional("XETHZEUR", amount("10"))
+            .with_price_band(amount("0.1"));
+
+        let limit = order("limit", "0.1", Some("9000"));
+        assert_eq!(
+            violation(&guard, &limit),
+            Some(Violation::NoLastPrice("XXBTZEUR".into()))
+        );
+
+        guard.set_last_price("XXBTZEUR", amount("10000"));
+        assert!(guard.check(&limit).is_ok());
+        assert!(matches!(
+            violation(&guard, &order("limit", "0.1", Some("8999"))),
+            Some(Violation::PriceBand { .. })
+        ));
+        assert!(matches!(
+            violation(&guard, &order("market", "0.11", None)),
+            Some(Violation::MaxNotional { .. })
+        ));
+
+        let eth: Api = api::private::add_order()
+            .with("pair", "XETHZEUR")
+            .with("volume", "1")
+            .into();
+        assert_eq!(
+            violation(&guard, &eth),
+            Some(Violation::PairNotAllowed("XETHZEUR".into()))
+        );
+    }
+
+    #[test]
+    fn open_orders_and_kill_switch() {
+        let guard = RiskGuard::new().with_max_open_orders(2);
+        guard.set_open_orders(1);
+
+        let validate: Api = api::private::add_order()
+            .with("pair", "XXBTZEUR")
+            .with("validate", true)
+            .into();
+        assert!(guard.check(&validate).is_ok());
+        assert_eq!(guard.open_orders(), 1);
+
+        let limit = order("limit", "0.1", Some("9000"));
+        assert!(guard.check(&limit).is_ok());
+        assert_eq!(
+            violation(&guard, &limit),
+            Some(Violation::MaxOpenOrders { open: 2, limit: 2 })
+        );
+
+        guard.set_open_orders(0);
+        guard.kill();
+        assert_eq!(violation(&guard, &limit), Some(Violation::Killed));
+        assert!(guard.check(&api::private::cancel_all().into()).is_ok());
+
+        guard.resume();
+        assert!(guard.check(&limit).is_ok());
+    }
+
+    #[test]
+    fn order_edits() {
+        let guard = RiskGuard::new()
+            .with_max_open_orders(1)
+            .with_max_notional(amount("1000"));
+        guard.set_last_price("XXBTZEUR", amount("10000"));
+        guard.set_open_orders(1);
+
+        let edit = |volume: Option<&str>| -> Api {
+            let mut api = api::private::edit_order()
+                .with("pair", "XXBTZEUR")
+                .with("txid", "OUF4EM-FRGI2-MQMWZD")
+                .with("price", "9000");
+            if let Some(volume) = volume {
+                api.with_mut("volume", volume);
+            }
+            api.into()
+        };
+        // the edits replace an open order, at the limit
+        assert!(guard.check(&edit(None)).is_ok());
+        assert!(guard.check(&edit(Some("0.1"))).is_ok());
+        assert_eq!(guard.open_orders(), 1);
+        assert!(matches!(
+            violation(&guard, &edit(Some("0.2"))),
+            Some(Violation::MaxNotional { .. })
+        ));
+    }
+
+    #[test]
+    fn pair_names() {
+        assert_eq!(normalize("XXBTZEUR"), "XBT/EUR");
+        assert_eq!(normalize("XBTEUR"), "XBT/EUR");
+        assert_eq!(normalize("XBT/EUR"), "XBT/EUR");
+        assert_eq!(normalize("USDCUSD"), "USDCUSD");
+
+        let guard = RiskGuard::new()
+            .with_allowed_pairs(vec!["XXBTZEUR"])
+            .with_pair_notional("XBT/EUR", amount("500"));
+        guard.set_last_price("XBTEUR", amount("10000"));
+
+        let order: Api = api::private::add_order()
+            .with("pair", "XBTEUR")
+            .with("ordertype", "market")
+            .with("volume", "0.1")
+            .into();
+        assert_eq!(
+            violation(&guard, &order),
+            Some(Violation::MaxPairNotional {
+                pair: "XBTEUR".into(),
+                notional: (amount("0.1") * amount("10000")).to_string(),
+                limit: amount("500").to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn pair_notional() {
+        let guard = RiskGuard::new()
+            .with_max_notional(amount("1000"))
+            .with_pair_notional("XXBTZEUR", amount("2500"));
+        guard.set_last_price("XXBTZEUR", amount("10000"));
+
+        let market = order("market", "0.1", None);
+        let accepted = guard.check(&market).expect("order rejected");
+        guard.settle(accepted, Some(&response(json!({"error": []}))));
+        let rejected = guard.check(&market).expect("order rejected");
+        assert_eq!(guard.pair_notional("XBTEUR"), amount("2000"));
+        assert_eq!(guard.open_orders(), 2);
+        assert_eq!(
+            violation(&guard, &market),
+            Some(Violation::MaxPairNotional {
+                pair: "XXBTZEUR".into(),
+                notional: (amount("2000") + amount("0.1") * amount("10000"))
+                    .to_string(),
+                limit: amount("2500").to_string(),
+            })
+        );
+
+        // only the orders accepted by the exchange are kept
+        let errors = json!({"error": ["EOrder:Insufficient funds"]});
+        guard.settle(rejected, Some(&response(errors)));
+        assert_eq!(guard.pair_notional("XXBTZEUR"), amount("1000"));
+        assert_eq!(guard.open_orders(), 1);
+        let failed = guard.check(&market).expect("order rejected");
+        guard.settle(failed, None);
+        assert_eq!(guard.open_orders(), 1);
+
+        guard.set_pair_notional("XXBTZEUR", amount("0"));
+        assert!(guard.check(&order("market", "0.1", None)).is_ok());
+    }
+
+    #[test]
+    fn batch_orders() {
+        let guard = RiskGuard::new()
+            .with_max_open_orders(3)
+            .with_max_notional(amount("1000"));
+        guard.set_open_orders(1);
+
+        let batch = |volumes: &[&str]| -> Api {
+            let mut api =
+                api::private::add_order_batch().with("pair", "XXBTZEUR");
+            for (i, volume) in volumes.iter().enumerate() {
+                api.with_mut(format!("orders[{}][type]", i), "buy")
+                    .with_mut(format!("orders[{}][ordertype]", i), "limit")
+                    .with_mut(format!("orders[{}][price]", i), "9000")
+                    .with_mut(format!("orders[{}][volume]", i), volume);
+            }
+            api.into()
+        };
+        assert!(matches!(
+            violation(&guard, &batch(&["0.1", "0.2"])),
+            Some(Violation::MaxNotional { .. })
+        ));
+        assert_eq!(
+            violation(&guard, &batch(&["0.1", "0.1", "0.1"])),
+            Some(Violation::MaxOpenOrders { open: 3, limit: 3 })
+        );
+        assert_eq!(
+            violation(&guard, &api::private::add_order_batch().into()),
+            Some(Violation::InvalidOrder("missing orders".into()))
+        );
+        assert_eq!(guard.open_orders(), 1);
+
+        // the orders of a batch are accepted one by one
+        let reservation = guard
+            .check(&batch(&["0.1", "0.1"]))
+            .expect("batch rejected");
+        assert_eq!(guard.open_orders(), 3);
+        let orders = json!({"error": [], "result": {"orders": [
+            {"txid": "OUF4EM-FRGI2-MQMWZD"},
+            {"error": "EOrder:Insufficient funds"}
+        ]}});
+        guard.settle(reservation, Some(&response(orders)));
+        assert_eq!(guard.open_orders(), 2);
+
+        guard.kill();
+        assert_eq!(
+            violation(&guard, &batch(&["0.1"])),
+            Some(Violation::Killed)
+        );
+    }
+
+    #[test]
+    fn client_orders() -> anyhow::Result<()> {
+        let guard = RiskGuard::new().with_max_open_orders(1);
+        let sim = SimExchange::new()
+            .with_balance("ZEUR", amount("100"))
+            .with_balance("XXBT", amount("1"));
+        sim.set_price("XXBTZEUR", amount("10000"))?;
+        let client = Client::new(client::user_agent())?
+            .with_sim(sim)
+            .with_risk_guard(guard.clone());
+
+        let limit = |side: &str| {
+            api::private::add_order()
+                .with("pair", "XXBTZEUR")
+                .with("type", side)
+                .with("ordertype", "limit")
+                .with("price", "10500")
+                .with("volume", "0.1")
+        };
+        // the order rejected by the exchange is not counted as open
+        let resp: ResponseValue = client.send(limit("buy"))?;
+        assert!(!resp.error.is_empty());
+        assert_eq!(guard.open_orders(), 0);
+
+        let resp: ResponseValue = client.send(limit("sell"))?;
+        assert!(resp.error.is_empty());
+        assert_eq!(guard.open_orders(), 1);
+        assert!(matches!(
+            client.send::<_, Value>(limit("sell")),
+            Err(Error::Risk(Violation::MaxOpenOrders { .. }))
+        ));
+
+        guard.kill();
+        let batch = api::private::add_order_batch()
+            .with("pair", "XXBTZEUR")
+            .with("orders[0][volume]", "0.1");
+        assert!(matches!(
+            client.send::<_, Value>(batch),
+            Err(Error::Risk(Violation::Killed))
+        ));
+        let edit = api::private::edit_order()
+            .with("pair", "XXBTZEUR")
+            .with("txid", "OUF4EM-FRGI2-MQMWZD");
+        assert!(matches!(
+            client.send::<_, Value>(edit),
+            Err(Error::Risk(Violation::Killed))
+        ));
+
+        Ok(())
+    }
+}
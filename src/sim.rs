@@ -0,0 +1,1066 @@
+//! Paper trading simulated exchange.
+//!
+//! The `SimExchange` answers the trading APIs (`add_order`, `cancel_order`,
+//! `cancel_all`, `open_orders`, `closed_orders`, `query_orders`, `balance`
+//! and `trades_history`) in the Kraken format, without sending any request.
+//!
+//! Orders are matched against the supplied prices (`set_price`) or depth
+//! snapshots (`set_depth`) of their pair: market orders and crossing limit
+//! orders are filled as taker against the book, the other limit orders rest
+//! until the book crosses them and are then filled as maker at their limit
+//! price. Fees are charged in the quote asset, and the balances are keyed by
+//! the asset names of the order pairs (e.g. `XXBT` and `ZEUR` for the
+//! `XXBTZEUR` pair).
+//!
+//! Without the assets list, the pair names are split as the `AssetPair`
+//! parser does: only the Websockets names (e.g. `XBT/EUR`), the legacy names
+//! (e.g. `XXBTZEUR`) and the names of two three characters assets (e.g.
+//! `XBTEUR`) are accepted, and `XBTEUR` uses the `XBT` and `EUR` balances
+//! instead of the `XXBT` and `ZEUR` ones. Other names (e.g. `XBTUSDT` or
+//! `USDCUSD`), or names that should share the same book and balances, are
+//! mapped to their assets with `with_pair`.
+//!
+//! A client configured with the `SimExchange` sends all its private requests
+//! to it, so that the same strategy runs unchanged against the simulated or
+//! the live exchange. The private APIs not simulated are rejected as unknown
+//! methods (they never reach the live account), while the public APIs (e.g.
+//! `ticker` or `depth`) are sent as usual, or replayed from the client
+//! cassette.
+//!
+//! ```
+//! use akkorokamui::{api, blocking::Client, sim::SimExchange, ResponseValue};
+//! use anyhow::Result;
+//!
+//! fn main() -> Result<()> {
+//!     let sim = SimExchange::new().with_balance("ZEUR", "10000".parse()?);
+//!     sim.set_price("XXBTZEUR", "30000".parse()?)?;
+//!
+//!     let user_agent = "<product>/<product-version>";
+//!     let client = Client::new(user_agent)?.with_sim(sim.clone());
+//!
+//!     let api = api::private::add_order()
+//!         .with("pair", "XXBTZEUR")
+//!         .with("type", "buy")
+//!         .with("ordertype", "market")
+//!         .with("volume", "0.1");
+//!     let resp: ResponseValue = client.send(api)?;
+//!     println!("{:?}", resp);
+//!
+//!     println!("XXBT balance: {}", sim.balance("XXBT"));
+//!     Ok(())
+//! }
+//! ```
+
+use reqwest::header::HeaderMap;
+use serde_json::{json, Map, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::{
+    book::{Depth, Level},
+    history::{OrderDescription, OrderInfo, TradeInfo},
+    timestamp::Timestamp,
+    Amount, Api, Asset, AssetPair, Error, RawResponse, Result,
+};
+
+/// Default maker fee, in percent.
+const MAKER_FEE: &str = "0.16";
+
+/// Default taker fee, in percent.
+const TAKER_FEE: &str = "0.26";
+
+/// Number of entries per page of the history APIs.
+const PAGE_SIZE: usize = 50;
+
+/// Side of an order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OrderSide {
+    Buy,
+    Sell,
+}
+
+impl fmt::Display for OrderSide {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let side = match self {
+            Self::Buy => "buy",
+            Self::Sell => "sell",
+        };
+        write!(f, "{}", side)
+    }
+}
+
+impl OrderSide {
+    /// Returns true only if an order of this side with the given limit price
+    /// can be filled at the given price.
+    fn crosses(self, limit: Amount, price: Amount) -> bool {
+        match self {
+            Self::Buy => price <= limit,
+            Self::Sell => price >= limit,
+        }
+    }
+}
+
+/// Volume available at a price.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Liquidity {
+    price: Amount,
+    /// The available volume, unlimited if none.
+    volume: Option<Amount>,
+}
+
+/// Simulated book of a pair.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Market {
+    /// Ask levels sorted by ascending price.
+    asks: Vec<Liquidity>,
+    /// Bid levels sorted by descending price.
+    bids: Vec<Liquidity>,
+}
+
+impl Market {
+    /// Constructs a book with unlimited volume at the given price.
+    fn with_price(price: Amount) -> Self {
+        let level = Liquidity {
+            price,
+            volume: None,
+        };
+        Self {
+            asks: vec![level],
+            bids: vec![level],
+        }
+    }
+
+    /// Constructs a book from the given depth snapshot.
+    fn with_depth(depth: &Depth) -> Result<Self> {
+        let levels = |levels: &[Level]| -> Result<Vec<Liquidity>> {
+            levels
+                .iter()
+                .map(|level| {
+                    Ok(Liquidity {
+                        price: parse_level(&level.price)?,
+                        volume: Some(parse_level(&level.volume)?),
+                    })
+                })
+                .collect()
+        };
+        Ok(Self {
+            asks: levels(&depth.asks)?,
+            bids: levels(&depth.bids)?,
+        })
+    }
+
+    /// Takes up to the given volume from the levels an order of the given
+    /// side can be filled at, returning the filled prices and volumes.
+    fn take(
+        &mut self,
+        side: OrderSide,
+        volume: Amount,
+        limit: Option<Amount>,
+    ) -> Vec<(Amount, Amount)> {
+        let levels = match side {
+            OrderSide::Buy => &mut self.asks,
+            OrderSide::Sell => &mut self.bids,
+        };
+
+        let zero = Amount::default();
+        let mut fills = Vec::new();
+        let mut remaining = volume;
+        while remaining > zero {
+            let level = match levels.first_mut() {
+                Some(level) => level,
+                None => break,
+            };
+            if matches!(limit, Some(limit) if !side.crosses(limit, level.price))
+            {
+                break;
+            }
+
+            let filled = match level.volume {
+                Some(volume) if volume < remaining => volume,
+                _ => remaining,
+            };
+            fills.push((level.price, filled));
+            remaining -= filled;
+
+            if let Some(volume) = &mut level.volume {
+                *volume -= filled;
+                if *volume <= zero {
+                    levels.remove(0);
+                }
+            }
+        }
+        fills
+    }
+}
+
+/// Order of the simulated exchange.
+#[derive(Debug, Clone)]
+struct SimOrder {
+    txid: String,
+    userref: Option<i64>,
+    /// The pair name of the request.
+    pair: String,
+    /// The assets of the pair.
+    assets: AssetPair<'static>,
+    side: OrderSide,
+    /// The limit price, none for market orders.
+    limit: Option<Amount>,
+    vol: Amount,
+    vol_exec: Amount,
+    cost: Amount,
+    fee: Amount,
+    opentm: Timestamp,
+    closetm: Option<Timestamp>,
+    status: &'static str,
+    reason: Option<String>,
+}
+
+impl SimOrder {
+    /// Gets the volume not executed yet.
+    fn remaining(&self) -> Amount {
+        self.vol - self.vol_exec
+    }
+
+    /// Gets the order type.
+    fn ordertype(&self) -> &'static str {
+        match self.limit {
+            Some(_) => "limit",
+            None => "market",
+        }
+    }
+
+    /// Gets the order description.
+    fn description(&self) -> String {
+        match self.limit {
+            Some(limit) => format!(
+                "{} {} {} @ limit {}",
+                self.side, self.vol, self.pair, limit
+            ),
+            None => {
+                format!("{} {} {} @ market", self.side, self.vol, self.pair)
+            }
+        }
+    }
+
+    /// Gets the order info, as returned by the orders APIs.
+    fn info(&self) -> OrderInfo {
+        let zero = Amount::default();
+        let price = if self.vol_exec > zero {
+            self.cost / self.vol_exec
+        } else {
+            zero
+        };
+
+        OrderInfo {
+            refid: None,
+            userref: self.userref,
+            status: self.status.into(),
+            opentm: self.opentm,
+            starttm: Timestamp::default(),
+            expiretm: Timestamp::default(),
+            closetm: self.closetm,
+            descr: OrderDescription {
+                pair: self.pair.clone(),
+                kind: self.side.to_string(),
+                ordertype: self.ordertype().into(),
+                price: self.limit.unwrap_or_default().to_string(),
+                price2: "0".into(),
+                leverage: "none".into(),
+                order: self.description(),
+                close: String::new(),
+            },
+            vol: self.vol,
+            vol_exec: self.vol_exec,
+            cost: self.cost,
+            fee: self.fee,
+            price,
+            stopprice: zero,
+            limitprice: zero,
+            misc: String::new(),
+            oflags: "fciq".into(),
+            reason: self.reason.clone(),
+        }
+    }
+}
+
+/// State shared by all the clones of a simulated exchange.
+#[derive(Debug, Default)]
+struct State {
+    balances: BTreeMap<String, Amount>,
+    /// The maker fee, in percent.
+    maker_fee: Amount,
+    /// The taker fee, in percent.
+    taker_fee: Amount,
+    /// The assets of the pair names that cannot be parsed.
+    pairs: HashMap<String, AssetPair<'static>>,
+    /// The books by pair.
+    markets: HashMap<AssetPair<'static>, Market>,
+    /// The open orders, oldest first.
+    open: Vec<SimOrder>,
+    /// The closed orders, oldest first.
+    closed: Vec<SimOrder>,
+    /// The trades by ID, oldest first.
+    trades: Vec<(String, TradeInfo)>,
+    /// The last order or trade ID.
+    last_id: u64,
+    /// The simulated time, the current time if none.
+    time: Option<Timestamp>,
+}
+
+impl State {
+    /// Gets the simulated time.
+    fn now(&self) -> Timestamp {
+        self.time.unwrap_or_else(Timestamp::now)
+    }
+
+    /// Gets a new order or trade ID with the given prefix.
+    fn next_id(&mut self, prefix: char) -> String {
+        self.last_id += 1;
+        format!("{}SIM-{:06}", prefix, self.last_id)
+    }
+
+    /// Gets the assets of the given pair name, mapped with `with_pair` or
+    /// parsed.
+    fn assets(&self, pair: &str) -> Result<AssetPair<'static>> {
+        match self.pairs.get(pair) {
+            Some(assets) => Ok(assets.clone()),
+            None => pair.parse(),
+        }
+    }
+
+    /// Gets the balance of the given asset.
+    fn balance(&self, asset: &str) -> Amount {
+        self.balances.get(asset).copied().unwrap_or_default()
+    }
+
+    /// Gets the balance of the given asset not committed to open orders.
+    fn available(&self, asset: &str) -> Amount {
+        let committed =
+            self.open.iter().fold(Amount::default(), |sum, o| {
+                match (o.side, o.limit) {
+                    (OrderSide::Buy, Some(limit))
+                        if o.assets.quote.to_string() == asset =>
+                    {
+                        sum + o.remaining() * limit * self.taker_rate()
+                    }
+                    (OrderSide::Sell, _)
+                        if o.assets.base.to_string() == asset =>
+                    {
+                        sum + o.remaining()
+                    }
+                    _ => sum,
+                }
+            });
+        self.balance(asset) - committed
+    }
+
+    /// Gets the cost multiplier including the taker fee.
+    fn taker_rate(&self) -> Amount {
+        let hundred = Amount::from(100);
+        (hundred + self.taker_fee) / hundred
+    }
+
+    /// Adds the given amount to the balance of the given asset.
+    fn credit(&mut self, asset: &str, amount: Amount) {
+        *self.balances.entry(asset.into()).or_default() += amount;
+    }
+
+    /// Fills the given open order.
+    fn fill(&mut self, txid: &str, price: Amount, volume: Amount, maker: bool) {
+        let index = match self.open.iter().position(|o| o.txid == txid) {
+            Some(index) => index,
+            None => return,
+        };
+        let fee_percent = if maker {
+            self.maker_fee
+        } else {
+            self.taker_fee
+        };
+        let time = self.now();
+        let trade_id = self.next_id('T');
+
+        let order = &mut self.open[index];
+        let cost = price * volume;
+        let fee = cost * fee_percent / Amount::from(100);
+        let done = volume >= order.remaining();
+        order.vol_exec = if done {
+            order.vol
+        } else {
+            order.vol_exec + volume
+        };
+        order.cost += cost;
+        order.fee += fee;
+
+        let trade = TradeInfo {
+            ordertxid: order.txid.clone(),
+            postxid: String::new(),
+            pair: order.pair.clone(),
+            time,
+            kind: order.side.to_string(),
+            ordertype: order.ordertype().into(),
+            price,
+            cost,
+            fee,
+            vol: volume,
+            margin: Amount::default(),
+            misc: String::new(),
+        };
+        let (side, base, quote) = (
+            order.side,
+            order.assets.base.to_string(),
+            order.assets.quote.to_string(),
+        );
+
+        match side {
+            OrderSide::Buy => {
+                self.credit(&base, volume);
+                self.credit(&quote, -(cost + fee));
+            }
+            OrderSide::Sell => {
+                self.credit(&base, -volume);
+                self.credit(&quote, cost - fee);
+            }
+        }
+        self.trades.push((trade_id, trade));
+
+        if done {
+            let mut order = self.open.remove(index);
+            order.status = "closed";
+            order.closetm = Some(time);
+            self.closed.push(order);
+        }
+    }
+
+    /// Fills the open orders of the given pair crossed by its book, as maker.
+    fn match_open(&mut self, pair: &AssetPair<'static>) {
+        let orders: Vec<_> = self
+            .open
+            .iter()
+            .filter(|o| &o.assets == pair)
+            .filter_map(|o| Some((o.txid.clone(), o.side, o.limit?)))
+            .collect();
+
+        for (txid, side, limit) in orders {
+            let remaining = match self.open.iter().find(|o| o.txid == txid) {
+                Some(order) => order.remaining(),
+                None => continue,
+            };
+            let fills = match self.markets.get_mut(pair) {
+                Some(market) => market.take(side, remaining, Some(limit)),
+                None => return,
+            };
+            for (_, volume) in fills {
+                self.fill(&txid, limit, volume, true);
+            }
+        }
+    }
+
+    /// Cancels the open orders matching the given predicate, returning their
+    /// number.
+    fn cancel(&mut self, predicate: impl Fn(&SimOrder) -> bool) -> usize {
+        let time = self.now();
+        let (canceled, open) =
+            self.open.drain(..).partition::<Vec<_>, _>(|o| predicate(o));
+        self.open = open;
+
+        let count = canceled.len();
+        for mut order in canceled {
+            order.status = "canceled";
+            order.reason = Some("User requested".into());
+            order.closetm = Some(time);
+            self.closed.push(order);
+        }
+        count
+    }
+
+    /// Answers the given API.
+    fn respond(&mut self, api: &Api) -> Option<Result<Value>> {
+        let params = &api.inner.params;
+        let result = match api.inner.method.as_str() {
+            "AddOrder" => self.add_order(params),
+            "CancelOrder" => self.cancel_order(params),
+            "CancelAll" => Ok(json!({ "count": self.cancel(|_| true) })),
+            "OpenOrders" => self.open_orders(params),
+            "ClosedOrders" => self.closed_orders(params),
+            "QueryOrders" => self.query_orders(params),
+            "Balance" => Ok(self.balances()),
+            "TradesHistory" => self.trades_history(params),
+            _ => return None,
+        };
+        Some(result)
+    }
+
+    fn add_order(&mut self, params: &HashMap<String, String>) -> Result<Value> {
+        let pair = param(params, "pair")?;
+        let assets = self
+            .assets(pair)
+            .map_err(|_| api_error("EQuery:Unknown asset pair"))?;
+        let side = match param(params, "type")? {
+            "buy" => OrderSide::Buy,
+            "sell" => OrderSide::Sell,
+            _ => return Err(invalid_argument("type")),
+        };
+        let limit = match param(params, "ordertype")? {
+            "market" => None,
+            "limit" => Some(amount_param(params, "price")?),
+            _ => return Err(api_error("EOrder:Unsupported order type")),
+        };
+        let vol = amount_param(params, "volume")?;
+        if vol <= Amount::default() {
+            return Err(invalid_argument("volume"));
+        }
+        let userref = match params.get("userref") {
+            Some(userref) => {
+                Some(userref.parse().map_err(|_| invalid_argument("userref"))?)
+            }
+            None => None,
+        };
+
+        // the taker fills are computed on a copy of the book, which is
+        // updated only once the order is accepted
+        let mut market = self.markets.get(&assets).cloned().unwrap_or_default();
+        let fills = market.take(side, vol, limit);
+        let filled = fills.iter().fold(Amount::default(), |sum, f| sum + f.1);
+        if limit.is_none() && filled < vol {
+            return Err(api_error("EOrder:Insufficient liquidity"));
+        }
+
+        let (asset, required) = match side {
+            OrderSide::Buy => {
+                let taken = fills
+                    .iter()
+                    .fold(Amount::default(), |sum, (p, v)| sum + *p * *v);
+                let resting = (vol - filled) * limit.unwrap_or_default();
+                (&assets.quote, (taken + resting) * self.taker_rate())
+            }
+            OrderSide::Sell => (&assets.base, vol),
+        };
+        if required > self.available(&asset.to_string()) {
+            return Err(api_error("EOrder:Insufficient funds"));
+        }
+
+        let order = SimOrder {
+            txid: String::new(),
+            userref,
+            pair: pair.into(),
+            assets: assets.clone(),
+            side,
+            limit,
+            vol,
+            vol_exec: Amount::default(),
+            cost: Amount::default(),
+            fee: Amount::default(),
+            opentm: self.now(),
+            closetm: None,
+            status: "open",
+            reason: None,
+        };
+        let descr = json!({ "order": order.description() });
+        if params.get("validate").map(String::as_str) == Some("true") {
+            return Ok(json!({ "descr": descr }));
+        }
+
+        let txid = self.next_id('O');
+        self.open.push(SimOrder {
+            txid: txid.clone(),
+            ..order
+        });
+        self.markets.insert(assets, market);
+        for (price, volume) in fills {
+            self.fill(&txid, price, volume, false);
+        }
+
+        Ok(json!({ "descr": descr, "txid": [txid] }))
+    }
+
+    fn cancel_order(
+        &mut self,
+        params: &HashMap<String, String>,
+    ) -> Result<Value> {
+        let txid = param(params, "txid")?;
+        // an integer is a userref, unless it is the ID of an order
+        let count = match txid.parse::<i64>() {
+            Ok(userref) if !self.open.iter().any(|o| o.txid == txid) => {
+                self.cancel(|o| o.userref == Some(userref))
+            }
+            _ => self.cancel(|o| o.txid == txid),
+        };
+        if count == 0 {
+            return Err(api_error("EOrder:Unknown order"));
+        }
+        Ok(json!({ "count": count }))
+    }
+
+    fn open_orders(&self, params: &HashMap<String, String>) -> Result<Value> {
+        let userref = userref_param(params)?;
+        let open = orders(self.open.iter(), userref)?;
+        Ok(json!({ "open": open }))
+    }
+
+    fn closed_orders(&self, params: &HashMap<String, String>) -> Result<Value> {
+        let userref = userref_param(params)?;
+        let closed: Vec<_> = self
+            .closed
+            .iter()
+            .rev()
+            .filter(|o| userref.is_none() || o.userref == userref)
+            .collect();
+        let count = closed.len();
+        let page = page(closed, params)?;
+        Ok(json!({ "closed": orders(page.into_iter(), None)?, "count": count }))
+    }
+
+    fn query_orders(&self, params: &HashMap<String, String>) -> Result<Value> {
+        let mut result = Map::new();
+        for txid in param(params, "txid")?.split(',') {
+            let order = self
+                .open
+                .iter()
+                .chain(&self.closed)
+                .find(|o| o.txid == txid)
+                .ok_or_else(|| api_error("EOrder:Unknown order"))?;
+            result.insert(txid.into(), to_value(order.info())?);
+        }
+        Ok(Value::Object(result))
+    }
+
+    fn balances(&self) -> Value {
+        let balances = self
+            .balances
+            .iter()
+            .map(|(asset, amount)| (asset.clone(), amount.to_string().into()))
+            .collect();
+        Value::Object(balances)
+    }
+
+    fn trades_history(
+        &self,
+        params: &HashMap<String, String>,
+    ) -> Result<Value> {
+        let trades: Vec<_> = self.trades.iter().rev().collect();
+        let count = trades.len();
+        let mut result = Map::new();
+        for (id, trade) in page(trades, params)? {
+            result.insert(id.clone(), to_value(trade)?);
+        }
+        Ok(json!({ "trades": result, "count": count }))
+    }
+}
+
+/// Simulated exchange, shared by all its clones.
+#[derive(Debug, Clone)]
+pub struct SimExchange {
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for SimExchange {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimExchange {
+    /// Constructs a new simulated exchange without balances nor prices, and
+    /// with the default Kraken fees.
+    pub fn new() -> Self {
+        let state = State {
+            maker_fee: MAKER_FEE.parse().unwrap_or_default(),
+            taker_fee: TAKER_FEE.parse().unwrap_or_default(),
+            ..State::default()
+        };
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Sets the initial balance of the given asset.
+    pub fn with_balance(
+        self,
+        asset: impl Into<String>,
+        amount: Amount,
+    ) -> Self {
+        self.lock().balances.insert(asset.into(), amount);
+        self
+    }
+
+    /// Sets the maker and taker fees, in percent.
+    pub fn with_fees(self, maker: Amount, taker: Amount) -> Self {
+        {
+            let mut state = self.lock();
+            state.maker_fee = maker;
+            state.taker_fee = taker;
+        }
+        self
+    }
+
+    /// Maps the given pair name to the given base and quote assets (e.g.
+    /// `XBTUSDT` to `XXBT` and `USDT`), for the names that cannot be parsed
+    /// or that should share the book and balances of another name.
+    pub fn with_pair(
+        self,
+        pair: impl Into<String>,
+        base: impl Into<String>,
+        quote: impl Into<String>,
+    ) -> Self {
+        let assets = Asset::new(base.into()).pair(quote.into());
+        self.lock().pairs.insert(pair.into(), assets);
+        self
+    }
+
+    /// Sets the simulated time of the orders and trades, instead of the
+    /// current time (e.g. when replaying historical prices).
+    pub fn set_time(&self, time: Timestamp) {
+        self.lock().time = Some(time);
+    }
+
+    /// Sets the price of the given pair, with unlimited volume, filling the
+    /// open orders it crosses.
+    pub fn set_price(&self, pair: &str, price: Amount) -> Result<()> {
+        let mut state = self.lock();
+        let pair = state.assets(pair)?;
+        state
+            .markets
+            .insert(pair.clone(), Market::with_price(price));
+        state.match_open(&pair);
+        Ok(())
+    }
+
+    /// Sets the book of the given pair from the given depth snapshot, filling
+    /// the open orders it crosses.
+    pub fn set_depth(&self, pair: &str, depth: &Depth) -> Result<()> {
+        let market = Market::with_depth(depth)?;
+        let mut state = self.lock();
+        let pair = state.assets(pair)?;
+        state.markets.insert(pair.clone(), market);
+        state.match_open(&pair);
+        Ok(())
+    }
+
+    /// Gets the balance of the given asset.
+    pub fn balance(&self, asset: &str) -> Amount {
+        self.lock().balance(asset)
+    }
+
+    /// Gets all the balances.
+    pub fn balances(&self) -> BTreeMap<String, Amount> {
+        self.lock().balances.clone()
+    }
+
+    /// Gets all the trades, oldest first.
+    pub fn trades(&self) -> Vec<TradeInfo> {
+        self.lock().trades.iter().map(|t| t.1.clone()).collect()
+    }
+
+    /// Answers the given API as the Kraken servers would, or `None` if a
+    /// public API that is not simulated.
+    pub(crate) fn respond(&self, api: &Api) -> Option<RawResponse> {
+        if api.is_public() {
+            return None;
+        }
+        log::trace!("Simulating request {}", api);
+
+        let body = match self.lock().respond(api) {
+            Some(Ok(result)) => json!({ "error": [], "result": result }),
+            Some(Err(Error::Api(errors))) => json!({ "error": errors }),
+            Some(Err(err)) => {
+                log::warn!("Simulated request failed: {}", err);
+                json!({ "error": ["EGeneral:Internal error"] })
+            }
+            None => {
+                return Some(RawResponse {
+                    status_code: 404,
+                    headers: HeaderMap::new(),
+                    body: json!({ "error": ["EGeneral:Unknown method"] })
+                        .to_string()
+                        .into_bytes(),
+                })
+            }
+        };
+        Some(RawResponse {
+            status_code: 200,
+            headers: HeaderMap::new(),
+            body: body.to_string().into_bytes(),
+        })
+    }
+
+    /// Locks the shared state.
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Constructs a Kraken API error.
+fn api_error(error: &str) -> Error {
+    Error::Api(vec![error.into()])
+}
+
+/// Constructs the Kraken error of an invalid parameter.
+fn invalid_argument(param: &str) -> Error {
+    api_error(&format!("EGeneral:Invalid arguments:{}", param))
+}
+
+/// Gets the given required parameter.
+fn param<'a>(
+    params: &'a HashMap<String, String>,
+    key: &str,
+) -> Result<&'a str> {
+    params
+        .get(key)
+        .map(String::as_str)
+        .ok_or_else(|| invalid_argument(key))
+}
+
+/// Parses the given required amount parameter.
+fn amount_param(params: &HashMap<String, String>, key: &str) -> Result<Amount> {
+    param(params, key)?
+        .parse()
+        .map_err(|_| invalid_argument(key))
+}
+
+/// Parses the optional `userref` parameter.
+fn userref_param(params: &HashMap<String, String>) -> Result<Option<i64>> {
+    match params.get("userref") {
+        Some(userref) => userref
+            .parse()
+            .map(Some)
+            .map_err(|_| invalid_argument("userref")),
+        None => Ok(None),
+    }
+}
+
+/// Gets the page of the given entries selected by the `ofs` parameter.
+fn page<T>(
+    entries: Vec<T>,
+    params: &HashMap<String, String>,
+) -> Result<Vec<T>> {
+    let ofs = match params.get("ofs") {
+        Some(ofs) => ofs.parse().map_err(|_| invalid_argument("ofs"))?,
+        None => 0,
+    };
+    Ok(entries.into_iter().skip(ofs).take(PAGE_SIZE).collect())
+}
+
+/// Gets the info of the given orders by transaction ID.
+fn orders<'a>(
+    orders: impl Iterator<Item = &'a SimOrder>,
+    userref: Option<i64>,
+) -> Result<Map<String, Value>> {
+    orders
+        .filter(|o| userref.is_none() || o.userref == userref)
+        .map(|o| Ok((o.txid.clone(), to_value(o.info())?)))
+        .collect()
+}
+
+/// Serializes the given model.
+fn to_value(model: impl serde::Serialize) -> Result<Value> {
+    serde_json::to_value(model).map_err(Error::internal)
+}
+
+/// Parses the given book level price or volume.
+fn parse_level(amount: &str) -> Result<Amount> {
+    amount.parse().map_err(|_| {
+        Error::invalid_message(format!("invalid book amount: {}", amount))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{amount::amount, api, blocking::Client, client, ResponseValue};
+    use anyhow::Result;
+
+    fn add_order(
+        client: &Client,
+        kind: &str,
+        ordertype: &str,
+        volume: &str,
+        price: Option<&str>,
+    ) -> Result<ResponseValue> {
+        let mut api = api::private::add_order()
+            .with("pair", "XXBTZEUR")
+            .with("type", kind)
+            .with("ordertype", ordertype)
+            .with("volume", volume);
+        if let Some(price) = price {
+            api = api.with("price", price);
+        }
+        Ok(client.send(api)?)
+    }
+
+    fn level(price: &str, volume: &str) -> Level {
+        Level {
+            price: price.into(),
+            volume: volume.into(),
+            timestamp: "1625918400.0".into(),
+        }
+    }
+
+    #[test]
+    fn paper_trading() -> Result<()> {
+        let sim = SimExchange::new().with_balance("ZEUR", amount("10000"));
+        sim.set_price("XXBTZEUR", amount("30000"))?;
+        let client = Client::new(client::user_agent())?.with_sim(sim.clone());
+
+        // filled as taker
+        let resp = add_order(&client, "buy", "market", "0.1", None)?;
+        assert!(resp.is_success(), "{:?}", resp);
+        assert_eq!(sim.balance("XXBT"), amount("0.1"));
+        // 10000 - 3000 - 0.26%
+        assert_eq!(format!("{:.2}", sim.balance("ZEUR")), "6992.20");
+
+        // resting until the price crosses it, then filled as maker
+        let resp = add_order(&client, "sell", "limit", "0.05", Some("31000"))?;
+        let txid = resp.get("txid").and_then(|t| t[0].as_str()).unwrap();
+        let resp = add_order(&client, "sell", "limit", "0.1", Some("31000"))?;
+        assert_eq!(resp.error, vec!["EOrder:Insufficient funds"]);
+
+        let resp: ResponseValue = client.send(api::private::open_orders())?;
+        assert!(resp.get("open").and_then(|o| o.get(txid)).is_some());
+
+        sim.set_price("XXBTZEUR", amount("31500"))?;
+        assert_eq!(sim.balance("XXBT"), amount("0.05"));
+        // 6992.2 + 1550 - 0.16%
+        assert_eq!(format!("{:.2}", sim.balance("ZEUR")), "8539.72");
+
+        let api = api::private::query_orders().with("txid", txid);
+        let resp: ResponseValue = client.send(api)?;
+        let info: OrderInfo =
+            serde_json::from_value(resp.get(txid).cloned().unwrap())?;
+        assert_eq!(info.status, "closed");
+        assert_eq!(info.vol_exec, amount("0.05"));
+        assert_eq!(info.price, amount("31000"));
+
+        let resp: ResponseValue = client.send(api::private::trades_history())?;
+        assert_eq!(resp.get("count"), Some(&json!(2)));
+
+        // canceled by userref
+        let api = api::private::add_order()
+            .with("pair", "XXBTZEUR")
+            .with("type", "buy")
+            .with("ordertype", "limit")
+            .with("price", "20000")
+            .with("volume", "0.01")
+            .with("userref", 7);
+        assert!(client.send::<_, Value>(api)?.is_success());
+        let api = api::private::cancel_order().with("txid", 7);
+        let resp: ResponseValue = client.send(api)?;
+        assert_eq!(resp.get("count"), Some(&json!(1)));
+        let api = api::private::cancel_order().with("txid", 7);
+        let resp: ResponseValue = client.send(api)?;
+        assert_eq!(resp.error, vec!["EOrder:Unknown order"]);
+
+        let resp: ResponseValue = client.send(api::private::closed_orders())?;
+        assert_eq!(resp.get("count"), Some(&json!(3)));
+
+        let resp: ResponseValue = client.send(api::private::balance())?;
+        assert_eq!(resp.get("XXBT"), Some(&json!("0.05")));
+
+        let resp: ResponseValue = client.send(api::private::ledgers())?;
+        assert_eq!(resp.status_code, 404);
+
+        Ok(())
+    }
+
+    #[test]
+    fn pairs_and_cancels() -> Result<()> {
+        let sim = SimExchange::new()
+            .with_balance("USDT", amount("100000"))
+            .with_pair("XBTUSDT", "XXBT", "USDT")
+            .with_pair("XBTEUR", "XXBT", "ZEUR");
+        assert!(sim.set_price("USDCUSD", amount("1")).is_err());
+        sim.set_price("XBTUSDT", amount("30000"))?;
+        let client = Client::new(client::user_agent())?.with_sim(sim.clone());
+
+        let order = |pair: &str, userref: i64| {
+            api::private::add_order()
+                .with("pair", pair)
+                .with("type", "buy")
+                .with("ordertype", "limit")
+                .with("price", "20000")
+                .with("volume", "0.1")
+                .with("userref", userref)
+        };
+        let resp: ResponseValue = client.send(order("XBTUSDT", 1))?;
+        assert!(resp.is_success(), "{:?}", resp);
+        let resp: ResponseValue = client.send(order("USDCUSD", 1))?;
+        assert_eq!(resp.error, vec!["EQuery:Unknown asset pair"]);
+
+        // mapped to the same balances as XXBTZEUR
+        let sim = sim.with_balance("ZEUR", amount("1000"));
+        sim.set_price("XXBTZEUR", amount("30000"))?;
+        let api = api::private::add_order()
+            .with("pair", "XBTEUR")
+            .with("type", "buy")
+            .with("ordertype", "market")
+            .with("volume", "0.01");
+        assert!(client.send::<_, Value>(api)?.is_success());
+        assert_eq!(sim.balance("XXBT"), amount("0.01"));
+
+        // an unknown order ID is not a userref
+        let api = api::private::cancel_order().with("txid", "OSIM-000001X");
+        let resp: ResponseValue = client.send(api)?;
+        assert_eq!(resp.error, vec!["EOrder:Unknown order"]);
+        let api = api::private::cancel_order().with("txid", 1);
+        let resp: ResponseValue = client.send(api)?;
+        assert_eq!(resp.get("count"), Some(&json!(1)));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "testkit")]
+    #[test]
+    fn public_fallthrough() -> Result<()> {
+        use crate::testkit::MockServer;
+
+        let server = MockServer::start()?;
+        server.mock("Time", json!({"unixtime": 1625918400}));
+        let client = Client::new(client::user_agent())?
+            .with_base_url(server.url())
+            .with_sim(SimExchange::new());
+
+        let resp: ResponseValue = client.send(api::public::time())?;
+        assert_eq!(resp.get("unixtime"), Some(&json!(1625918400)));
+        let resp: ResponseValue = client.send(api::private::balance())?;
+        assert!(resp.is_success());
+        assert_eq!(server.requests().len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn depth_matching() -> Result<()> {
+        let sim = SimExchange::new()
+            .with_balance("ZEUR", amount("100000"))
+            .with_fees(amount("0"), amount("0"));
+        let depth = Depth {
+            asks: vec![level("30100", "0.05"), level("30200", "1")],
+            bids: vec![level("29900", "1")],
+        };
+        sim.set_depth("XXBTZEUR", &depth)?;
+        let client = Client::new(client::user_agent())?.with_sim(sim.clone());
+
+        // walks the book
+        add_order(&client, "buy", "market", "0.1", None)?;
+        assert_eq!(sim.balance("XXBT"), amount("0.1"));
+        assert_eq!(format!("{:.2}", sim.balance("ZEUR")), "96985.00");
+
+        // partially filled as taker up to the limit price
+        add_order(&client, "buy", "limit", "2", Some("30200"))?;
+        let resp: ResponseValue = client.send(api::private::open_orders())?;
+        let open = resp.get("open").and_then(Value::as_object).unwrap();
+        let info: OrderInfo =
+            serde_json::from_value(open.values().next().cloned().unwrap())?;
+        assert_eq!(format!("{:.2}", info.vol_exec), "0.95");
+
+        let resp = add_order(&client, "sell", "market", "5", None)?;
+        assert_eq!(resp.error, vec!["EOrder:Insufficient liquidity"]);
+
+        let resp = add_order(&client, "buy", "stop-loss", "1", None)?;
+        assert_eq!(resp.error, vec!["EOrder:Unsupported order type"]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,162 @@
+//! Dead man's switch built on the `cancel_all_after` API.
+//!
+//! The `cancel_all_after` API cancels all the open orders once its timeout
+//! expires, unless the countdown is refreshed before. The `DeadMansSwitch`
+//! refreshes the countdown in background at a fixed interval, so that all the
+//! orders are canceled if the process stops or loses connectivity, and
+//! disarms it (with a zero timeout) when dropped.
+//!
+//! ```no_run
+//! use akkorokamui::{switch::DeadMansSwitch, Client, Credentials};
+//! use anyhow::Result;
+//! use std::time::Duration;
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<()> {
+//!     let credentials = Credentials::read("kraken.key")?;
+//!
+//!     let user_agent = "<product>/<product-version>";
+//!     let client = Client::with_credentials(user_agent, credentials)?;
+//!
+//!     let timeout = Duration::from_secs(60);
+//!     let interval = Duration::from_secs(15);
+//!     let (switch, mut failures) =
+//!         DeadMansSwitch::spawn(client, timeout, interval);
+//!
+//!     // ... trade ...
+//!     if let Ok(err) = failures.try_recv() {
+//!         eprintln!("Cannot refresh the dead man's switch: {}", err);
+//!     }
+//!
+//!     switch.disarm().await?;
+//!     Ok(())
+//! }
+//! ```
+
+use std::time::Duration;
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+};
+
+use crate::{api, Client, Error, ResponseValue, Result};
+
+pub mod blocking;
+
+/// Background task refreshing the `cancel_all_after` countdown.
+///
+/// Dropping the switch stops the task, which disarms the countdown before
+/// exiting (as long as the runtime is still running). Use `disarm` to wait
+/// for the countdown to be disarmed.
+pub struct DeadMansSwitch {
+    /// The signal to stop the task.
+    stop: Option<oneshot::Sender<()>>,
+    /// The task refreshing the countdown.
+    task: Option<JoinHandle<Result<()>>>,
+}
+
+impl DeadMansSwitch {
+    /// Spawns a new task that arms the countdown with the given timeout and
+    /// refreshes it at the given interval, which should be shorter than the
+    /// timeout. The timeout is rounded up to whole seconds, and to at least
+    /// one second (a zero timeout would disarm the countdown).
+    ///
+    /// Returns the switch and the receiver of the refresh failures.
+    pub fn spawn(
+        client: Client,
+        timeout: Duration,
+        interval: Duration,
+    ) -> (Self, mpsc::UnboundedReceiver<Error>) {
+        warn_interval(timeout, interval);
+        let timeout = timeout_secs(timeout);
+        let (failures_tx, failures_rx) = mpsc::unbounded_channel();
+        let (stop_tx, mut stop_rx) = oneshot::channel();
+
+        let task = tokio::spawn(async move {
+            loop {
+                if let Err(e) = arm(&client, timeout).await {
+                    log::warn!("Cannot refresh dead man's switch: {}", e);
+                    let _ = failures_tx.send(e);
+                }
+
+                // stops when signaled or when the switch has been dropped
+                let stop = tokio::time::timeout(interval, &mut stop_rx).await;
+                if stop.is_ok() {
+                    break;
+                }
+            }
+            arm(&client, 0).await
+        });
+
+        let switch = Self {
+            stop: Some(stop_tx),
+            task: Some(task),
+        };
+        (switch, failures_rx)
+    }
+
+    /// Stops refreshing the countdown and disarms it.
+    pub async fn disarm(mut self) -> Result<()> {
+        self.stop.take().map(|stop| stop.send(()));
+        match self.task.take() {
+            Some(task) => task.await.map_err(Error::internal)?,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for DeadMansSwitch {
+    /// Signals the task to disarm the countdown and exit.
+    fn drop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+    }
+}
+
+/// Sends the `cancel_all_after` request with the given timeout in seconds
+/// (zero to disarm the countdown).
+async fn arm(client: &Client, timeout: u64) -> Result<()> {
+    let api = api::private::cancel_all_after().with("timeout", timeout);
+    let resp: ResponseValue = client.send(api).await?;
+    check(resp)
+}
+
+/// Checks the `cancel_all_after` response.
+fn check(resp: ResponseValue) -> Result<()> {
+    if resp.error.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Api(resp.error))
+    }
+}
+
+/// Gets the countdown timeout in seconds, rounded up to at least one second.
+fn timeout_secs(timeout: Duration) -> u64 {
+    let secs = timeout.as_secs() + u64::from(timeout.subsec_nanos() > 0);
+    secs.max(1)
+}
+
+/// Warns if the countdown could expire before being refreshed.
+fn warn_interval(timeout: Duration, interval: Duration) {
+    if interval >= timeout {
+        log::warn!(
+            "Dead man's switch interval {:?} is not shorter than the timeout {:?}",
+            interval,
+            timeout
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeout_rounding() {
+        assert_eq!(timeout_secs(Duration::from_secs(0)), 1);
+        assert_eq!(timeout_secs(Duration::from_millis(500)), 1);
+        assert_eq!(timeout_secs(Duration::from_secs(60)), 60);
+        assert_eq!(timeout_secs(Duration::from_millis(60_001)), 61);
+    }
+}
@@ -0,0 +1,124 @@
+use std::{
+    sync::mpsc::{self, RecvTimeoutError},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{api, blocking::Client, switch, Error, ResponseValue, Result};
+
+/// Background thread refreshing the `cancel_all_after` countdown.
+///
+/// Dropping the switch stops the thread and waits for it to disarm the
+/// countdown.
+pub struct DeadMansSwitch {
+    /// The signal to stop the thread.
+    stop: Option<mpsc::Sender<()>>,
+    /// The thread refreshing the countdown.
+    thread: Option<JoinHandle<Result<()>>>,
+}
+
+impl DeadMansSwitch {
+    /// Spawns a new thread that arms the countdown with the given timeout and
+    /// refreshes it at the given interval, which should be shorter than the
+    /// timeout. The timeout is rounded up to whole seconds, and to at least
+    /// one second (a zero timeout would disarm the countdown).
+    ///
+    /// Returns the switch and the receiver of the refresh failures.
+    pub fn spawn(
+        client: Client,
+        timeout: Duration,
+        interval: Duration,
+    ) -> (Self, mpsc::Receiver<Error>) {
+        switch::warn_interval(timeout, interval);
+        let timeout = switch::timeout_secs(timeout);
+        let (failures_tx, failures_rx) = mpsc::channel();
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+
+        let thread = thread::spawn(move || {
+            loop {
+                if let Err(e) = arm(&client, timeout) {
+                    log::warn!("Cannot refresh dead man's switch: {}", e);
+                    let _ = failures_tx.send(e);
+                }
+
+                // stops when signaled or when the switch has been dropped
+                match stop_rx.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Ok(()) | Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            arm(&client, 0)
+        });
+
+        let switch = Self {
+            stop: Some(stop_tx),
+            thread: Some(thread),
+        };
+        (switch, failures_rx)
+    }
+
+    /// Stops refreshing the countdown and disarms it.
+    pub fn disarm(mut self) -> Result<()> {
+        self.stop_thread()
+    }
+
+    /// Signals the thread to disarm the countdown and waits for it to exit.
+    fn stop_thread(&mut self) -> Result<()> {
+        self.stop.take();
+        match self.thread.take() {
+            Some(thread) => thread
+                .join()
+                .map_err(|_| Error::internal("dead man's switch panicked"))?,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for DeadMansSwitch {
+    /// Disarms the countdown.
+    fn drop(&mut self) {
+        if let Err(e) = self.stop_thread() {
+            log::error!("Cannot disarm dead man's switch: {}", e);
+        }
+    }
+}
+
+/// Sends the `cancel_all_after` request with the given timeout in seconds
+/// (zero to disarm the countdown).
+fn arm(client: &Client, timeout: u64) -> Result<()> {
+    let api = api::private::cancel_all_after().with("timeout", timeout);
+    let resp: ResponseValue = client.send(api)?;
+    switch::check(resp)
+}
+
+#[cfg(all(test, feature = "testkit"))]
+mod tests {
+    use super::*;
+    use crate::{client, testkit::MockServer};
+    use anyhow::Result;
+
+    #[test]
+    fn report_failures() -> Result<()> {
+        let server = MockServer::start()?;
+        server.mock_errors("CancelAllOrdersAfter", &["EAPI:Invalid key"]);
+        let client =
+            Client::new(client::user_agent())?.with_base_url(server.url());
+        let timeout = Duration::from_millis(200);
+        let interval = Duration::from_millis(10);
+
+        let (switch, failures) =
+            DeadMansSwitch::spawn(client, timeout, interval);
+        let failure = failures.recv_timeout(Duration::from_secs(30))?;
+        assert_eq!(failure, Error::Api(vec!["EAPI:Invalid key".into()]));
+
+        assert!(switch.disarm().is_err());
+        let requests = server.requests();
+        // the sub-second timeout does not disarm the countdown
+        assert_eq!(requests[0].params["timeout"], "1");
+        assert_eq!(
+            requests.last().map(|r| &r.params["timeout"]),
+            Some(&"0".to_string())
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,523 @@
+//! In-process mock Kraken server for offline tests.
+//!
+//! This module is only available with the `testkit` feature.
+//!
+//! The `MockServer` listens on a local port and answers the public and
+//! private APIs with scripted responses, keyed by API method name (e.g.
+//! `Time`, `AddOrder`). When constructed with the client credentials, it
+//! also validates the `API-Key` and `API-Sign` headers and the nonce
+//! monotonicity of the private requests, answering with the same errors as
+//! Kraken. Clients are pointed to the server with `with_base_url`.
+//!
+//! ```
+//! use akkorokamui::{api, blocking::Client, testkit::MockServer, ResponseValue};
+//! use anyhow::Result;
+//! use serde_json::json;
+//!
+//! fn main() -> Result<()> {
+//!     let server = MockServer::start()?;
+//!     server.mock("Time", json!({"unixtime": 1625918400}));
+//!
+//!     let client = Client::new("test/0.1")?.with_base_url(server.url());
+//!     let resp: ResponseValue = client.send(api::public::time())?;
+//!     assert_eq!(resp.result, Some(json!({"unixtime": 1625918400})));
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use percent_encoding::percent_decode_str;
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::{client, Credentials, Error, Result};
+
+/// Scripted response of the mock server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockResponse {
+    /// The HTTP status code.
+    pub status_code: u16,
+    /// The response body.
+    pub body: Vec<u8>,
+}
+
+impl MockResponse {
+    /// Constructs a successful response with the given result.
+    pub fn result(result: Value) -> Self {
+        Self::json(200, json!({ "error": [], "result": result }))
+    }
+
+    /// Constructs a response with the given Kraken errors.
+    pub fn errors(errors: &[&str]) -> Self {
+        Self::json(200, json!({ "error": errors }))
+    }
+
+    /// Constructs a response with the given status code and JSON body.
+    pub fn json(status_code: u16, body: Value) -> Self {
+        Self {
+            status_code,
+            body: body.to_string().into_bytes(),
+        }
+    }
+
+    /// Constructs a response with the given status code and raw body (e.g.
+    /// an HTML error page or a zip report).
+    pub fn raw(status_code: u16, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status_code,
+            body: body.into(),
+        }
+    }
+}
+
+/// Request received by the mock server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockRequest {
+    /// The HTTP method (`GET` or `POST`).
+    pub method: String,
+    /// The URI path (e.g. `/0/public/Time`).
+    pub path: String,
+    /// The query (public) or body (private) parameters.
+    pub params: HashMap<String, String>,
+    /// The headers, with lower case names.
+    pub headers: HashMap<String, String>,
+    /// The raw body.
+    pub body: String,
+}
+
+impl MockRequest {
+    /// Gets the API method name (e.g. `Time`).
+    pub fn api_method(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or_default()
+    }
+}
+
+/// State shared with the server thread.
+#[derive(Default)]
+struct State {
+    /// The scripted responses by API method name.
+    responses: HashMap<String, VecDeque<MockResponse>>,
+    /// The requests received so far.
+    requests: Vec<MockRequest>,
+    /// The last nonce of the private requests.
+    last_nonce: Option<u64>,
+}
+
+/// In-process mock Kraken server.
+///
+/// Each connection is served by its own thread. The server is stopped when
+/// dropped.
+pub struct MockServer {
+    /// The local address of the server.
+    addr: SocketAddr,
+    /// The credentials to validate the private requests with, if any.
+    credentials: Option<Credentials>,
+    /// The state shared with the server thread.
+    state: Arc<Mutex<State>>,
+    /// Whether the server has been stopped.
+    stopped: Arc<AtomicBool>,
+    /// The server thread.
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MockServer {
+    /// Starts a new server that does not validate the private requests.
+    pub fn start() -> Result<Self> {
+        Self::listen(None)
+    }
+
+    /// Starts a new server that validates the private requests signature
+    /// and nonce against the given credentials.
+    pub fn with_credentials(
+        credentials: impl Into<Credentials>,
+    ) -> Result<Self> {
+        Self::listen(Some(credentials.into()))
+    }
+
+    /// Starts listening on a random local port.
+    fn listen(credentials: Option<Credentials>) -> Result<Self> {
+        let listener =
+            TcpListener::bind("127.0.0.1:0").map_err(Error::internal)?;
+        let addr = listener.local_addr().map_err(Error::internal)?;
+
+        let state = Arc::new(Mutex::new(State::default()));
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let thread = {
+            let state = Arc::clone(&state);
+            let stopped = Arc::clone(&stopped);
+            let credentials = credentials.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stopped.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let stream = match stream {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            log::warn!("Mock server connection error: {}", e);
+                            continue;
+                        }
+                    };
+
+                    // each connection is served by its own thread, so that
+                    // concurrent clients do not wait for each other
+                    let state = Arc::clone(&state);
+                    let credentials = credentials.clone();
+                    thread::spawn(move || {
+                        if let Err(e) =
+                            serve(stream, credentials.as_ref(), &state)
+                        {
+                            log::warn!("Mock server connection error: {}", e);
+                        }
+                    });
+                }
+            })
+        };
+
+        Ok(Self {
+            addr,
+            credentials,
+            state,
+            stopped,
+            thread: Some(thread),
+        })
+    }
+
+    /// Gets the base URL of the server, to be used with `with_base_url`.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Returns true only if the server validates the private requests.
+    pub fn validates_signatures(&self) -> bool {
+        self.credentials.is_some()
+    }
+
+    /// Answers the given API method with a successful response of the given
+    /// result.
+    pub fn mock(&self, api_method: &str, result: Value) {
+        self.mock_response(api_method, MockResponse::result(result));
+    }
+
+    /// Answers the given API method with the given Kraken errors.
+    pub fn mock_errors(&self, api_method: &str, errors: &[&str]) {
+        self.mock_response(api_method, MockResponse::errors(errors));
+    }
+
+    /// Answers the given API method with the given response.
+    ///
+    /// Responses of the same method are returned in order, and the last one
+    /// is repeated for all the following requests.
+    pub fn mock_response(&self, api_method: &str, response: MockResponse) {
+        self.lock()
+            .responses
+            .entry(api_method.to_string())
+            .or_default()
+            .push_back(response);
+    }
+
+    /// Gets all the requests received so far.
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.lock().requests.clone()
+    }
+
+    /// Locks the shared state.
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for MockServer {
+    /// Stops the server thread.
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // wakes the listener up
+        let _ = TcpStream::connect(self.addr);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Serves a single request on the given connection.
+fn serve(
+    stream: TcpStream,
+    credentials: Option<&Credentials>,
+    state: &Mutex<State>,
+) -> Result<()> {
+    let mut reader =
+        BufReader::new(stream.try_clone().map_err(Error::internal)?);
+    let request = match read_request(&mut reader)? {
+        Some(request) => request,
+        None => return Ok(()),
+    };
+
+    let response = {
+        let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+        let response = respond(&request, credentials, &mut state);
+        state.requests.push(request);
+        response
+    };
+
+    write_response(stream, &response)
+}
+
+/// Gets the response to the given request.
+fn respond(
+    request: &MockRequest,
+    credentials: Option<&Credentials>,
+    state: &mut State,
+) -> MockResponse {
+    if request.method == "POST" {
+        if let Err(error) = validate(request, credentials, state) {
+            return MockResponse::errors(&[error]);
+        }
+    }
+
+    match state.responses.get_mut(request.api_method()) {
+        Some(responses) if responses.len() > 1 => {
+            responses.pop_front().unwrap_or_else(unknown_method)
+        }
+        Some(responses) => {
+            responses.front().cloned().unwrap_or_else(unknown_method)
+        }
+        None => unknown_method(),
+    }
+}
+
+/// The response to the API methods without scripted responses.
+fn unknown_method() -> MockResponse {
+    MockResponse::json(404, json!({ "error": ["EGeneral:Unknown method"] }))
+}
+
+/// Validates the signature and nonce of the given private request, returning
+/// the Kraken error otherwise.
+fn validate(
+    request: &MockRequest,
+    credentials: Option<&Credentials>,
+    state: &mut State,
+) -> std::result::Result<(), &'static str> {
+    let nonce: u64 = request
+        .params
+        .get("nonce")
+        .and_then(|n| n.parse().ok())
+        .ok_or("EAPI:Invalid nonce")?;
+
+    if let Some(credentials) = credentials {
+        let api_key = credentials.api_key();
+        if request.headers.get("api-key").map(String::as_str) != Some(api_key) {
+            return Err("EAPI:Invalid key");
+        }
+
+        // the signature is computed over the body as sent
+        let expected =
+            client::sign(credentials, &request.path, nonce, &request.body);
+        match (expected, request.headers.get("api-sign")) {
+            (Ok(expected), Some(sign)) if &expected == sign => (),
+            _ => return Err("EAPI:Invalid signature"),
+        }
+    }
+
+    if matches!(state.last_nonce, Some(last) if nonce <= last) {
+        return Err("EAPI:Invalid nonce");
+    }
+    state.last_nonce = Some(nonce);
+    Ok(())
+}
+
+/// Reads the next HTTP request, or `None` if the connection was closed.
+fn read_request(reader: &mut impl BufRead) -> Result<Option<MockRequest>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).map_err(Error::internal)? == 0 {
+        return Ok(None);
+    }
+
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or_default();
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), query),
+        None => (target.to_string(), ""),
+    };
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).map_err(Error::internal)?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers
+                .insert(name.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+
+    let length: usize = headers
+        .get("content-length")
+        .and_then(|l| l.parse().ok())
+        .unwrap_or_default();
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).map_err(Error::internal)?;
+    let body = String::from_utf8_lossy(&body).to_string();
+
+    let params = if method == "POST" {
+        parse_params(&body)
+    } else {
+        parse_params(query)
+    };
+
+    Ok(Some(MockRequest {
+        method,
+        path,
+        params,
+        headers,
+        body,
+    }))
+}
+
+/// Parses URL encoded parameters.
+fn parse_params(params: &str) -> HashMap<String, String> {
+    let decode =
+        |s: &str| percent_decode_str(s).decode_utf8_lossy().to_string();
+    params
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(|p| match p.split_once('=') {
+            Some((k, v)) => (decode(k), decode(v)),
+            None => (decode(p), String::new()),
+        })
+        .collect()
+}
+
+/// Writes the given response and closes the connection.
+fn write_response(
+    mut stream: TcpStream,
+    response: &MockResponse,
+) -> Result<()> {
+    let head = format!(
+        "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status_code,
+        response.body.len()
+    );
+    stream
+        .write_all(head.as_bytes())
+        .and_then(|_| stream.write_all(&response.body))
+        .and_then(|_| stream.flush())
+        .map_err(Error::internal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api, auth::tests::DummyCredentials, blocking, Client, ResponseValue,
+    };
+    use anyhow::Result;
+
+    #[test]
+    fn public_responses() -> Result<()> {
+        let server = MockServer::start()?;
+        server.mock("Time", json!({"unixtime": 1}));
+        server.mock("Time", json!({"unixtime": 2}));
+
+        let client = blocking::Client::new(client::user_agent())?
+            .with_base_url(server.url());
+        let api = || api::public::time().with("pair", "XBT/EUR");
+
+        let unixtime =
+            |resp: ResponseValue| resp.result.map(|r| r["unixtime"].clone());
+        assert_eq!(unixtime(client.send(api())?), Some(json!(1)));
+        assert_eq!(unixtime(client.send(api())?), Some(json!(2)));
+        // the last response is repeated
+        assert_eq!(unixtime(client.send(api())?), Some(json!(2)));
+
+        let resp: ResponseValue = client.send(api::public::ticker())?;
+        assert_eq!(resp.status_code, 404);
+        assert_eq!(resp.error, vec!["EGeneral:Unknown method"]);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(requests[0].path, "/0/public/Time");
+        assert_eq!(requests[0].params["pair"], "XBT/EUR");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn private_validation() -> Result<()> {
+        let dummy = DummyCredentials::new()?;
+        let credentials = Credentials::read(&dummy.path)?;
+        let server = MockServer::with_credentials(credentials.clone())?;
+        assert!(server.validates_signatures());
+        server.mock("Balance", json!({"ZEUR": "100.0000"}));
+        server.mock_errors("AddOrder", &["EOrder:Insufficient funds"]);
+
+        let client =
+            Client::with_credentials(client::user_agent(), credentials)?
+                .with_base_url(server.url());
+        let resp: ResponseValue = client.send(api::private::balance()).await?;
+        assert!(resp.is_success());
+
+        let api = api::private::add_order().with("pair", "XXBTZEUR");
+        let resp: ResponseValue = client.send(api).await?;
+        assert_eq!(resp.error, vec!["EOrder:Insufficient funds"]);
+        assert_eq!(server.requests()[1].params["pair"], "XXBTZEUR");
+
+        // unsigned requests are rejected
+        let client =
+            Client::new(client::user_agent())?.with_base_url(server.url());
+        let resp: ResponseValue = client.send(api::private::balance()).await?;
+        assert_eq!(resp.error, vec!["EAPI:Invalid key"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn concurrent_connections() -> Result<()> {
+        let server = MockServer::start()?;
+        server.mock("Time", json!({"unixtime": 1}));
+
+        // an idle connection does not block the other clients
+        let _idle = TcpStream::connect(server.addr)?;
+        let client = blocking::Client::new(client::user_agent())?
+            .with_base_url(server.url());
+        let resp: ResponseValue = client.send(api::public::time())?;
+        assert!(resp.is_success());
+
+        Ok(())
+    }
+
+    #[test]
+    fn nonce_monotonicity() {
+        let mut state = State::default();
+        let request = |nonce: u64| MockRequest {
+            method: "POST".into(),
+            path: "/0/private/Balance".into(),
+            params: vec![("nonce".to_string(), nonce.to_string())]
+                .into_iter()
+                .collect(),
+            headers: HashMap::new(),
+            body: String::new(),
+        };
+
+        assert!(validate(&request(2), None, &mut state).is_ok());
+        assert_eq!(
+            validate(&request(2), None, &mut state),
+            Err("EAPI:Invalid nonce")
+        );
+        assert!(validate(&request(3), None, &mut state).is_ok());
+    }
+}
@@ -0,0 +1,437 @@
+//! Kraken timestamps and time parameters.
+//!
+//! Kraken returns timestamps as Unix seconds with a fractional part (e.g.
+//! `1625918400.1234`), either as numbers or strings, and paginates some
+//! public APIs with opaque `last` cursors (e.g. nanoseconds strings). The
+//! `Timestamp` type parses them without any precision loss, the `Since` type
+//! keeps a cursor as returned by the API, and `Relative` formats the `+<n>`
+//! parameters relative to the current time. All of them can be passed
+//! directly to `ApiBuilder::with`.
+//!
+//! Conversions from and to `chrono::DateTime<Utc>` and
+//! `time::OffsetDateTime` are available with the `chrono` and `time` optional
+//! features.
+//!
+//! ```
+//! use akkorokamui::{api, timestamp::{Relative, Timestamp}};
+//! use std::time::Duration;
+//!
+//! let start: Timestamp = "1625918400.1234".parse().unwrap();
+//! let api = api::private::ledgers().with("start", start);
+//!
+//! let api = api::private::add_order()
+//!     .with("expiretm", Relative(Duration::from_secs(60)));
+//! ```
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{Error, Result};
+
+/// Number of nanoseconds in a second.
+const NANOS_PER_SEC: u32 = 1_000_000_000;
+
+/// Unix timestamp with nanoseconds precision.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp {
+    /// Seconds since the Unix epoch (negative before the epoch).
+    secs: i64,
+    /// Nanoseconds after `secs`, always less than a second.
+    nanos: u32,
+}
+
+impl Timestamp {
+    /// Constructs a new timestamp, where the nanoseconds can exceed a
+    /// second.
+    pub fn new(secs: i64, nanos: u32) -> Self {
+        Self {
+            secs: secs + i64::from(nanos / NANOS_PER_SEC),
+            nanos: nanos % NANOS_PER_SEC,
+        }
+    }
+
+    /// Constructs a new timestamp of whole seconds.
+    pub fn from_secs(secs: i64) -> Self {
+        Self::new(secs, 0)
+    }
+
+    /// Constructs a new timestamp from nanoseconds since the Unix epoch.
+    pub fn from_nanos(nanos: i128) -> Self {
+        let secs = nanos.div_euclid(i128::from(NANOS_PER_SEC)) as i64;
+        let nanos = nanos.rem_euclid(i128::from(NANOS_PER_SEC)) as u32;
+        Self { secs, nanos }
+    }
+
+    /// Gets the current time.
+    pub fn now() -> Self {
+        SystemTime::now().into()
+    }
+
+    /// Gets the whole seconds since the Unix epoch.
+    pub fn secs(&self) -> i64 {
+        self.secs
+    }
+
+    /// Gets the nanoseconds after the whole seconds.
+    pub fn subsec_nanos(&self) -> u32 {
+        self.nanos
+    }
+
+    /// Gets the nanoseconds since the Unix epoch.
+    pub fn as_nanos(&self) -> i128 {
+        i128::from(self.secs) * i128::from(NANOS_PER_SEC)
+            + i128::from(self.nanos)
+    }
+
+    /// Gets the seconds since the Unix epoch as float (losing precision).
+    pub fn as_f64(&self) -> f64 {
+        self.secs as f64 + f64::from(self.nanos) / f64::from(NANOS_PER_SEC)
+    }
+}
+
+impl fmt::Display for Timestamp {
+    /// Formats the timestamp as Unix seconds, with the fractional part only
+    /// if not zero.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.nanos == 0 {
+            return write!(f, "{}", self.secs);
+        }
+
+        let (sign, secs, nanos) = if self.secs < 0 {
+            ("-", -(self.secs + 1), NANOS_PER_SEC - self.nanos)
+        } else {
+            ("", self.secs, self.nanos)
+        };
+        let frac = format!("{:09}", nanos);
+        write!(f, "{}{}.{}", sign, secs, frac.trim_end_matches('0'))
+    }
+}
+
+impl FromStr for Timestamp {
+    type Err = Error;
+
+    /// Parses Unix seconds with an optional fractional part, where digits
+    /// beyond the nanoseconds are truncated.
+    fn from_str(timestamp: &str) -> Result<Self> {
+        let invalid = || Error::invalid_message(timestamp);
+        let (negative, unsigned) = match timestamp.strip_prefix('-') {
+            Some(unsigned) => (true, unsigned),
+            None => (false, timestamp),
+        };
+        let (int, frac) = match unsigned.split_once('.') {
+            Some((int, frac)) => (int, frac),
+            None => (unsigned, ""),
+        };
+
+        let digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+        if int.is_empty() || !digits(int) || !digits(frac) {
+            return Err(invalid());
+        }
+
+        let secs: i64 = int.parse().map_err(|_| invalid())?;
+        let nanos: u32 =
+            format!("{:0<9.9}", frac).parse().map_err(|_| invalid())?;
+        let nanos =
+            i128::from(secs) * i128::from(NANOS_PER_SEC) + i128::from(nanos);
+
+        Ok(Self::from_nanos(if negative { -nanos } else { nanos }))
+    }
+}
+
+impl From<SystemTime> for Timestamp {
+    fn from(time: SystemTime) -> Self {
+        match time.duration_since(UNIX_EPOCH) {
+            Ok(d) => Self::new(d.as_secs() as i64, d.subsec_nanos()),
+            Err(e) => Self::from_nanos(-(e.duration().as_nanos() as i128)),
+        }
+    }
+}
+
+impl From<Timestamp> for SystemTime {
+    fn from(timestamp: Timestamp) -> Self {
+        let nanos = timestamp.as_nanos();
+        let duration = Duration::from_nanos(nanos.unsigned_abs() as u64);
+        if nanos < 0 {
+            UNIX_EPOCH - duration
+        } else {
+            UNIX_EPOCH + duration
+        }
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl From<chrono::DateTime<chrono::Utc>> for Timestamp {
+    fn from(time: chrono::DateTime<chrono::Utc>) -> Self {
+        Self::new(time.timestamp(), time.timestamp_subsec_nanos())
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl From<Timestamp> for chrono::DateTime<chrono::Utc> {
+    fn from(timestamp: Timestamp) -> Self {
+        use chrono::TimeZone;
+        chrono::Utc
+            .timestamp_opt(timestamp.secs, timestamp.nanos)
+            .single()
+            .unwrap_or_else(|| SystemTime::from(timestamp).into())
+    }
+}
+
+#[cfg(feature = "time")]
+impl From<time::OffsetDateTime> for Timestamp {
+    fn from(time: time::OffsetDateTime) -> Self {
+        Self::from_nanos(time.unix_timestamp_nanos())
+    }
+}
+
+#[cfg(feature = "time")]
+impl std::convert::TryFrom<Timestamp> for time::OffsetDateTime {
+    type Error = Error;
+
+    fn try_from(timestamp: Timestamp) -> Result<Self> {
+        time::OffsetDateTime::from_unix_timestamp_nanos(timestamp.as_nanos())
+            .map_err(Error::invalid_message)
+    }
+}
+
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    /// Deserializes Unix seconds either as string or number.
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        struct TimestampVisitor;
+
+        impl<'de> de::Visitor<'de> for TimestampVisitor {
+            type Value = Timestamp;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "Unix seconds as string or number")
+            }
+
+            fn visit_str<E: de::Error>(
+                self,
+                v: &str,
+            ) -> std::result::Result<Timestamp, E> {
+                v.parse().map_err(|_| {
+                    E::invalid_value(de::Unexpected::Str(v), &self)
+                })
+            }
+
+            fn visit_f64<E: de::Error>(
+                self,
+                v: f64,
+            ) -> std::result::Result<Timestamp, E> {
+                // the shortest representation of the number is parsed exactly
+                self.visit_str(&v.to_string())
+            }
+
+            fn visit_i64<E: de::Error>(
+                self,
+                v: i64,
+            ) -> std::result::Result<Timestamp, E> {
+                Ok(Timestamp::from_secs(v))
+            }
+
+            fn visit_u64<E: de::Error>(
+                self,
+                v: u64,
+            ) -> std::result::Result<Timestamp, E> {
+                Ok(Timestamp::from_secs(v as i64))
+            }
+        }
+
+        deserializer.deserialize_any(TimestampVisitor)
+    }
+}
+
+/// Time relative to the current time, formatted as `+<n>` seconds (e.g. for
+/// the `starttm` and `expiretm` parameters).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Relative(pub Duration);
+
+impl fmt::Display for Relative {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "+{}", self.0.as_secs())
+    }
+}
+
+/// Cursor of the public APIs `since` parameter.
+///
+/// The `last` field of the responses is kept as returned by the API (Unix
+/// seconds for `ohlc` and `spread`, nanoseconds for `trades`), and can be
+/// passed back as `since` parameter to get the following entries.
+#[derive(
+    Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Since(#[serde(deserialize_with = "cursor")] String);
+
+impl Since {
+    /// Gets the cursor as returned by the API.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Interprets the cursor as timestamp, where cursors longer than 12
+    /// digits are nanoseconds.
+    pub fn to_timestamp(&self) -> Result<Timestamp> {
+        let is_nanos = self.0.len() > 12 && !self.0.contains('.');
+        if is_nanos {
+            let nanos: i128 = self
+                .0
+                .parse()
+                .map_err(|_| Error::invalid_message(&self.0))?;
+            Ok(Timestamp::from_nanos(nanos))
+        } else {
+            self.0.parse()
+        }
+    }
+}
+
+impl fmt::Display for Since {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for Since {
+    type Err = Error;
+
+    fn from_str(cursor: &str) -> Result<Self> {
+        let valid = !cursor.is_empty()
+            && cursor.bytes().all(|b| b.is_ascii_digit() || b == b'.');
+        if valid {
+            Ok(Self(cursor.to_string()))
+        } else {
+            Err(Error::invalid_message(cursor))
+        }
+    }
+}
+
+impl From<Timestamp> for Since {
+    fn from(timestamp: Timestamp) -> Self {
+        Self(timestamp.to_string())
+    }
+}
+
+/// Deserializes a cursor either as string or number.
+fn cursor<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<String, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Cursor {
+        Text(String),
+        Number(u64),
+    }
+
+    Ok(match Cursor::deserialize(deserializer)? {
+        Cursor::Text(text) => text,
+        Cursor::Number(number) => number.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use serde_json::json;
+
+    #[test]
+    fn timestamp_parse() -> Result<()> {
+        let t: Timestamp = "1625918400.1234".parse()?;
+        assert_eq!(t.secs(), 1625918400);
+        assert_eq!(t.subsec_nanos(), 123_400_000);
+        assert_eq!(t.to_string(), "1625918400.1234");
+
+        assert_eq!(
+            "1625918400".parse::<Timestamp>()?.to_string(),
+            "1625918400"
+        );
+        assert_eq!(
+            "1.1234567891".parse::<Timestamp>()?,
+            Timestamp::new(1, 123_456_789)
+        );
+
+        let negative: Timestamp = "-1.5".parse()?;
+        assert_eq!(negative, Timestamp::new(-2, 500_000_000));
+        assert_eq!(negative.to_string(), "-1.5");
+        assert_eq!("-0.25".parse::<Timestamp>()?.to_string(), "-0.25");
+
+        assert!("".parse::<Timestamp>().is_err());
+        assert!(".5".parse::<Timestamp>().is_err());
+        assert!("1e9".parse::<Timestamp>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn timestamp_serde() -> Result<()> {
+        let t: Timestamp = serde_json::from_value(json!(1625918400.1234))?;
+        assert_eq!(t, "1625918400.1234".parse()?);
+        let t: Timestamp = serde_json::from_value(json!("1625918400.1234"))?;
+        assert_eq!(serde_json::to_value(t)?, json!("1625918400.1234"));
+        let t: Timestamp = serde_json::from_value(json!(0))?;
+        assert_eq!(t, Timestamp::default());
+
+        let system = SystemTime::from(Timestamp::new(10, 5));
+        assert_eq!(Timestamp::from(system), Timestamp::new(10, 5));
+        assert!(Timestamp::now() > Timestamp::from_secs(1625918400));
+
+        Ok(())
+    }
+
+    #[test]
+    fn since_cursor() -> Result<()> {
+        let since: Since =
+            serde_json::from_value(json!("1625918400123456789"))?;
+        assert_eq!(since.to_string(), "1625918400123456789");
+        assert_eq!(
+            since.to_timestamp()?,
+            Timestamp::new(1625918400, 123_456_789)
+        );
+
+        let since: Since = serde_json::from_value(json!(1625918400))?;
+        assert_eq!(since.to_timestamp()?, Timestamp::from_secs(1625918400));
+        assert_eq!(Since::from(Timestamp::from_secs(5)).as_str(), "5");
+        assert!("abc".parse::<Since>().is_err());
+
+        assert_eq!(Relative(Duration::from_secs(60)).to_string(), "+60");
+
+        Ok(())
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn chrono_conversions() -> Result<()> {
+        let t = Timestamp::new(1625918400, 123_400_000);
+        let time: chrono::DateTime<chrono::Utc> = t.into();
+        assert_eq!(time.to_rfc3339(), "2021-07-10T12:00:00.123400+00:00");
+        assert_eq!(Timestamp::from(time), t);
+        Ok(())
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn time_conversions() -> Result<()> {
+        use std::convert::TryFrom;
+
+        let t = Timestamp::new(1625918400, 123_400_000);
+        let time = time::OffsetDateTime::try_from(t)?;
+        assert_eq!(time.unix_timestamp(), 1625918400);
+        assert_eq!(Timestamp::from(time), t);
+        Ok(())
+    }
+}
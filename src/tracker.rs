@@ -0,0 +1,664 @@
+//! Order lifecycle tracking.
+//!
+//! The `OrderTracker` records the orders submitted with the `add_order` API
+//! and follows them until they are closed, canceled or expired, reconciling
+//! the state returned by the `query_orders` API (polling) with the updates of
+//! the Websockets `openOrders` channel. Each status change is returned as a
+//! typed `Transition`.
+//!
+//! ```no_run
+//! use akkorokamui::{
+//!     api, blocking::Client, tracker::OrderTracker, Credentials,
+//!     ResponseValue,
+//! };
+//! use anyhow::Result;
+//! use std::{thread, time::Duration};
+//!
+//! fn main() -> Result<()> {
+//!     let credentials = Credentials::read("kraken.key")?;
+//!
+//!     let user_agent = "<product>/<product-version>";
+//!     let client = Client::with_credentials(user_agent, credentials)?;
+//!
+//!     let api = api::private::add_order()
+//!         .with("pair", "XXBTZEUR")
+//!         .with("type", "buy")
+//!         .with("ordertype", "limit")
+//!         .with("price", "30000")
+//!         .with("volume", "0.01")
+//!         .with("userref", 42);
+//!     let resp: ResponseValue = client.send(api)?;
+//!
+//!     let mut tracker = OrderTracker::new();
+//!     tracker.record_response(resp, Some(42))?;
+//!
+//!     while tracker.pending().next().is_some() {
+//!         for transition in tracker.poll(&client)? {
+//!             println!("{:?}", transition);
+//!         }
+//!         thread::sleep(Duration::from_secs(5));
+//!     }
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{collections::BTreeMap, fmt};
+
+use crate::{
+    amount::serde_opt_amount,
+    api::{self, ApiBuilder},
+    blocking,
+    history::OrderInfo,
+    Amount, Client, Error, ResponseValue, Result,
+};
+
+/// Maximum number of transaction IDs of a `query_orders` request.
+const QUERY_TXIDS: usize = 50;
+
+/// Status of a tracked order.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStatus {
+    /// Submitted, but not yet in the book.
+    Pending,
+    /// In the book, without any fill.
+    Open,
+    /// In the book, partially filled.
+    PartiallyFilled,
+    /// Fully filled.
+    Closed,
+    /// Canceled (possibly after some fills).
+    Canceled,
+    /// Expired (possibly after some fills).
+    Expired,
+}
+
+impl OrderStatus {
+    /// Returns true only if the order cannot change anymore.
+    pub fn is_final(self) -> bool {
+        matches!(self, Self::Closed | Self::Canceled | Self::Expired)
+    }
+
+    /// Gets the status from the API status and executed volume.
+    fn from_api(status: &str, vol_exec: Amount) -> Result<Self> {
+        let status = match status {
+            "pending" => Self::Pending,
+            "open" if vol_exec > Amount::default() => Self::PartiallyFilled,
+            "open" => Self::Open,
+            "closed" => Self::Closed,
+            "canceled" => Self::Canceled,
+            "expired" => Self::Expired,
+            _ => return Err(Error::invalid_message(status)),
+        };
+        Ok(status)
+    }
+}
+
+impl fmt::Display for OrderStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            Self::Pending => "pending",
+            Self::Open => "open",
+            Self::PartiallyFilled => "partially filled",
+            Self::Closed => "closed",
+            Self::Canceled => "canceled",
+            Self::Expired => "expired",
+        };
+        write!(f, "{}", status)
+    }
+}
+
+/// State of a tracked order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackedOrder {
+    /// Transaction ID.
+    pub txid: String,
+    /// User reference ID.
+    pub userref: Option<i64>,
+    /// Current status.
+    pub status: OrderStatus,
+    /// Volume of order (zero until known).
+    pub vol: Amount,
+    /// Volume executed.
+    pub vol_exec: Amount,
+    /// Total cost of the fills.
+    pub cost: Amount,
+    /// Total fee of the fills.
+    pub fee: Amount,
+}
+
+impl TrackedOrder {
+    /// Constructs a new pending order.
+    fn new(txid: String, userref: Option<i64>) -> Self {
+        Self {
+            txid,
+            userref,
+            status: OrderStatus::Pending,
+            vol: Amount::default(),
+            vol_exec: Amount::default(),
+            cost: Amount::default(),
+            fee: Amount::default(),
+        }
+    }
+
+    /// Gets the average price of the fills, if any.
+    pub fn avg_price(&self) -> Option<Amount> {
+        if self.vol_exec > Amount::default() {
+            Some(self.cost / self.vol_exec)
+        } else {
+            None
+        }
+    }
+
+    /// Gets the volume not executed yet.
+    pub fn remaining(&self) -> Amount {
+        if self.vol > self.vol_exec {
+            self.vol - self.vol_exec
+        } else {
+            Amount::default()
+        }
+    }
+}
+
+/// Status change of a tracked order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transition {
+    /// Transaction ID.
+    pub txid: String,
+    /// User reference ID.
+    pub userref: Option<i64>,
+    /// Previous status.
+    pub from: OrderStatus,
+    /// New status.
+    pub to: OrderStatus,
+    /// Volume executed after the transition.
+    pub vol_exec: Amount,
+}
+
+/// Partial order state, as returned by the APIs.
+#[derive(Debug, Default, Deserialize)]
+struct OrderUpdate {
+    #[serde(default)]
+    status: Option<String>,
+    #[serde(default)]
+    userref: Option<i64>,
+    #[serde(default, with = "serde_opt_amount")]
+    vol: Option<Amount>,
+    #[serde(default, with = "serde_opt_amount")]
+    vol_exec: Option<Amount>,
+    #[serde(default, with = "serde_opt_amount")]
+    cost: Option<Amount>,
+    #[serde(default, with = "serde_opt_amount")]
+    fee: Option<Amount>,
+}
+
+impl OrderUpdate {
+    /// Parses the fields of the given order object, all of which are
+    /// optional (the Websockets updates only contain the changed fields).
+    fn from_object(order: &Map<String, Value>) -> Result<Self> {
+        serde_json::from_value(Value::Object(order.clone()))
+            .map_err(Error::invalid_message)
+    }
+}
+
+impl From<&OrderInfo> for OrderUpdate {
+    fn from(info: &OrderInfo) -> Self {
+        Self {
+            status: Some(info.status.clone()),
+            userref: info.userref,
+            vol: Some(info.vol),
+            vol_exec: Some(info.vol_exec),
+            cost: Some(info.cost),
+            fee: Some(info.fee),
+        }
+    }
+}
+
+/// Tracker of the orders lifecycle.
+#[derive(Debug, Clone, Default)]
+pub struct OrderTracker {
+    /// The tracked orders by transaction ID.
+    orders: BTreeMap<String, TrackedOrder>,
+}
+
+impl OrderTracker {
+    /// Constructs a new tracker without orders.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts tracking the given order as pending, unless already tracked.
+    pub fn record(&mut self, txid: impl Into<String>, userref: Option<i64>) {
+        let txid = txid.into();
+        self.orders
+            .entry(txid.clone())
+            .or_insert_with(|| TrackedOrder::new(txid, userref));
+    }
+
+    /// Starts tracking all the orders of the given `add_order` response, and
+    /// returns their transaction IDs.
+    pub fn record_response(
+        &mut self,
+        resp: ResponseValue,
+        userref: Option<i64>,
+    ) -> Result<Vec<String>> {
+        if !resp.error.is_empty() {
+            return Err(Error::Api(resp.error));
+        }
+
+        let result = resp.result.unwrap_or_default();
+        let txids: Vec<String> = match result.get("txid") {
+            Some(Value::Array(txids)) => txids
+                .iter()
+                .filter_map(|t| t.as_str().map(String::from))
+                .collect(),
+            _ => return Err(Error::invalid_message(result)),
+        };
+
+        for txid in &txids {
+            self.record(txid.as_str(), userref);
+        }
+        Ok(txids)
+    }
+
+    /// Gets the tracked order with the given transaction ID.
+    pub fn order(&self, txid: &str) -> Option<&TrackedOrder> {
+        self.orders.get(txid)
+    }
+
+    /// Gets all the tracked orders with the given user reference ID.
+    pub fn by_userref(
+        &self,
+        userref: i64,
+    ) -> impl Iterator<Item = &TrackedOrder> {
+        self.orders
+            .values()
+            .filter(move |o| o.userref == Some(userref))
+    }
+
+    /// Gets all the tracked orders.
+    pub fn orders(&self) -> impl Iterator<Item = &TrackedOrder> {
+        self.orders.values()
+    }
+
+    /// Gets the tracked orders that can still change.
+    pub fn pending(&self) -> impl Iterator<Item = &TrackedOrder> {
+        self.orders.values().filter(|o| !o.status.is_final())
+    }
+
+    /// Stops tracking the orders that cannot change anymore, and returns
+    /// them.
+    pub fn remove_final(&mut self) -> Vec<TrackedOrder> {
+        let txids: Vec<String> = self
+            .orders
+            .values()
+            .filter(|o| o.status.is_final())
+            .map(|o| o.txid.clone())
+            .collect();
+        txids
+            .iter()
+            .filter_map(|txid| self.orders.remove(txid))
+            .collect()
+    }
+
+    /// Gets the `query_orders` APIs of the orders that can still change, at
+    /// most 50 orders per request (none if there are no such orders).
+    pub fn queries(&self) -> Vec<ApiBuilder> {
+        let txids: Vec<&str> =
+            self.pending().map(|o| o.txid.as_str()).collect();
+        txids
+            .chunks(QUERY_TXIDS)
+            .map(|txids| {
+                api::private::query_orders().with("txid", txids.join(","))
+            })
+            .collect()
+    }
+
+    /// Queries the orders that can still change and applies their state.
+    pub fn poll(
+        &mut self,
+        client: &blocking::Client,
+    ) -> Result<Vec<Transition>> {
+        let mut transitions = Vec::new();
+        for api in self.queries() {
+            transitions.extend(self.apply_query(client.send(api)?)?);
+        }
+        Ok(transitions)
+    }
+
+    /// Queries the orders that can still change and applies their state.
+    pub async fn poll_async(
+        &mut self,
+        client: &Client,
+    ) -> Result<Vec<Transition>> {
+        let mut transitions = Vec::new();
+        for api in self.queries() {
+            transitions.extend(self.apply_query(client.send(api).await?)?);
+        }
+        Ok(transitions)
+    }
+
+    /// Applies the given `query_orders` (or `open_orders`/`closed_orders`)
+    /// response, and returns the status transitions of the tracked orders.
+    ///
+    /// Orders that are not tracked are ignored.
+    pub fn apply_query(
+        &mut self,
+        resp: ResponseValue,
+    ) -> Result<Vec<Transition>> {
+        if !resp.error.is_empty() {
+            return Err(Error::Api(resp.error));
+        }
+
+        let mut result = resp.result.unwrap_or_default();
+        // open_orders and closed_orders nest the orders in a field
+        for field in &["open", "closed"] {
+            if let Some(orders) = result.get_mut(*field) {
+                result = orders.take();
+                break;
+            }
+        }
+
+        let orders: BTreeMap<String, OrderInfo> =
+            serde_json::from_value(result).map_err(Error::invalid_message)?;
+
+        let mut transitions = Vec::new();
+        for (txid, info) in &orders {
+            transitions.extend(self.update(txid, info.into())?);
+        }
+        Ok(transitions)
+    }
+
+    /// Applies the given Websockets `openOrders` channel message, and returns
+    /// the status transitions of the tracked orders.
+    ///
+    /// Other messages, and orders that are not tracked, are ignored.
+    pub fn apply_ws(&mut self, message: &Value) -> Result<Vec<Transition>> {
+        let orders = match message.as_array().map(Vec::as_slice) {
+            Some([Value::Array(orders), Value::String(channel), ..])
+                if channel == "openOrders" =>
+            {
+                orders
+            }
+            _ => return Ok(Vec::new()),
+        };
+
+        let mut transitions = Vec::new();
+        for entry in orders {
+            let entry = entry
+                .as_object()
+                .ok_or_else(|| Error::invalid_message(entry))?;
+            for (txid, order) in entry {
+                let order = order
+                    .as_object()
+                    .ok_or_else(|| Error::invalid_message(order))?;
+                let update = OrderUpdate::from_object(order)?;
+                transitions.extend(self.update(txid, update)?);
+            }
+        }
+        Ok(transitions)
+    }
+
+    /// Updates the given order, returning its status transition (if any).
+    fn update(
+        &mut self,
+        txid: &str,
+        update: OrderUpdate,
+    ) -> Result<Option<Transition>> {
+        let order = match self.orders.get_mut(txid) {
+            Some(order) => order,
+            None => return Ok(None),
+        };
+
+        // the executed volume never decreases, whatever the updates order
+        if let Some(vol_exec) = update.vol_exec {
+            if vol_exec >= order.vol_exec {
+                order.vol_exec = vol_exec;
+                order.cost = update.cost.unwrap_or(order.cost);
+                order.fee = update.fee.unwrap_or(order.fee);
+            }
+        }
+        order.vol = update.vol.unwrap_or(order.vol);
+        order.userref = update.userref.or(order.userref);
+
+        let from = order.status;
+        let filled = order.vol_exec > Amount::default();
+        let to = match update.status {
+            Some(status) => OrderStatus::from_api(&status, order.vol_exec)?,
+            // the fills updates do not include the status
+            None if filled
+                && order.vol > Amount::default()
+                && order.vol_exec >= order.vol =>
+            {
+                OrderStatus::Closed
+            }
+            None if filled && from < OrderStatus::PartiallyFilled => {
+                OrderStatus::PartiallyFilled
+            }
+            None => from,
+        };
+
+        // final states are never left (e.g. stale polling responses)
+        if from == to || from.is_final() {
+            return Ok(None);
+        }
+
+        order.status = to;
+        Ok(Some(Transition {
+            txid: txid.to_string(),
+            userref: order.userref,
+            from,
+            to,
+            vol_exec: order.vol_exec,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::amount;
+    use anyhow::Result;
+    use serde_json::json;
+
+    fn order_info(status: &str, vol_exec: &str, cost: &str) -> Value {
+        json!({
+            "refid": null,
+            "userref": 42,
+            "status": status,
+            "opentm": 1625918400.1234,
+            "starttm": 0,
+            "expiretm": 0,
+            "descr": {
+                "pair": "XBTEUR",
+                "type": "buy",
+                "ordertype": "limit",
+                "price": "30000.0",
+                "price2": "0",
+                "leverage": "none",
+                "order": "buy 1.00000000 XBTEUR @ limit 30000.0",
+                "close": ""
+            },
+            "vol": "1.00000000",
+            "vol_exec": vol_exec,
+            "cost": cost,
+            "fee": "0.00000",
+            "price": "0.00000",
+            "misc": "",
+            "oflags": "fciq"
+        })
+    }
+
+    fn response(result: Value) -> ResponseValue {
+        ResponseValue {
+            error: Vec::new(),
+            result: Some(result),
+            status_code: 200,
+        }
+    }
+
+    #[test]
+    fn record_orders() -> Result<()> {
+        let mut tracker = OrderTracker::new();
+        let resp = response(json!({
+            "descr": {"order": "buy 1.00000000 XBTEUR @ limit 30000.0"},
+            "txid": ["OUF4EM-FRGI2-MQMWZD"]
+        }));
+        let txids = tracker.record_response(resp, Some(42))?;
+        assert_eq!(txids, vec!["OUF4EM-FRGI2-MQMWZD"]);
+
+        let order = tracker.order("OUF4EM-FRGI2-MQMWZD").expect("order");
+        assert_eq!(order.status, OrderStatus::Pending);
+        assert_eq!(tracker.by_userref(42).count(), 1);
+        assert_eq!(tracker.queries().len(), 1);
+
+        let resp = ResponseValue {
+            error: vec!["EOrder:Insufficient funds".into()],
+            result: None,
+            status_code: 200,
+        };
+        assert!(tracker.record_response(resp, None).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn poll_transitions() -> Result<()> {
+        let txid = "OUF4EM-FRGI2-MQMWZD";
+        let mut tracker = OrderTracker::new();
+        tracker.record(txid, Some(42));
+
+        let resp = response(json!({ (txid): order_info("open", "0", "0") }));
+        let transitions = tracker.apply_query(resp)?;
+        assert_eq!(transitions.len(), 1);
+        assert_eq!(transitions[0].from, OrderStatus::Pending);
+        assert_eq!(transitions[0].to, OrderStatus::Open);
+
+        let resp = response(json!({
+            "open": { (txid): order_info("open", "0.5", "15000") }
+        }));
+        let transitions = tracker.apply_query(resp)?;
+        assert_eq!(transitions[0].to, OrderStatus::PartiallyFilled);
+
+        let order = tracker.order(txid).expect("order");
+        assert_eq!(order.avg_price(), Some(amount("30000")));
+        assert_eq!(order.remaining(), amount("0.5"));
+
+        let resp = response(json!({
+            txid: order_info("canceled", "0.5", "15000")
+        }));
+        let transitions = tracker.apply_query(resp)?;
+        assert_eq!(transitions[0].to, OrderStatus::Canceled);
+        assert!(tracker.queries().is_empty());
+
+        // stale responses cannot reopen a final order
+        let resp = response(json!({ (txid): order_info("open", "0", "0") }));
+        assert!(tracker.apply_query(resp)?.is_empty());
+        assert_eq!(tracker.order(txid).expect("order").vol_exec, amount("0.5"));
+
+        assert_eq!(tracker.remove_final().len(), 1);
+        assert_eq!(tracker.orders().count(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn ws_transitions() -> Result<()> {
+        let txid = "OGTT3Y-C6I3P-XRI6HX";
+        let mut tracker = OrderTracker::new();
+        tracker.record(txid, None);
+
+        let message = json!([
+            [{ (txid): {
+                "status": "open",
+                "userref": 7,
+                "vol": "2.00000000",
+                "vol_exec": "0.00000000",
+                "cost": "0.00000",
+                "fee": "0.00000"
+            }}],
+            "openOrders",
+            {"sequence": 1}
+        ]);
+        let transitions = tracker.apply_ws(&message)?;
+        assert_eq!(transitions[0].to, OrderStatus::Open);
+        assert_eq!(transitions[0].userref, Some(7));
+
+        let fill = |vol_exec: &str, cost: &str, sequence: u64| {
+            json!([
+                [{ (txid): {
+                    "vol_exec": vol_exec,
+                    "cost": cost,
+                    "fee": "156.00000",
+                    "avg_price": "30000.00000"
+                }}],
+                "openOrders",
+                {"sequence": sequence}
+            ])
+        };
+        let transitions = tracker.apply_ws(&fill("1.0", "30000", 2))?;
+        assert_eq!(transitions[0].to, OrderStatus::PartiallyFilled);
+
+        // the status is derived from the filled volume
+        let transitions = tracker.apply_ws(&fill("2.0", "60000", 3))?;
+        assert_eq!(transitions[0].from, OrderStatus::PartiallyFilled);
+        assert_eq!(transitions[0].to, OrderStatus::Closed);
+
+        let message = json!([
+            [{ (txid): {"status": "closed"} }],
+            "openOrders",
+            {"sequence": 4}
+        ]);
+        assert!(tracker.apply_ws(&message)?.is_empty());
+
+        let order = tracker.order(txid).expect("order");
+        assert_eq!(order.fee, amount("156"));
+        assert_eq!(order.avg_price(), Some(amount("30000")));
+
+        let heartbeat = json!({"event": "heartbeat"});
+        assert!(tracker.apply_ws(&heartbeat)?.is_empty());
+
+        // a pending order can be filled before its open update
+        let pending = "OPENDG-FILLD-EARLY1";
+        tracker.record(pending, None);
+        let message = json!([
+            [{ (pending): {"vol_exec": "0.1", "cost": "3000"} }],
+            "openOrders",
+            {"sequence": 5}
+        ]);
+        let transitions = tracker.apply_ws(&message)?;
+        assert_eq!(transitions[0].from, OrderStatus::Pending);
+        assert_eq!(transitions[0].to, OrderStatus::PartiallyFilled);
+
+        Ok(())
+    }
+
+    #[test]
+    fn chunk_queries() {
+        let mut tracker = OrderTracker::new();
+        for i in 0..120 {
+            tracker.record(format!("O{:03}", i), None);
+        }
+
+        let queries = tracker.queries();
+        assert_eq!(queries.len(), 3);
+        let txids = |api: &ApiBuilder| api.params["txid"].split(',').count();
+        assert_eq!(txids(&queries[0]), 50);
+        assert_eq!(txids(&queries[2]), 20);
+        assert!(queries[0].params["txid"].starts_with("O000,O001"));
+    }
+}
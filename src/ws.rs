@@ -0,0 +1,139 @@
+//! Websockets connections shared by the Kraken feeds clients.
+//!
+//! The reconnection policy and the keep-alive socket are only used by the
+//! Futures feeds client (`futures::ws`) for now: this crate has no spot
+//! Websockets client, the spot book models (`book`) only parse the messages
+//! received by the user's own connection.
+//!
+//! This module is only available with the `ws` feature.
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::Value;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    tungstenite::Message, MaybeTlsStream, WebSocketStream,
+};
+
+use crate::{Error, Result};
+
+/// Reconnection policy of a Websockets feed.
+///
+/// After a connection failure the delay before the next attempt is doubled,
+/// starting from the initial delay up to the maximum delay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reconnect {
+    /// Delay before the first reconnection attempt.
+    pub initial_delay: Duration,
+    /// Maximum delay between two reconnection attempts.
+    pub max_delay: Duration,
+    /// Maximum number of consecutive failed attempts (unlimited if `None`).
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            max_attempts: None,
+        }
+    }
+}
+
+impl Reconnect {
+    /// Gets the delay before the given (zero based) reconnection attempt, or
+    /// `None` if no more attempts are allowed.
+    pub fn delay(&self, attempt: u32) -> Option<Duration> {
+        if matches!(self.max_attempts, Some(max) if attempt >= max) {
+            return None;
+        }
+        let factor = 2u32.saturating_pow(attempt);
+        let delay = self.initial_delay.checked_mul(factor);
+        Some(delay.map_or(self.max_delay, |d| d.min(self.max_delay)))
+    }
+}
+
+/// A Websockets connection exchanging JSON messages.
+pub(crate) struct Socket {
+    inner: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+impl Socket {
+    /// Connects to the given Websockets URL.
+    pub(crate) async fn connect(url: &str) -> Result<Self> {
+        log::debug!("Connecting to {}", url);
+        let (inner, _) = tokio_tungstenite::connect_async(url).await?;
+        Ok(Self { inner })
+    }
+
+    /// Sends the given JSON message.
+    pub(crate) async fn send(&mut self, message: &Value) -> Result<()> {
+        log::trace!("Sending message {}", message);
+        self.inner.send(Message::Text(message.to_string())).await?;
+        Ok(())
+    }
+
+    /// Receives the next JSON message like `recv`, sending a ping each time
+    /// no message is received within the given interval to keep the
+    /// connection alive.
+    pub(crate) async fn recv_keepalive(
+        &mut self,
+        interval: Duration,
+    ) -> Option<Result<Value>> {
+        loop {
+            match tokio::time::timeout(interval, self.recv()).await {
+                Ok(message) => return message,
+                Err(_) => {
+                    log::trace!("Sending keep-alive ping");
+                    if let Err(e) =
+                        self.inner.send(Message::Ping(Vec::new())).await
+                    {
+                        return Some(Err(e.into()));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Receives the next JSON message, or `None` if the connection has been
+    /// closed.
+    pub(crate) async fn recv(&mut self) -> Option<Result<Value>> {
+        while let Some(message) = self.inner.next().await {
+            match message {
+                Ok(Message::Text(text)) => {
+                    return Some(
+                        serde_json::from_str(&text)
+                            .map_err(Error::invalid_message),
+                    )
+                }
+                Ok(Message::Close(_)) => return None,
+                Ok(_) => continue,
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reconnect_delay() {
+        let reconnect = Reconnect {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(5),
+            max_attempts: Some(4),
+        };
+        assert_eq!(reconnect.delay(0), Some(Duration::from_secs(1)));
+        assert_eq!(reconnect.delay(1), Some(Duration::from_secs(2)));
+        assert_eq!(reconnect.delay(2), Some(Duration::from_secs(4)));
+        assert_eq!(reconnect.delay(3), Some(Duration::from_secs(5)));
+        assert_eq!(reconnect.delay(4), None);
+
+        let unlimited = Reconnect::default();
+        assert_eq!(unlimited.delay(100), Some(unlimited.max_delay));
+    }
+}